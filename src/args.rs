@@ -1,18 +1,123 @@
-use clap::Parser;
+use clap::{ColorChoice, Parser, Subcommand};
+use std::path::PathBuf;
 
-/// Command line arguments
+/// Compile soundfonts from text and audio files
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    after_help = "Exit status is 0 on success, 1 if the command failed, e.g. on compile errors or \
+                  a failed check, and 2 on invalid arguments."
+)]
 pub struct Args {
-    /// Project manifest path
-    #[arg(short, long, default_value_t = String::from("./SoundFont.toml"))]
-    pub path: String,
+    #[command(subcommand)]
+    pub command: Command,
 
-    /// Print in detail
-    #[arg(short, long, default_value_t = false)]
-    pub verbose: bool,
+    /// Don't print progress or warnings
+    #[arg(short, long, global = true, default_value_t = false)]
+    pub quiet: bool,
 
-    /// Validate
-    #[arg(short, long, default_value_t = false)]
-    pub check: bool,
+    /// Color errors and warnings
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compile a project into a .sf2 file
+    Build {
+        /// Project manifest path
+        #[arg(default_value_t = String::from("./SoundFont.toml"))]
+        path: String,
+
+        /// Output file. Overrides the manifest's `output`
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Check the output against the SoundFont 2 spec
+        #[arg(short, long, default_value_t = false)]
+        check: bool,
+    },
+
+    /// Check a .sf2 file against the SoundFont 2 spec
+    Check {
+        /// SoundFont 2 file to check
+        input: String,
+    },
+
+    /// Print the INFO list, and tables of presets, instruments and samples
+    Info {
+        /// SoundFont 2 file to read
+        input: String,
+    },
+
+    /// Start a new project
+    Init {
+        /// New or empty directory for the project
+        #[arg(default_value_t = String::from("."))]
+        dir: String,
+    },
+
+    /// Turn a .sf2 file into a project directory
+    Decompile {
+        /// SoundFont 2 file to read
+        input: String,
+
+        /// New or empty directory for the project. Defaults to the input's name
+        dir: Option<String>,
+    },
+
+    /// Render a preset to a WAVE file
+    Render(RenderArgs),
+
+    /// Render the project's audio tests and compare them against their reference WAVE files
+    Test {
+        /// Project manifest path
+        #[arg(default_value_t = String::from("./SoundFont.toml"))]
+        path: String,
+
+        /// Save the renderings as the new references
+        #[arg(long, default_value_t = false)]
+        bless: bool,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RenderArgs {
+    /// Project manifest, or a .sf2 file
+    pub input: PathBuf,
+
+    /// WAVE file to write
+    pub output: PathBuf,
+
+    /// Preset as bank:program, e.g. 0:25, or by name. Defaults to the lowest bank and program,
+    /// or to the MIDI file's own programs
+    #[arg(short, long)]
+    pub preset: Option<String>,
+
+    /// Notes to play one after another, e.g. "C4 E4:80 G4:100:2 C4+E4+G4". Each is
+    /// NOTE[+NOTE...][:VELOCITY[:SECONDS]].
+    #[arg(short, long, default_value_t = String::from("C4"), conflicts_with = "midi")]
+    pub notes: String,
+
+    /// MIDI file to play instead of notes
+    #[arg(short, long)]
+    pub midi: Option<PathBuf>,
+
+    /// Sample rate, 16000..=192000
+    #[arg(long, default_value_t = 44100)]
+    pub sample_rate: u32,
+
+    /// Seconds to keep rendering after the last note
+    #[arg(long, default_value_t = 1.0, value_parser = parse_seconds)]
+    pub tail: f64,
+}
+
+/// Zero or more seconds
+fn parse_seconds(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(seconds),
+        _ => Err(format!("'{text}' isn't zero or more seconds")),
+    }
 }
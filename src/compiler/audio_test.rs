@@ -0,0 +1,334 @@
+//! Audio tests: presets rendered and compared against reference WAVE files, to catch changes
+//! that leave a preset silent, clipped or otherwise sounding different
+
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use super::{
+    note::MiddleC,
+    render::{render, Difference, Note, PresetSelector, RenderSettings, Rendering, Score},
+    CompilerError, ErrorKind,
+};
+use crate::soundfont::SoundFont2;
+
+/// A test, read from a TOML file in the tests directory. The reference is the WAVE file with
+/// the same name next to it.
+///
+/// ```toml
+/// preset = "Guitar" # Or "0:25"
+/// notes = ["E2", "C4", "C4+E4+G4"]
+/// velocities = [40, 127]
+/// duration = 0.5
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AudioTest {
+    /// Preset as "bank:program", or by name
+    pub preset: String,
+    /// Note names, or chords joined by '+'. They're played one after another, at each velocity
+    /// in turn.
+    pub notes: Vec<String>,
+    /// Defaults to 100
+    #[serde(default = "AudioTest::default_velocities")]
+    pub velocities: Vec<u8>,
+    /// Seconds each note is held. Defaults to 1
+    #[serde(default = "AudioTest::default_duration")]
+    pub duration: f64,
+    /// Seconds rendered after the last note. Defaults to 1
+    #[serde(default = "AudioTest::default_tail")]
+    pub tail: f64,
+    /// Largest passing RMS difference to the reference, full scale being 1. Defaults to 0.001
+    #[serde(default = "AudioTest::default_rms_tolerance")]
+    pub rms_tolerance: f32,
+    /// Largest passing difference of any point. Defaults to 0.01
+    #[serde(default = "AudioTest::default_peak_tolerance")]
+    pub peak_tolerance: f32,
+    /// File the test was read from
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// How a test went
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed(Difference),
+    /// The difference is beyond tolerance
+    Failed(Difference),
+    /// The rendering and the reference differ in length or sample rate
+    Mismatched,
+    /// There's no reference to compare against
+    NoReference,
+    /// The rendering was saved as the reference
+    Blessed,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, Outcome::Passed(_) | Outcome::Blessed)
+    }
+}
+
+impl AudioTest {
+    /// Read every `*.toml` file in a directory, in filename order. Fails with every error found.
+    /// A missing directory has no tests.
+    pub fn read_dir<P>(dir: P) -> Result<Vec<Self>, Vec<CompilerError>>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => {
+                return Err(vec![ErrorKind::FileCantOpen {
+                    path: dir.display().to_string(),
+                    reason: err.to_string(),
+                }
+                .into()])
+            }
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "toml")
+            })
+            .collect();
+        paths.sort();
+
+        let mut tests = vec![];
+        let mut errors = vec![];
+        for path in paths {
+            match Self::read(&path) {
+                Ok(test) => tests.push(test),
+                Err(err) => errors.push(err),
+            }
+        }
+        if errors.is_empty() {
+            Ok(tests)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn read<P>(path: P) -> Result<Self, CompilerError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|err| ErrorKind::FileCantOpen {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        })?;
+        let mut this: Self =
+            toml::from_str(&contents).map_err(|err| CompilerError::from(err).in_file(path))?;
+        this.path = path.to_path_buf();
+        Ok(this)
+    }
+
+    fn default_velocities() -> Vec<u8> {
+        vec![Note::DEFAULT_VELOCITY]
+    }
+
+    fn default_duration() -> f64 {
+        Note::DEFAULT_DURATION
+    }
+
+    fn default_tail() -> f64 {
+        RenderSettings::default().tail
+    }
+
+    fn default_rms_tolerance() -> f32 {
+        0.001
+    }
+
+    fn default_peak_tolerance() -> f32 {
+        0.01
+    }
+
+    /// The test's filename without extension
+    pub fn name(&self) -> String {
+        self.path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn reference_path(&self) -> PathBuf {
+        self.path.with_extension("wav")
+    }
+
+    /// The notes, each velocity in turn
+    fn score(&self, middle_c: MiddleC) -> Result<Score, CompilerError> {
+        if !(self.duration.is_finite() && self.duration > 0.0) {
+            return Err(CompilerError::from(ErrorKind::InvalidAudioTest {
+                reason: "Duration must be positive.".into(),
+            })
+            .at_key(&["duration"]));
+        }
+        if !(self.tail.is_finite() && self.tail >= 0.0) {
+            return Err(CompilerError::from(ErrorKind::InvalidAudioTest {
+                reason: "Tail must be zero or more.".into(),
+            })
+            .at_key(&["tail"]));
+        }
+
+        let mut notes = vec![];
+        let mut start = 0.0;
+        for (i, velocity) in self.velocities.iter().enumerate() {
+            if !(1..=127).contains(velocity) {
+                return Err(CompilerError::from(ErrorKind::InvalidAudioTest {
+                    reason: "Velocities must be within 1..=127.".into(),
+                })
+                .at_key(&["velocities", &i.to_string()]));
+            }
+            for (j, name) in self.notes.iter().enumerate() {
+                let keys = Note::parse_chord(name, middle_c).ok_or_else(|| {
+                    CompilerError::from(ErrorKind::InvalidNote { note: name.clone() })
+                        .at_key(&["notes", &j.to_string()])
+                })?;
+                notes.extend(keys.into_iter().map(|key| Note {
+                    key,
+                    velocity: *velocity,
+                    start,
+                    duration: self.duration,
+                }));
+                start += self.duration;
+            }
+        }
+        Ok(Score::Notes(notes))
+    }
+
+    pub fn render(&self, font: &SoundFont2, middle_c: MiddleC) -> Result<Rendering, CompilerError> {
+        let located = |err: CompilerError| err.in_file(&self.path);
+        let score = self.score(middle_c).map_err(located)?;
+        let settings = RenderSettings {
+            tail: self.tail,
+            ..Default::default()
+        };
+        let preset = PresetSelector::parse(&self.preset);
+        render(font, Some(&preset), &score, &settings).map_err(|err| match *err.kind {
+            ErrorKind::PresetNotFound { .. } => located(err.at_key(&["preset"])),
+            _ => located(err),
+        })
+    }
+
+    /// Render and compare against the reference. With `bless`, the rendering becomes the new
+    /// reference instead.
+    pub fn run(
+        &self,
+        font: &SoundFont2,
+        middle_c: MiddleC,
+        bless: bool,
+    ) -> Result<Outcome, CompilerError> {
+        let rendering = self.render(font, middle_c)?;
+        let reference_path = self.reference_path();
+        if bless {
+            rendering.save(&reference_path)?;
+            return Ok(Outcome::Blessed);
+        }
+        if !reference_path.exists() {
+            return Ok(Outcome::NoReference);
+        }
+
+        let reference = Rendering::read(&reference_path)?;
+        Ok(match rendering.difference(&reference) {
+            None => Outcome::Mismatched,
+            Some(difference)
+                if difference.rms <= self.rms_tolerance
+                    && difference.peak <= self.peak_tolerance =>
+            {
+                Outcome::Passed(difference)
+            }
+            Some(difference) => Outcome::Failed(difference),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::FontData;
+
+    #[test]
+    fn test_bless_and_compare() {
+        let root = std::env::temp_dir().join(format!("sfc-audio-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let manifest = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/example_project/SoundFont.toml"
+        );
+        let font = FontData::read(manifest).unwrap().generate_soundfont();
+        let middle_c = MiddleC::default();
+
+        let spec = root.join("guitar.toml");
+        std::fs::write(
+            &spec,
+            "preset = \"Guitar\"\nnotes = [\"C3\", \"C4+E4\"]\nvelocities = [60, 127]\n\
+             duration = 0.25\ntail = 0.25\n",
+        )
+        .unwrap();
+        let test = AudioTest::read(&spec).unwrap();
+        let run = |test: AudioTest| test.run(&font, middle_c, false);
+
+        let no_reference = run(test.clone()).unwrap();
+        let blessed = test.run(&font, middle_c, true).unwrap();
+        let passed = run(test.clone()).unwrap();
+        let mismatched = run(AudioTest {
+            duration: 0.5,
+            ..test.clone()
+        })
+        .unwrap();
+        let failed = run(AudioTest {
+            notes: vec!["C5".into(), "C4+E4".into()],
+            ..test.clone()
+        })
+        .unwrap();
+        let invalid = run(AudioTest {
+            velocities: vec![60, 0],
+            ..test.clone()
+        });
+        let negative_tail = run(AudioTest {
+            tail: -1.0,
+            ..test.clone()
+        });
+        let tests = AudioTest::read_dir(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(no_reference, Outcome::NoReference);
+        assert_eq!(blessed, Outcome::Blessed);
+        // The reference is 16-bit
+        assert!(matches!(passed, Outcome::Passed(d) if d.peak < 0.0001));
+        assert_eq!(mismatched, Outcome::Mismatched);
+        assert!(matches!(failed, Outcome::Failed(_)));
+        let invalid = invalid.unwrap_err();
+        assert_eq!(invalid.key, ["velocities", "1"]);
+        assert_eq!(invalid.path.as_deref(), Some(spec.as_path()));
+        assert_eq!(negative_tail.unwrap_err().key, ["tail"]);
+        assert!(AudioTest::read_dir(&root).unwrap().is_empty());
+        // Not a directory
+        assert!(AudioTest::read_dir(manifest).is_err());
+        assert_eq!(tests.len(), 1);
+        assert_eq!(tests[0].name(), "guitar");
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let root = std::env::temp_dir().join(format!("sfc-audio-keys-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let spec = root.join("misspelt.toml");
+        std::fs::write(
+            &spec,
+            "preset = \"Guitar\"\nnotes = [\"C4\"]\nvelocity = [60]\n",
+        )
+        .unwrap();
+        let err = AudioTest::read(&spec).unwrap_err();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(err.path.as_deref(), Some(spec.as_path()));
+        assert!(
+            matches!(&*err.kind, ErrorKind::Toml { message } if message.contains("velocity")),
+            "{err:?}"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Loop points found from the audio: a start and an end at upward zero crossings, where the
+//! waveform around the end best carries on into the start
+
+use serde::{Deserialize, Serialize};
+
+/// How a sample's loop points are chosen, apart from startloop and endloop given by hand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreLoop {
+    /// Search the audio after the attack
+    Auto,
+}
+
+/// Required points before startloop, as `SampleHeader::validate` checks
+const MIN_LEAD: usize = 8;
+/// Required points after endloop
+const MIN_TAIL: usize = 8;
+/// Shortest loop
+const MIN_LOOP: usize = 32;
+/// Points compared on each side of the seam
+const WINDOW: usize = 32;
+/// Crossings tried as loop starts, spread over the first quarter of the search
+const MAX_STARTS: usize = 32;
+/// Crossings tried as loop ends, spread over the second half
+const MAX_ENDS: usize = 256;
+
+/// Find a loop in mono audio, starting no earlier than `from`, which is usually the end of the
+/// attack. The loop spans at least a quarter of what's left, so it doesn't buzz.
+///
+/// Given the period of the audio in points, loop lengths within a point of a whole number of
+/// periods are preferred. None if there are no zero crossings to loop between.
+pub fn find_loop(points: &[f32], from: usize, period: Option<f64>) -> Option<(u32, u32)> {
+    let len = points.len();
+    if len < MIN_LEAD + MIN_LOOP + MIN_TAIL {
+        return None;
+    }
+    let first = from.clamp(MIN_LEAD, len - MIN_TAIL - MIN_LOOP);
+    let last = len - MIN_TAIL;
+    let crossings: Vec<usize> = (first..=last)
+        .filter(|i| points[i - 1] < 0.0 && points[*i] >= 0.0)
+        .collect();
+    let span = last - first;
+    let starts: Vec<usize> = crossings
+        .iter()
+        .copied()
+        .filter(|i| *i < first + span / 4)
+        .collect();
+    let ends: Vec<usize> = crossings
+        .iter()
+        .copied()
+        .filter(|i| *i >= first + span / 2)
+        .collect();
+    let starts = spread(&starts, MAX_STARTS);
+    let ends = spread(&ends, MAX_ENDS);
+
+    let whole_periods = |length: usize| {
+        period.is_none_or(|period| {
+            let periods = length as f64 / period;
+            periods >= 1.0 && (periods - periods.round()).abs() * period <= 1.0
+        })
+    };
+    let best = |snap: bool| {
+        starts
+            .iter()
+            .flat_map(|start| ends.iter().map(move |end| (*start, *end)))
+            .filter(|(start, end)| end - start >= MIN_LOOP)
+            .filter(|(start, end)| !snap || whole_periods(end - start))
+            .map(|(start, end)| (discontinuity(points, start, end), start, end))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    };
+    let (_, start, end) = best(period.is_some()).or_else(|| best(false))?;
+    Some((start as u32, end as u32))
+}
+
+/// Up to `max` values, evenly spaced
+fn spread(values: &[usize], max: usize) -> Vec<usize> {
+    if values.len() <= max {
+        return values.to_vec();
+    }
+    (0..max).map(|i| values[i * values.len() / max]).collect()
+}
+
+/// Mean squared difference between the audio around the loop start and around its end. Playback
+/// jumps from just before the end to the start, so the two should look alike.
+fn discontinuity(points: &[f32], start: usize, end: usize) -> f32 {
+    let before = WINDOW.min(start);
+    let after = WINDOW.min(points.len() - end);
+    let sum: f32 = (0..before + after)
+        .map(|i| (points[start + i - before] - points[end + i - before]).powi(2))
+        .sum();
+    sum / (before + after) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_loop() {
+        // A decaying tone with a loud click of an attack
+        let period = 44100.0 / 220.0;
+        let mut points: Vec<f32> = (0..20000)
+            .map(|i| {
+                let phase = std::f64::consts::TAU * i as f64 / period;
+                let decay = (-(i as f64) / 20000.0).exp();
+                ((phase.sin() + 0.3 * (2.0 * phase).sin()) * 0.5 * decay) as f32
+            })
+            .collect();
+        points[100] = 1.0;
+
+        let (start, end) = find_loop(&points, 100, Some(period)).unwrap();
+        let (start, end) = (start as usize, end as usize);
+        assert!(start >= 100 && end - start >= 5000 && end <= points.len() - MIN_TAIL);
+        for i in [start, end] {
+            assert!(points[i - 1] < 0.0 && points[i] >= 0.0);
+        }
+        let periods = (end - start) as f64 / period;
+        assert!((periods - periods.round()).abs() * period <= 1.0);
+        // Far smoother than the loop over the whole sample
+        let naive = discontinuity(&points, MIN_LEAD, points.len() - MIN_TAIL);
+        assert!(discontinuity(&points, start, end) < naive / 10.0);
+
+        assert_eq!(find_loop(&[0.0; 1000], 0, None), None);
+        assert_eq!(find_loop(&[0.5; 40], 0, None), None);
+    }
+}
@@ -0,0 +1,327 @@
+//! Instrument zones generated from sample filenames, e.g. `piano/*_{note}_{vel}.wav`
+
+use std::{collections::BTreeMap, path::Path};
+
+use super::{
+    note::{MiddleC, NoteName},
+    preprocess_formats::PreInstZone,
+    ranges::{PreRange, RangeBound},
+    CompilerError, ErrorKind,
+};
+
+/// A sample found by an auto map, and where it goes on the keyboard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappedSample {
+    /// Path relative to the samples directory, like a zone's `sample`
+    pub reference: String,
+    /// Key from the filename
+    pub root: u8,
+    pub keys: (u8, u8),
+    /// Only if the pattern has `{vel}`
+    pub velocities: Option<(u8, u8)>,
+}
+
+impl MappedSample {
+    /// A zone playing the sample over its ranges. The root key is left for the sample header.
+    pub fn zone(&self) -> PreInstZone {
+        let range = |(lo, hi): (u8, u8)| PreRange {
+            lo: RangeBound::Number(lo),
+            hi: RangeBound::Number(hi),
+        };
+        PreInstZone {
+            sample: Some(self.reference.clone()),
+            key_range: Some(range(self.keys)),
+            vel_range: self.velocities.map(range),
+            ..Default::default()
+        }
+    }
+}
+
+/// Find the samples matching a pattern, and split the keyboard between them.
+///
+/// The pattern is relative to the samples directory. Its file name may have `*` for any text,
+/// `?` for any character, `{note}` for a note name or MIDI key number, and `{vel}` for the
+/// velocity the sample was recorded at. Key ranges split halfway between neighbouring roots,
+/// and cover the whole keyboard. Likewise velocity ranges, with each velocity layer mapped
+/// separately.
+///
+/// Letters match in either case, so `*.wav` finds `.WAV` files too. File names that aren't
+/// valid UTF-8 can't be referred to from a project, so they're skipped.
+pub fn auto_map(
+    samples_dir: &Path,
+    pattern: &str,
+    middle_c: MiddleC,
+) -> Result<Vec<MappedSample>, CompilerError> {
+    let invalid = |reason: String| {
+        CompilerError::from(ErrorKind::InvalidAutoMap {
+            pattern: pattern.into(),
+            reason,
+        })
+    };
+
+    let (dir, file_pattern) = match pattern.rsplit_once('/') {
+        Some((dir, file_pattern)) => (Some(dir), file_pattern),
+        None => (None, pattern),
+    };
+    if dir.is_some_and(|dir| dir.contains(['*', '?', '{'])) {
+        return Err(invalid(
+            "Wildcards are only allowed in the file name.".into(),
+        ));
+    }
+    let tokens = tokenize(file_pattern).map_err(invalid)?;
+
+    let dir_path = samples_dir.join(dir.unwrap_or_default());
+    let entries = std::fs::read_dir(&dir_path).map_err(|err| ErrorKind::FileCantOpen {
+        path: dir_path.display().to_string(),
+        reason: err.to_string(),
+    })?;
+    let mut filenames: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    filenames.sort();
+
+    // Files by velocity layer, then root key
+    let mut layers: BTreeMap<Option<u8>, BTreeMap<u8, String>> = BTreeMap::new();
+    for filename in filenames {
+        let chars: Vec<char> = filename.chars().collect();
+        let mut captures = Captures::default();
+        if !matches(&tokens, &chars, &mut captures, middle_c) {
+            continue;
+        }
+        let (Some(root), vel) = (captures.note, captures.vel) else {
+            continue;
+        };
+        let reference = match dir {
+            Some(dir) => format!("{dir}/{filename}"),
+            None => filename,
+        };
+        if let Some(other) = layers
+            .entry(vel)
+            .or_default()
+            .insert(root, reference.clone())
+        {
+            return Err(invalid(format!(
+                "'{other}' and '{reference}' map to the same key{}.",
+                vel.map(|vel| format!(" and velocity {vel}"))
+                    .unwrap_or_default()
+            )));
+        }
+    }
+    if layers.is_empty() {
+        return Err(invalid("No files match.".into()));
+    }
+
+    let vels: Vec<u8> = layers.keys().flatten().copied().collect();
+    let vel_ranges = split_halfway(&vels);
+    let mut mapped = vec![];
+    for (i, (vel, samples)) in layers.into_iter().enumerate() {
+        let roots: Vec<u8> = samples.keys().copied().collect();
+        for ((root, reference), keys) in samples.into_iter().zip(split_halfway(&roots)) {
+            mapped.push(MappedSample {
+                reference,
+                root,
+                keys,
+                velocities: vel.map(|_| vel_ranges[i]),
+            });
+        }
+    }
+    Ok(mapped)
+}
+
+/// Inclusive ranges covering 0..=127, split halfway between neighbouring values. The values
+/// are sorted and unique.
+fn split_halfway(values: &[u8]) -> Vec<(u8, u8)> {
+    let halfway = |lo: u8, hi: u8| ((lo as u16 + hi as u16) / 2) as u8;
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let lo = match i {
+                0 => 0,
+                _ => halfway(values[i - 1], *value) + 1,
+            };
+            let hi = values.get(i + 1).map_or(127, |next| halfway(*value, *next));
+            (lo, hi)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    AnyText,
+    Note,
+    Vel,
+}
+
+fn tokenize(pattern: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = pattern;
+    while let Some(c) = rest.chars().next() {
+        let placeholder = [("{note}", Token::Note), ("{vel}", Token::Vel)]
+            .into_iter()
+            .find(|(text, _)| rest.starts_with(text));
+        if let Some((text, token)) = placeholder {
+            if tokens.contains(&token) {
+                return Err(format!("{text} is there more than once."));
+            }
+            tokens.push(token);
+            rest = &rest[text.len()..];
+            continue;
+        }
+        tokens.push(match c {
+            '*' => Token::AnyText,
+            '?' => Token::AnyChar,
+            c => Token::Char(c),
+        });
+        rest = &rest[c.len_utf8()..];
+    }
+    if !tokens.contains(&Token::Note) {
+        return Err("{note} is missing.".into());
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Default)]
+struct Captures {
+    note: Option<u8>,
+    vel: Option<u8>,
+}
+
+/// Match a whole file name. Wildcards and placeholders take as little as they can, as long as
+/// the rest matches and the placeholders are valid.
+fn matches(tokens: &[Token], text: &[char], captures: &mut Captures, middle_c: MiddleC) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return text.is_empty();
+    };
+    match token {
+        Token::Char(c) => {
+            text.first().is_some_and(|t| t.eq_ignore_ascii_case(c))
+                && matches(rest, &text[1..], captures, middle_c)
+        }
+        Token::AnyChar => !text.is_empty() && matches(rest, &text[1..], captures, middle_c),
+        Token::AnyText => {
+            (0..=text.len()).any(|len| matches(rest, &text[len..], captures, middle_c))
+        }
+        Token::Note | Token::Vel => (1..=text.len()).any(|len| {
+            let value: String = text[..len].iter().collect();
+            let parsed = match token {
+                Token::Note => parse_key(&value, middle_c),
+                _ => value.parse().ok().filter(|vel| (1..=127).contains(vel)),
+            };
+            let Some(parsed) = parsed else {
+                return false;
+            };
+            let capture = match token {
+                Token::Note => &mut captures.note,
+                _ => &mut captures.vel,
+            };
+            *capture = Some(parsed);
+            matches(rest, &text[len..], captures, middle_c)
+        }),
+    }
+}
+
+/// A MIDI key number, or a note name
+fn parse_key(value: &str, middle_c: MiddleC) -> Option<u8> {
+    if value.chars().all(|c| c.is_ascii_digit()) {
+        return value.parse().ok().filter(|key| *key <= 127);
+    }
+    NoteName::parse(value).and_then(|note| middle_c.key(note))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(pattern: &str, filename: &str) -> Option<(Option<u8>, Option<u8>)> {
+        let tokens = tokenize(pattern).unwrap();
+        let chars: Vec<char> = filename.chars().collect();
+        let mut captures = Captures::default();
+        matches(&tokens, &chars, &mut captures, MiddleC::default())
+            .then_some((captures.note, captures.vel))
+    }
+
+    #[test]
+    fn test_matches() {
+        let pattern = "*_{note}_{vel}.wav";
+        assert_eq!(
+            capture(pattern, "Piano_C4_80.wav"),
+            Some((Some(60), Some(80)))
+        );
+        assert_eq!(
+            capture(pattern, "Grand_Piano_F#2_127.wav"),
+            Some((Some(42), Some(127)))
+        );
+        assert_eq!(
+            capture(pattern, "Piano_61_80.wav"),
+            Some((Some(61), Some(80)))
+        );
+        assert_eq!(capture(pattern, "Piano_C4_0.wav"), None);
+        assert_eq!(capture(pattern, "Piano_C4_80.aif"), None);
+        assert_eq!(
+            capture(pattern, "PIANO_C4_80.WAV"),
+            Some((Some(60), Some(80)))
+        );
+        assert_eq!(
+            capture("{note}{vel}.wav", "Bb3100.wav"),
+            Some((Some(58), Some(100)))
+        );
+        assert_eq!(
+            capture("pluck?{note}.wav", "pluck-e2.wav"),
+            Some((Some(40), None))
+        );
+
+        assert!(tokenize("*_{vel}.wav").is_err());
+        assert!(tokenize("{note}_{note}.wav").is_err());
+    }
+
+    #[test]
+    fn test_split_halfway() {
+        assert_eq!(split_halfway(&[60]), [(0, 127)]);
+        assert_eq!(split_halfway(&[48, 60, 61]), [(0, 54), (55, 60), (61, 127)]);
+    }
+
+    #[test]
+    fn test_auto_map() {
+        let root = std::env::temp_dir().join(format!("sfc-auto-map-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("piano")).unwrap();
+        for filename in [
+            "p_C4_40.wav",
+            "p_C5_40.wav",
+            "p_C4_100.wav",
+            "p_C5.wav",
+            "notes.txt",
+        ] {
+            std::fs::write(root.join("piano").join(filename), []).unwrap();
+        }
+        std::fs::write(root.join("piano/p_60_100.wav"), []).unwrap();
+        let duplicate = auto_map(&root, "piano/p_{note}_{vel}.wav", MiddleC::default());
+        std::fs::remove_file(root.join("piano/p_60_100.wav")).unwrap();
+        let mapped = auto_map(&root, "piano/p_{note}_{vel}.wav", MiddleC::default());
+        let nothing = auto_map(&root, "piano/q_{note}.wav", MiddleC::default());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(duplicate.is_err());
+        assert!(nothing.is_err());
+        let sample = |reference: &str, root, keys, velocities| MappedSample {
+            reference: reference.into(),
+            root,
+            keys,
+            velocities: Some(velocities),
+        };
+        assert_eq!(
+            mapped.unwrap(),
+            [
+                sample("piano/p_C4_40.wav", 60, (0, 66), (0, 70)),
+                sample("piano/p_C5_40.wav", 72, (67, 127), (0, 70)),
+                sample("piano/p_C4_100.wav", 60, (0, 127), (71, 127)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,860 @@
+//! Turning a SoundFont 2 file back into a project
+
+use riff::{ChunkContents, ChunkId};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::{self, BufWriter},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    error::ErrorKind,
+    init::{cant_write, create_project_dir, MANIFEST},
+    note::MiddleC,
+    pitch::PrePitch,
+    preprocess_formats::{
+        sanitize_filename, PreInstZone, PreInstrument, PreModulator, PrePreset, PrePresetZone,
+        PreSample, PreSoundFont,
+    },
+    ranges::{PreRange, RangeBound},
+    CompilerError,
+};
+use crate::soundfont::{
+    pdta::{
+        GenAmountType, Generator, InstrumentGen, InstrumentMod, ModDestination, ModTransform,
+        ModulatorSource, SFSampleLink, SampleHeader, SourceCurve,
+    },
+    SoundFont2,
+};
+
+/// Decompile a SoundFont 2 file into a new project directory. Gives the manifest path.
+///
+/// Compiling the project gives an equivalent font: the same presets, instruments, generators,
+/// modulators and sample data, though records may come out in a different order. Stereo pairs
+/// used as pairs become stereo WAVE files again. Sample loops go into the WAVE files.
+pub fn decompile<P, Q>(input: P, dir: Q) -> Result<PathBuf, CompilerError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let input = input.as_ref();
+    let cant_read = |reason: String| {
+        CompilerError::from(ErrorKind::SoundFontCantRead {
+            path: input.display().to_string(),
+            reason,
+        })
+    };
+    let bytes = std::fs::read(input).map_err(|err| cant_read(err.to_string()))?;
+    let font = SoundFont2::from_bytes(&bytes).map_err(|err| cant_read(err.to_string()))?;
+    Decompiler::new(&font).project()?.write(dir.as_ref())
+}
+
+/// Everything that goes into the project directory
+struct Project {
+    manifest: PreSoundFont,
+    /// By filename
+    presets: Vec<(String, PrePreset)>,
+    instruments: Vec<(String, PreInstrument)>,
+    wavs: Vec<(String, Wav)>,
+}
+
+impl Project {
+    /// Refuses to write into a directory with anything in it.
+    fn write(&self, dir: &Path) -> Result<PathBuf, CompilerError> {
+        let manifest = &self.manifest;
+        create_project_dir(
+            dir,
+            &[
+                &manifest.presets_dir,
+                &manifest.instruments_dir,
+                &manifest.samples_dir,
+            ],
+        )?;
+        let presets_dir = dir.join(&manifest.presets_dir);
+        let instruments_dir = dir.join(&manifest.instruments_dir);
+        let samples_dir = dir.join(&manifest.samples_dir);
+
+        let manifest_path = dir.join(MANIFEST);
+        write_toml(&manifest_path, manifest)?;
+        for (filename, preset) in &self.presets {
+            write_toml(&presets_dir.join(filename), preset)?;
+        }
+        for (filename, instrument) in &self.instruments {
+            write_toml(&instruments_dir.join(filename), instrument)?;
+        }
+        for (filename, wav) in &self.wavs {
+            let path = samples_dir.join(filename);
+            wav.write(&path).map_err(|err| cant_write(&path, err))?;
+        }
+        Ok(manifest_path)
+    }
+}
+
+fn write_toml<T: Serialize>(path: &Path, value: &T) -> Result<(), CompilerError> {
+    let contents = toml::to_string(value).map_err(|err| cant_write(path, err))?;
+    std::fs::write(path, contents).map_err(|err| cant_write(path, err))
+}
+
+/// The samples a WAVE file is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SampleUse {
+    Mono(u16),
+    /// Left and right sample of a linked pair
+    Stereo(u16, u16),
+}
+
+struct Decompiler<'a> {
+    font: &'a SoundFont2,
+    middle_c: MiddleC,
+    /// Sample key in the manifest, by the samples it's made of
+    sample_keys: HashMap<SampleUse, String>,
+    samples: BTreeMap<String, PreSample>,
+    sample_names: UniqueNames,
+    sample_files: UniqueNames,
+    wavs: Vec<(String, Wav)>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(font: &'a SoundFont2) -> Self {
+        Self {
+            font,
+            middle_c: MiddleC::default(),
+            sample_keys: HashMap::new(),
+            samples: BTreeMap::new(),
+            sample_names: UniqueNames::default(),
+            sample_files: UniqueNames::default(),
+            wavs: vec![],
+        }
+    }
+
+    fn project(mut self) -> Result<Project, CompilerError> {
+        let font = self.font;
+
+        // The last header of each list is the terminal record
+        let inst_count = font.pdta.inst.contents.len().saturating_sub(1);
+        let mut inst_files = UniqueNames::default();
+        let mut instruments = vec![];
+        for idx in 0..inst_count {
+            let instrument = self.instrument(idx)?;
+            let filename = inst_files.claim(&sanitize_filename(&instrument.name));
+            instruments.push((format!("{filename}.toml"), instrument));
+        }
+
+        let preset_count = font.pdta.phdr.contents.len().saturating_sub(1);
+        let mut preset_files = UniqueNames::default();
+        let mut presets = vec![];
+        for idx in 0..preset_count {
+            let preset = self.preset(idx, &instruments)?;
+            let filename = preset_files.claim(&sanitize_filename(&format!(
+                "{:03}-{:03} {}",
+                preset.midi_bank, preset.midi_preset, preset.name
+            )));
+            presets.push((format!("{filename}.toml"), preset));
+        }
+
+        let info = &font.info;
+        let manifest = PreSoundFont {
+            sound_engine: (info.isng() != "EMU8000").then(|| info.isng().clone()),
+            name: info.inam().clone(),
+            authors: info.ieng().map(String::from),
+            target_product: info.iprd().map(String::from),
+            copyright: info.icop().map(String::from),
+            comments: info.icmt().map(String::from),
+            middle_c: self.middle_c,
+            force_16_bit: false,
+            dedupe_by_content: false,
+            output: None,
+            presets_dir: "presets".into(),
+            instruments_dir: "instruments".into(),
+            samples_dir: "samples".into(),
+            tests_dir: "tests".into(),
+            root: PathBuf::new(),
+            presets: presets
+                .iter()
+                .map(|(filename, _)| filename.clone())
+                .collect(),
+            samples: self.samples,
+        };
+
+        Ok(Project {
+            manifest,
+            presets,
+            instruments,
+            wavs: self.wavs,
+        })
+    }
+
+    fn preset(
+        &mut self,
+        idx: usize,
+        instruments: &[(String, PreInstrument)],
+    ) -> Result<PrePreset, CompilerError> {
+        let pdta = &self.font.pdta;
+        let header = &pdta.phdr.contents[idx];
+        let bags: Vec<(u16, u16)> = pdta
+            .pbag
+            .contents
+            .iter()
+            .map(|zone| (zone.gen_idx, zone.mod_idx))
+            .collect();
+        let ranges = zone_ranges(
+            &bags,
+            header.pbag_idx..pdta.phdr.contents[idx + 1].pbag_idx,
+            (pdta.pgen.contents.len(), pdta.pmod.contents.len()),
+            &format!("preset '{}'", header.name),
+        )?;
+
+        let mut names = UniqueNames::default();
+        let mut zones = BTreeMap::new();
+        for (i, zone) in ranges.into_iter().enumerate() {
+            let gens = &pdta.pgen.contents[zone.gens];
+            let mods = &pdta.pmod.contents[zone.mods];
+
+            let mut zone = PrePresetZone::default();
+            for gen in gens {
+                let amount = gen.gen_amount;
+                match (gen.sf_gen_oper, amount) {
+                    (Generator::KeyRange, GenAmountType::Range { lo, hi }) => {
+                        zone.key_range = Some(self.key_range(lo, hi))
+                    }
+                    (Generator::VelRange, GenAmountType::Range { lo, hi }) => {
+                        zone.vel_range = Some(vel_range(lo, hi))
+                    }
+                    (generator, amount) => {
+                        if let Some(field) = zone.generators.field_mut(generator) {
+                            *field = Some(amount.to_i16());
+                        }
+                    }
+                }
+            }
+            let mods: Vec<ModParts> = mods
+                .iter()
+                .map(|m| {
+                    (
+                        m.sf_mod_src_oper,
+                        m.sf_mod_dest_oper,
+                        m.mod_amount,
+                        m.sf_mod_amt_src_oper,
+                        m.sf_mod_trans_oper,
+                    )
+                })
+                .collect();
+            zone.modulators = modulators(&mods);
+
+            let name = match gens.last() {
+                Some(gen) if gen.sf_gen_oper == Generator::Instrument => {
+                    let inst_idx = gen.gen_amount.to_i16() as u16 as usize;
+                    let Some((filename, instrument)) = instruments.get(inst_idx) else {
+                        return Err(invalid(format!(
+                            "Preset '{}' uses instrument {inst_idx}, which doesn't exist.",
+                            header.name
+                        )));
+                    };
+                    zone.instrument = Some(filename.clone());
+                    names.claim(&instrument.name)
+                }
+                // Only the first zone may be global. The spec says to ignore others.
+                _ if i == 0 => names.claim("global"),
+                _ => continue,
+            };
+            zones.insert(name, zone);
+        }
+
+        Ok(PrePreset {
+            name: header.name.clone(),
+            midi_preset: header.preset,
+            midi_bank: header.bank,
+            zones,
+        })
+    }
+
+    fn instrument(&mut self, idx: usize) -> Result<PreInstrument, CompilerError> {
+        let font = self.font;
+        let pdta = &font.pdta;
+        let header = &pdta.inst.contents[idx];
+        let bags: Vec<(u16, u16)> = pdta
+            .ibag
+            .contents
+            .iter()
+            .map(|zone| (zone.gen_idx, zone.mod_idx))
+            .collect();
+        let ranges = zone_ranges(
+            &bags,
+            header.inst_bag_idx..pdta.inst.contents[idx + 1].inst_bag_idx,
+            (pdta.igen.contents.len(), pdta.imod.contents.len()),
+            &format!("instrument '{}'", header.name),
+        )?;
+        let zones: Vec<(&[InstrumentGen], &[InstrumentMod])> = ranges
+            .into_iter()
+            .map(|zone| {
+                (
+                    &pdta.igen.contents[zone.gens],
+                    &pdta.imod.contents[zone.mods],
+                )
+            })
+            .collect();
+
+        let mut names = UniqueNames::default();
+        let mut pre_zones = BTreeMap::new();
+        let mut merged = vec![false; zones.len()];
+        for (i, (gens, mods)) in zones.iter().enumerate() {
+            if merged[i] {
+                continue;
+            }
+            let Some(sample_idx) = sample_id(gens) else {
+                // Only the first zone may be global. The spec says to ignore others.
+                if i == 0 {
+                    pre_zones.insert(names.claim("global"), self.inst_zone(gens, mods));
+                }
+                continue;
+            };
+
+            let (sample_use, gens) = match self.stereo_partner(&zones, i, &merged) {
+                Some((partner, sample_use)) => {
+                    merged[partner] = true;
+                    // Pan is set for each channel when compiled
+                    let gens: Vec<InstrumentGen> = gens
+                        .iter()
+                        .filter(|gen| gen.sf_gen_oper != Generator::Pan)
+                        .cloned()
+                        .collect();
+                    (sample_use, gens)
+                }
+                None => (SampleUse::Mono(sample_idx), gens.to_vec()),
+            };
+            let key = self.sample_key(sample_use)?;
+            let mut zone = self.inst_zone(&gens, mods);
+            zone.sample = Some(key.clone());
+            pre_zones.insert(names.claim(&key), zone);
+        }
+
+        Ok(PreInstrument {
+            name: header.name.clone(),
+            zones: pre_zones,
+            auto_map: None,
+            default_modulators: Default::default(),
+        })
+    }
+
+    fn inst_zone(&self, gens: &[InstrumentGen], mods: &[InstrumentMod]) -> PreInstZone {
+        let mut zone = PreInstZone::default();
+        for gen in gens {
+            match (gen.sf_gen_oper, gen.gen_amount) {
+                (Generator::KeyRange, GenAmountType::Range { lo, hi }) => {
+                    zone.key_range = Some(self.key_range(lo, hi))
+                }
+                (Generator::VelRange, GenAmountType::Range { lo, hi }) => {
+                    zone.vel_range = Some(vel_range(lo, hi))
+                }
+                (generator, amount) => {
+                    if let Some(field) = zone.field_mut(generator) {
+                        *field = Some(amount.to_i16());
+                    }
+                }
+            }
+        }
+        let mods: Vec<ModParts> = mods
+            .iter()
+            .map(|m| {
+                (
+                    m.sf_mod_src_oper,
+                    m.sf_mod_dest_oper,
+                    m.mod_amount,
+                    m.sf_mod_amt_src_oper,
+                    m.sf_mod_trans_oper,
+                )
+            })
+            .collect();
+        zone.modulators = modulators(&mods);
+        zone
+    }
+
+    fn key_range(&self, lo: u8, hi: u8) -> PreRange {
+        let note = |key: u8| match key {
+            0..=127 => RangeBound::Note(self.middle_c.note(key)),
+            _ => RangeBound::Number(key),
+        };
+        PreRange {
+            lo: note(lo),
+            hi: note(hi),
+        }
+    }
+
+    /// The zone of the other channel, if the zone at `idx` is one channel of a stereo pair that
+    /// compiles back from a single zone: the samples are linked to each other, and the zones
+    /// only differ by their samples and hard left and right pan.
+    fn stereo_partner(
+        &self,
+        zones: &[(&[InstrumentGen], &[InstrumentMod])],
+        idx: usize,
+        merged: &[bool],
+    ) -> Option<(usize, SampleUse)> {
+        let headers = &self.font.pdta.shdr.contents;
+        let (gens, mods) = zones[idx];
+        let sample_idx = sample_id(gens)?;
+        let header = headers.get(sample_idx as usize)?;
+        let other_idx = header.sample_link;
+        headers.get(other_idx as usize)?;
+
+        let (left, right, pan, other_pan) = match header.sample_type {
+            t if t == SFSampleLink::Left as u16 => (sample_idx, other_idx, -500, 500),
+            t if t == SFSampleLink::Right as u16 => (other_idx, sample_idx, 500, -500),
+            _ => return None,
+        };
+        let (left_header, right_header) = (&headers[left as usize], &headers[right as usize]);
+        if left_header.sample_type != SFSampleLink::Left as u16
+            || right_header.sample_type != SFSampleLink::Right as u16
+            || left_header.sample_link != right
+            || right_header.sample_link != left
+            || !same_settings(left_header, right_header)
+            || pan_of(gens) != Some(pan)
+        {
+            return None;
+        }
+
+        let partner = (idx + 1..zones.len()).find(|&other_zone| {
+            let (other_gens, other_mods) = zones[other_zone];
+            !merged[other_zone]
+                && sample_id(other_gens) == Some(other_idx)
+                && pan_of(other_gens) == Some(other_pan)
+                && without_pan(other_gens) == without_pan(gens)
+                && other_mods == mods
+        })?;
+        Some((partner, SampleUse::Stereo(left, right)))
+    }
+
+    /// Manifest key of a sample, extracting it on first use.
+    fn sample_key(&mut self, sample_use: SampleUse) -> Result<String, CompilerError> {
+        if let Some(key) = self.sample_keys.get(&sample_use) {
+            return Ok(key.clone());
+        }
+
+        let headers = &self.font.pdta.shdr.contents;
+        let header = |idx: u16| {
+            headers.get(idx as usize).ok_or_else(|| {
+                invalid(format!(
+                    "Sample {idx} doesn't exist. There are {} samples.",
+                    headers.len().saturating_sub(1)
+                ))
+            })
+        };
+        let (header, name, channels) = match sample_use {
+            SampleUse::Mono(idx) => {
+                let header = header(idx)?;
+                (
+                    header,
+                    header.name.clone(),
+                    vec![self.sample_points(header)?],
+                )
+            }
+            SampleUse::Stereo(left, right) => {
+                let (left, right) = (header(left)?, header(right)?);
+                let channels = vec![self.sample_points(left)?, self.sample_points(right)?];
+                (left, stereo_name(&left.name), channels)
+            }
+        };
+
+        // Loops are only meaningful for looping zones. If it's not a valid one, leave it to
+        // the compiler's default, rather than failing the build.
+        let loop_points = header.validate().is_ok().then(|| {
+            (
+                header.startloop - header.start,
+                header.endloop - header.start,
+            )
+        });
+        let wav = Wav {
+            channels,
+            sample_rate: header.sample_rate,
+            high_resolution: self.font.sdta.sm24.is_some(),
+            original_pitch: header.original_pitch,
+            loop_points,
+        };
+
+        let key = self.sample_names.claim(&name);
+        let file = format!("{}.wav", self.sample_files.claim(&sanitize_filename(&key)));
+        let sample = PreSample {
+            file: (file != format!("{key}.wav")).then(|| file.clone()),
+            name: (key != name).then_some(name),
+            original_pitch: Some(PrePitch::Key(header.original_pitch)),
+            pitch_correction: Some(header.pitch_correction),
+            ..Default::default()
+        };
+        self.samples.insert(key.clone(), sample);
+        self.wavs.push((file, wav));
+        self.sample_keys.insert(sample_use, key.clone());
+        Ok(key)
+    }
+
+    /// 24-bit sample points. The low byte is zero without sm24.
+    fn sample_points(&self, header: &SampleHeader) -> Result<Vec<i32>, CompilerError> {
+        if header.sample_type & 0x8000 != 0 {
+            return Err(invalid(format!(
+                "Sample '{}' is in ROM, which isn't supported.",
+                header.name
+            )));
+        }
+        let sdta = &self.font.sdta;
+        let range = header.start as usize..header.end as usize;
+        if range.start > range.end || range.end > sdta.smpl.len() {
+            return Err(invalid(format!(
+                "Sample '{}' is out of the bounds of the sample data.",
+                header.name
+            )));
+        }
+        Ok(range
+            .map(|i| {
+                let low = sdta.sm24.as_ref().and_then(|sm24| sm24.get(i).copied());
+                (sdta.smpl[i] as i32) << 8 | low.unwrap_or(0) as i32
+            })
+            .collect())
+    }
+}
+
+fn invalid(reason: String) -> CompilerError {
+    ErrorKind::InvalidSoundFont { reason }.into()
+}
+
+/// Generator and modulator index ranges of a zone
+struct ZoneIndices {
+    gens: Range<usize>,
+    mods: Range<usize>,
+}
+
+/// Index ranges of each zone in `bags[zones]`.
+fn zone_ranges(
+    bags: &[(u16, u16)],
+    zones: Range<u16>,
+    (gen_len, mod_len): (usize, usize),
+    owner: &str,
+) -> Result<Vec<ZoneIndices>, CompilerError> {
+    let out_of_order = || {
+        invalid(format!(
+            "Zone indices of {owner} are out of order or bounds."
+        ))
+    };
+    let zones = zones.start as usize..zones.end as usize;
+    if zones.start > zones.end || zones.end >= bags.len() {
+        return Err(out_of_order());
+    }
+    zones
+        .map(|zone| {
+            let (gen_start, mod_start) = bags[zone];
+            let (gen_end, mod_end) = bags[zone + 1];
+            let gens = gen_start as usize..gen_end as usize;
+            let mods = mod_start as usize..mod_end as usize;
+            if gens.start > gens.end
+                || gens.end > gen_len
+                || mods.start > mods.end
+                || mods.end > mod_len
+            {
+                return Err(out_of_order());
+            }
+            Ok(ZoneIndices { gens, mods })
+        })
+        .collect()
+}
+
+fn vel_range(lo: u8, hi: u8) -> PreRange {
+    PreRange {
+        lo: RangeBound::Number(lo),
+        hi: RangeBound::Number(hi),
+    }
+}
+
+/// The sample id, if the zone ends in one
+fn sample_id(gens: &[InstrumentGen]) -> Option<u16> {
+    gens.last()
+        .filter(|gen| gen.sf_gen_oper == Generator::SampleId)
+        .map(|gen| gen.gen_amount.to_i16() as u16)
+}
+
+fn pan_of(gens: &[InstrumentGen]) -> Option<i16> {
+    gens.iter()
+        .rfind(|gen| gen.sf_gen_oper == Generator::Pan)
+        .map(|gen| gen.gen_amount.to_i16())
+}
+
+/// Generators apart from pan and the sample id, for comparing the zones of a stereo pair
+fn without_pan(gens: &[InstrumentGen]) -> Vec<(Generator, GenAmountType)> {
+    gens.iter()
+        .filter(|gen| !matches!(gen.sf_gen_oper, Generator::Pan | Generator::SampleId))
+        .map(|gen| (gen.sf_gen_oper, gen.gen_amount))
+        .collect()
+}
+
+/// Whether two samples can share one stereo WAVE file and its metadata
+fn same_settings(a: &SampleHeader, b: &SampleHeader) -> bool {
+    a.end - a.start == b.end - b.start
+        && a.startloop.wrapping_sub(a.start) == b.startloop.wrapping_sub(b.start)
+        && a.endloop.wrapping_sub(a.start) == b.endloop.wrapping_sub(b.start)
+        && a.sample_rate == b.sample_rate
+        && a.original_pitch == b.original_pitch
+        && a.pitch_correction == b.pitch_correction
+}
+
+/// Name of a stereo pair, without the left channel's suffix. Short enough for the compiler to
+/// add "_L" and "_R".
+fn stereo_name(left_name: &str) -> String {
+    let trimmed = left_name.trim_end();
+    let base = ["_L", " L", "-L", ".L", "(L)", "_l", " l", "-l", ".l", "(l)"]
+        .iter()
+        .find_map(|suffix| trimmed.strip_suffix(suffix))
+        .unwrap_or(trimmed)
+        .trim_end_matches([' ', '_', '-', '.']);
+    let base = if base.is_empty() { trimmed } else { base };
+    base.chars().take(18).collect()
+}
+
+/// Source, destination, amount, amount source and transform of a preset or instrument modulator
+type ModParts = (
+    ModulatorSource,
+    ModDestination,
+    i16,
+    ModulatorSource,
+    ModTransform,
+);
+
+/// A zone's modulators, leaving out the ones synths ignore: those with a curve, destination or
+/// transform the spec doesn't define, and links to those. Links are renumbered to match.
+fn modulators(mods: &[ModParts]) -> Vec<PreModulator> {
+    let defined = |(source, dest, _, amount_source, transform): &ModParts| {
+        !matches!(source.curve, SourceCurve::Unknown(_))
+            && !matches!(amount_source.curve, SourceCurve::Unknown(_))
+            && !matches!(dest, ModDestination::Generator(Generator::Unknown(_)))
+            && !matches!(transform, ModTransform::Unknown(_))
+    };
+    let mut kept: Vec<usize> = (0..mods.len()).filter(|i| defined(&mods[*i])).collect();
+    // Dropping a modulator can leave a link pointing nowhere, so repeat until nothing changes
+    loop {
+        let linked: Vec<usize> = kept
+            .iter()
+            .copied()
+            .filter(|i| match mods[*i].1 {
+                ModDestination::Link(target) => kept.contains(&(target as usize)),
+                _ => true,
+            })
+            .collect();
+        if linked.len() == kept.len() {
+            break;
+        }
+        kept = linked;
+    }
+    kept.iter()
+        .map(|i| {
+            let (source, dest, amount, amount_source, transform) = mods[*i];
+            let dest = match dest {
+                ModDestination::Link(target) => {
+                    let index = kept.iter().position(|k| *k == target as usize).unwrap();
+                    ModDestination::Link(index as u16)
+                }
+                dest => dest,
+            };
+            modulator(source, dest, amount, amount_source, transform)
+        })
+        .collect()
+}
+
+fn modulator(
+    source: ModulatorSource,
+    dest: ModDestination,
+    amount: i16,
+    amount_source: ModulatorSource,
+    transform: ModTransform,
+) -> PreModulator {
+    let curve = |source: ModulatorSource| {
+        (source.curve != SourceCurve::Linear).then(|| source.curve.name().to_string())
+    };
+    let has_amount_source = amount_source != ModulatorSource::NONE;
+    PreModulator {
+        source: source.to_string(),
+        curve: curve(source),
+        bipolar: source.bipolar,
+        negative: source.negative,
+        dest: match dest {
+            ModDestination::Generator(generator) => generator.name().into(),
+            ModDestination::Link(index) => format!("modulator:{index}"),
+        },
+        amount,
+        amount_source: has_amount_source.then(|| amount_source.to_string()),
+        amount_curve: has_amount_source.then(|| curve(amount_source)).flatten(),
+        amount_bipolar: has_amount_source && amount_source.bipolar,
+        amount_negative: has_amount_source && amount_source.negative,
+        transform: (transform != ModTransform::Linear).then(|| transform.name().to_string()),
+    }
+}
+
+/// Names not taken yet. Compared case-insensitively, as they may become filenames.
+#[derive(Default)]
+struct UniqueNames(HashSet<String>);
+
+impl UniqueNames {
+    /// The name itself if it's free, otherwise numbered, e.g. "Piano (2)".
+    fn claim(&mut self, name: &str) -> String {
+        let name = if name.is_empty() { "unnamed" } else { name };
+        let mut candidate = name.to_string();
+        let mut number = 2;
+        while !self.0.insert(candidate.to_lowercase()) {
+            candidate = format!("{name} ({number})");
+            number += 1;
+        }
+        candidate
+    }
+}
+
+/// An extracted sample, ready to be written as a WAVE file
+struct Wav {
+    /// 24-bit sample points of each channel
+    channels: Vec<Vec<i32>>,
+    sample_rate: u32,
+    /// Write 24 bits instead of 16
+    high_resolution: bool,
+    original_pitch: u8,
+    /// startloop and endloop in frames
+    loop_points: Option<(u32, u32)>,
+}
+
+impl Wav {
+    /// PCM data, with the loop in a `smpl` chunk.
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let channel_count = self.channels.len() as u16;
+        let point_size: u16 = if self.high_resolution { 3 } else { 2 };
+        let block_align = channel_count * point_size;
+
+        let mut fmt = vec![];
+        fmt.extend(1u16.to_le_bytes()); // PCM
+        fmt.extend(channel_count.to_le_bytes());
+        fmt.extend(self.sample_rate.to_le_bytes());
+        fmt.extend((self.sample_rate * block_align as u32).to_le_bytes());
+        fmt.extend(block_align.to_le_bytes());
+        fmt.extend((point_size * 8).to_le_bytes());
+
+        let len = self.channels.first().map_or(0, Vec::len);
+        let mut data = Vec::with_capacity(len * block_align as usize);
+        for i in 0..len {
+            for channel in &self.channels {
+                // 24-bit points are the low three bytes. 16-bit ones are the upper two of those.
+                let bytes = channel[i].to_le_bytes();
+                data.extend_from_slice(&bytes[3 - point_size as usize..3]);
+            }
+        }
+
+        let mut contents = vec![
+            ChunkContents::Data(ChunkId { value: *b"fmt " }, fmt),
+            ChunkContents::Data(ChunkId { value: *b"data" }, data),
+        ];
+        if let Some((startloop, endloop)) = self.loop_points {
+            let period_ns = 1_000_000_000 / self.sample_rate.max(1);
+            // Manufacturer, product, sample period, MIDI unity note, pitch fraction, SMPTE
+            // format and offset, loop count, sampler data size. Then the loop: cue point id,
+            // type (forward), start, end (inclusive), fraction, play count (infinite).
+            let fields = [
+                0,
+                0,
+                period_ns,
+                self.original_pitch as u32,
+                0,
+                0,
+                0,
+                1,
+                0,
+                0,
+                0,
+                startloop,
+                endloop.saturating_sub(1),
+                0,
+                0,
+            ];
+            let smpl = fields
+                .iter()
+                .flat_map(|field| field.to_le_bytes())
+                .collect();
+            contents.push(ChunkContents::Data(ChunkId { value: *b"smpl" }, smpl));
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+        ChunkContents::Children(riff::RIFF_ID, ChunkId { value: *b"WAVE" }, contents)
+            .write(&mut file)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::FontData;
+
+    fn compile(manifest: &Path, output: &Path) -> Vec<u8> {
+        let fontdata = FontData::read(manifest).unwrap();
+        fontdata.generate_soundfont().save(output).unwrap();
+        std::fs::read(output).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let root = std::env::temp_dir().join(format!("sfc-decompile-{}", std::process::id()));
+        let src = root.join("src");
+        std::fs::create_dir_all(src.join("samples")).unwrap();
+
+        // 24-bit stereo, so the pair and the sm24 chunk both have to survive
+        let wave = |phase: f32| -> Vec<i32> {
+            (0..200)
+                .map(|i| ((i as f32 * 0.2 + phase).sin() * 4_000_000.0) as i32)
+                .collect()
+        };
+        let pad = Wav {
+            channels: vec![wave(0.0), wave(1.0)],
+            sample_rate: 22050,
+            high_resolution: true,
+            original_pitch: 57,
+            loop_points: Some((20, 180)),
+        };
+        pad.write(&src.join("samples/pad.wav")).unwrap();
+        std::fs::write(
+            src.join("SoundFont.toml"),
+            "name = \"Round Trip\"\npresets = [\"Pad.toml\"]\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(src.join("presets")).unwrap();
+        std::fs::create_dir_all(src.join("instruments")).unwrap();
+        std::fs::write(
+            src.join("presets/Pad.toml"),
+            "name = \"Pad\"\nmidi_preset = 3\nmidi_bank = 1\n\
+             [zones.pad]\ninstrument = \"Pad.toml\"\ncoarse_tune = 2\n",
+        )
+        .unwrap();
+        std::fs::write(
+            src.join("instruments/Pad.toml"),
+            "name = \"Pad\"\n\
+             [zones.global]\nattack_vol_env = -1200\n\
+             [zones.pad]\nsample = \"pad.wav\"\nkey_range = \"C2..=C6\"\nsample_modes = 1\n\
+             [[zones.pad.modulators]]\nsource = \"cc1\"\ndest = \"initial_filter_fc\"\namount = -2400\n",
+        )
+        .unwrap();
+
+        let original = compile(&src.join("SoundFont.toml"), &root.join("original.sf2"));
+        let manifest = decompile(root.join("original.sf2"), root.join("decompiled")).unwrap();
+        let samples = std::fs::read_dir(root.join("decompiled/samples")).unwrap();
+        assert_eq!(samples.count(), 1, "Stereo pair should be one file");
+        let recompiled = compile(&manifest, &root.join("recompiled.sf2"));
+
+        let not_empty = decompile(root.join("original.sf2"), root.join("decompiled"));
+        std::fs::remove_dir_all(&root).unwrap();
+
+        // INFO has the creation date, so compare from the sample data on
+        let lists = |font: &[u8]| {
+            let sdta = font.windows(4).position(|id| id == b"sdta").unwrap();
+            font[sdta..].to_vec()
+        };
+        assert!(
+            lists(&original) == lists(&recompiled),
+            "Recompiled font differs"
+        );
+        assert!(matches!(
+            *not_empty.unwrap_err().kind,
+            ErrorKind::DirectoryNotEmpty { .. }
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+//! Rendering errors compiler-style, with a snippet of the offending line
+
+use std::ops::Range;
+use toml::{
+    de::{DeTable, DeValue},
+    Spanned,
+};
+
+use super::CompilerError;
+
+/// ANSI styles
+const ERROR: &str = "1;31";
+const GUTTER: &str = "1;34";
+const BOLD: &str = "1";
+
+impl CompilerError {
+    /// Render for the terminal, e.g.
+    ///
+    /// ```text
+    /// error: Invalid range 'G4..=C2'.
+    ///   --> instruments/Guitar.toml:15:13
+    ///    |
+    /// 15 | key_range = "G4..C2"
+    ///    |             ^^^^^^^^
+    ///    = hint: Bounds must be within 0..=127, low first. Velocities can't be note names.
+    /// ```
+    pub fn render(&self) -> String {
+        self.render_styled(false)
+    }
+
+    /// [CompilerError::render], in color like rustc if `color` is set.
+    pub fn render_styled(&self, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("\x1b[{style}m{text}\x1b[0m")
+            } else {
+                text.to_string()
+            }
+        };
+        let mut out = format!(
+            "{}{}\n",
+            paint(ERROR, "error"),
+            paint(BOLD, &format!(": {}", self.kind))
+        );
+
+        let source = self
+            .path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok());
+        let (span, key_found) = match (&self.span, &source) {
+            (Some(span), _) => (Some(span.clone()), true),
+            (None, Some(source)) => find_key(source, &self.key),
+            (None, None) => (None, false),
+        };
+
+        match (&self.path, &source, span) {
+            (Some(path), Some(source), Some(span)) => {
+                let (line_idx, col) = line_col(source, span.start);
+                let line = source.lines().nth(line_idx).unwrap_or_default();
+                let line_no = (line_idx + 1).to_string();
+                let gutter = " ".repeat(line_no.len());
+
+                // Underline to the end of the first line at most
+                let width = span
+                    .end
+                    .saturating_sub(span.start)
+                    .min(line.len().saturating_sub(col))
+                    .max(1);
+                let col_chars = line[..col.min(line.len())].chars().count();
+
+                let arrow = paint(GUTTER, &format!("{gutter}-->"));
+                let bar = paint(GUTTER, &format!("{gutter} |"));
+                let equals = paint(GUTTER, &format!("{gutter} ="));
+                out += &format!("{arrow} {}:{line_no}:{}\n", path.display(), col_chars + 1);
+                out += &format!("{bar}\n");
+                out += &format!("{} {line}\n", paint(GUTTER, &format!("{line_no} |")));
+                out += &format!(
+                    "{bar} {}{}\n",
+                    " ".repeat(col_chars),
+                    paint(ERROR, &"^".repeat(width))
+                );
+                if !key_found && !self.key.is_empty() {
+                    out += &format!("{equals} in: {}\n", self.key.join("."));
+                }
+                if let Some(hint) = self.hint() {
+                    out += &format!("{equals} hint: {hint}\n");
+                }
+            }
+            (Some(path), _, _) => {
+                out += &format!("{} {}\n", paint(GUTTER, " -->"), path.display());
+                if !self.key.is_empty() {
+                    out += &format!("{} in: {}\n", paint(GUTTER, "  ="), self.key.join("."));
+                }
+                if let Some(hint) = self.hint() {
+                    out += &format!("{} hint: {hint}\n", paint(GUTTER, "  ="));
+                }
+            }
+            (None, _, _) => {
+                if let Some(hint) = self.hint() {
+                    out += &format!("{} hint: {hint}\n", paint(GUTTER, "  ="));
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Span of the value at a key path. Falls back to the deepest key found, and tells if the
+/// full path was found.
+fn find_key(source: &str, key: &[String]) -> (Option<Range<usize>>, bool) {
+    let Ok(root) = DeTable::parse(source) else {
+        return (None, false);
+    };
+    let root = Spanned::new(root.span(), DeValue::Table(root.into_inner()));
+    let mut value = &root;
+    let mut span = None;
+    for segment in key {
+        let next = match (value.get_ref(), segment.parse::<usize>()) {
+            (DeValue::Array(_), Ok(index)) => value.get_ref().get(index),
+            _ => value.get_ref().get(segment.as_str()),
+        };
+        let Some(next) = next else {
+            return (span, false);
+        };
+        value = next;
+        span = Some(value.span());
+    }
+    (span, true)
+}
+
+/// Zero based line index and byte column of a byte offset
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count();
+    let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1);
+    (line, col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "name = \"Guitar\"\n\n[zones.guitar]\nkey_range = \"G4..C2\"\n\n[[zones.guitar.modulators]]\nsource = \"cc6\"\n";
+
+    fn key(path: &str) -> Vec<String> {
+        path.split('.').map(String::from).collect()
+    }
+
+    #[test]
+    fn test_find_key() {
+        let (span, found) = find_key(SOURCE, &key("zones.guitar.key_range"));
+        assert!(found);
+        assert_eq!(&SOURCE[span.unwrap()], "\"G4..C2\"");
+
+        let (span, found) = find_key(SOURCE, &key("zones.guitar.modulators.0.source"));
+        assert!(found);
+        assert_eq!(&SOURCE[span.unwrap()], "\"cc6\"");
+
+        let (_, found) = find_key(SOURCE, &key("zones.guitar.vel_range"));
+        assert!(!found);
+    }
+
+    #[test]
+    fn test_line_col() {
+        assert_eq!(line_col(SOURCE, 0), (0, 0));
+        assert_eq!(line_col(SOURCE, SOURCE.find("\"G4").unwrap()), (3, 12));
+    }
+}
@@ -1,28 +1,296 @@
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, ops::Range, path::PathBuf};
 
 use crate::soundfont::SoundfontError;
 
+/// What went wrong
 #[derive(Debug, Clone)]
-pub enum CompilerError {
+pub enum ErrorKind {
     ProjectManifestCantOpen,
-    SoundfontError { source: SoundfontError },
+    FileCantOpen {
+        path: String,
+        reason: String,
+    },
+    /// The file isn't valid TOML, or doesn't fit the expected format
+    Toml {
+        message: String,
+    },
+    InvalidRange {
+        range: String,
+    },
+    UnknownKey {
+        key: String,
+    },
+    MultipleGlobalZones {
+        name: String,
+    },
+    InvalidModulator {
+        reason: String,
+    },
+    WavCantRead {
+        path: String,
+        reason: String,
+    },
+    UnsupportedChannelCount {
+        path: String,
+        channels: u16,
+    },
+    InvalidSampleLoop {
+        name: String,
+        len: u32,
+        startloop: u32,
+        endloop: u32,
+        source: SoundfontError,
+    },
+    SoundfontError {
+        source: SoundfontError,
+    },
+    SoundFontCantRead {
+        path: String,
+        reason: String,
+    },
+    /// The soundfont parsed, but its records don't fit together
+    InvalidSoundFont {
+        reason: String,
+    },
+    FileCantWrite {
+        path: String,
+        reason: String,
+    },
+    DirectoryNotEmpty {
+        path: String,
+    },
+    PresetNotFound {
+        preset: String,
+    },
+    InvalidNote {
+        note: String,
+    },
+    InvalidAudioTest {
+        reason: String,
+    },
+    InvalidAutoMap {
+        pattern: String,
+        reason: String,
+    },
+    /// A zone made by auto_map is invalid
+    AutoMappedZone {
+        file: String,
+        source: Box<ErrorKind>,
+    },
+    PitchNotDetected {
+        name: String,
+    },
+    LoopNotFound {
+        name: String,
+    },
+    MidiCantRead {
+        path: String,
+        reason: String,
+    },
+    /// The synthesizer refused the soundfont or the settings
+    RenderFailed {
+        reason: String,
+    },
 }
 
-impl Display for CompilerError {
+impl ErrorKind {
+    /// Advice on fixing the error, if there's something to add to the message.
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            ErrorKind::ProjectManifestCantOpen => {
+                Some("Give the manifest path, e.g. build my_font/SoundFont.toml".into())
+            }
+            ErrorKind::FileCantOpen { .. } => Some(
+                "Paths are relative to the presets, instruments and samples directories next to the manifest.".into(),
+            ),
+            ErrorKind::InvalidRange { .. } => Some(
+                "Bounds must be within 0..=127, low first. Velocities can't be note names.".into(),
+            ),
+            ErrorKind::UnknownKey { .. } => Some(
+                "Check the spelling. Generators are snake case, e.g. initial_filter_fc.".into(),
+            ),
+            ErrorKind::MultipleGlobalZones { .. } => {
+                Some("Only one zone may leave out its sample or instrument.".into())
+            }
+            ErrorKind::UnsupportedChannelCount { .. } => {
+                Some("Only mono and stereo samples are supported.".into())
+            }
+            ErrorKind::InvalidSampleLoop { .. } => Some(
+                "Loop points come from startloop and endloop in the sample metadata, or from the loop embedded in the WAV.".into(),
+            ),
+            ErrorKind::DirectoryNotEmpty { .. } => {
+                Some("Give a new or empty directory, so that no files are overwritten.".into())
+            }
+            ErrorKind::PresetNotFound { .. } => Some(
+                "Give the preset as bank:program, e.g. 0:25, or by name. The info command lists them.".into(),
+            ),
+            ErrorKind::InvalidAutoMap { .. } => Some(
+                "Patterns are relative to the samples directory, e.g. \"piano/*_{note}_{vel}.wav\". {note} is a note name or MIDI key number, {vel} a velocity.".into(),
+            ),
+            ErrorKind::AutoMappedZone { source, .. } => source.hint(),
+            ErrorKind::PitchNotDetected { .. } => Some(
+                "The sample may be too short or silent. Give original_pitch as a MIDI key number instead.".into(),
+            ),
+            ErrorKind::LoopNotFound { .. } => Some(
+                "The sample may be too short, silent or without zero crossings. Give startloop and endloop instead.".into(),
+            ),
+            ErrorKind::InvalidNote { .. } => Some(
+                "Notes are NOTE[+NOTE...][:VELOCITY[:SECONDS]], e.g. \"C4 E4:80 G4:100:2 C4+E4+G4\".".into(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CompilerError::ProjectManifestCantOpen => {
+            ErrorKind::ProjectManifestCantOpen => {
                 write!(f, "Can't access project manifest at given path.")
             }
-            CompilerError::SoundfontError { source } => source.fmt(f),
+            ErrorKind::FileCantOpen { path, reason } => {
+                write!(f, "Can't open '{path}': {reason}")
+            }
+            ErrorKind::Toml { message } => write!(f, "{}", message.trim_end()),
+            ErrorKind::InvalidRange { range } => write!(f, "Invalid range '{range}'."),
+            ErrorKind::UnknownKey { key } => write!(f, "Unknown key '{key}'."),
+            ErrorKind::MultipleGlobalZones { name } => {
+                write!(f, "'{name}' has more than one global zone.")
+            }
+            ErrorKind::InvalidModulator { reason } => {
+                write!(f, "Invalid modulator. {reason}")
+            }
+            ErrorKind::WavCantRead { path, reason } => {
+                write!(f, "Can't read WAVE file '{path}': {reason}")
+            }
+            ErrorKind::UnsupportedChannelCount { path, channels } => {
+                write!(f, "'{path}' has {channels} channels.")
+            }
+            ErrorKind::InvalidSampleLoop {
+                name,
+                len,
+                startloop,
+                endloop,
+                source,
+            } => {
+                write!(
+                    f,
+                    "Sample '{name}' is {len} points long, with loop {startloop}..{endloop}. {source}"
+                )
+            }
+            ErrorKind::SoundfontError { source } => source.fmt(f),
+            ErrorKind::SoundFontCantRead { path, reason } => {
+                write!(f, "Can't read soundfont '{path}': {reason}")
+            }
+            ErrorKind::InvalidSoundFont { reason } => write!(f, "Invalid soundfont. {reason}"),
+            ErrorKind::FileCantWrite { path, reason } => {
+                write!(f, "Can't write '{path}': {reason}")
+            }
+            ErrorKind::DirectoryNotEmpty { path } => write!(f, "'{path}' is not empty."),
+            ErrorKind::PresetNotFound { preset } => write!(f, "No preset '{preset}'."),
+            ErrorKind::InvalidNote { note } => write!(f, "Invalid note '{note}'."),
+            ErrorKind::InvalidAudioTest { reason } => write!(f, "Invalid audio test. {reason}"),
+            ErrorKind::PitchNotDetected { name } => {
+                write!(f, "Can't detect the pitch of sample '{name}'.")
+            }
+            ErrorKind::LoopNotFound { name } => {
+                write!(f, "Can't find a loop in sample '{name}'.")
+            }
+            ErrorKind::InvalidAutoMap { pattern, reason } => {
+                write!(f, "Invalid auto_map '{pattern}'. {reason}")
+            }
+            ErrorKind::AutoMappedZone { file, source } => {
+                write!(f, "Auto-mapped sample '{file}': {source}")
+            }
+            ErrorKind::MidiCantRead { path, reason } => {
+                write!(f, "Can't read MIDI file '{path}': {reason}")
+            }
+            ErrorKind::RenderFailed { reason } => write!(f, "Can't render: {reason}"),
+        }
+    }
+}
+
+/// A compile error, located in the project as precisely as possible.
+///
+/// Errors are created without a location, and gain context on their way up: first the key
+/// path within a file, then the file itself. Once the file is known, the location is final.
+#[derive(Debug, Clone)]
+pub struct CompilerError {
+    pub kind: Box<ErrorKind>,
+    /// File the error is in
+    pub path: Option<PathBuf>,
+    /// Key path in the file, e.g. `["zones", "guitar", "key_range"]`. Array items are indices.
+    pub key: Vec<String>,
+    /// Byte range in the file. If unknown, it's looked up from the key when rendering.
+    pub span: Option<Range<usize>>,
+}
+
+impl CompilerError {
+    /// Prefix the key path with the key of the containing table, unless already located.
+    pub fn at_key(mut self, prefix: &[&str]) -> Self {
+        if self.path.is_none() {
+            let mut key: Vec<String> = prefix.iter().map(|s| s.to_string()).collect();
+            key.append(&mut self.key);
+            self.key = key;
         }
+        self
+    }
+
+    /// Place the error in a file, unless already located.
+    pub fn in_file<P>(mut self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        if self.path.is_none() {
+            self.path = Some(path.into());
+        }
+        self
+    }
+
+    pub fn with_span(mut self, span: Option<Range<usize>>) -> Self {
+        self.span = span;
+        self
+    }
+
+    pub fn hint(&self) -> Option<String> {
+        self.kind.hint()
+    }
+}
+
+impl Display for CompilerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}: ", path.display())?;
+        }
+        self.kind.fmt(f)
     }
 }
 
 impl Error for CompilerError {}
 
+impl From<ErrorKind> for CompilerError {
+    fn from(kind: ErrorKind) -> Self {
+        Self {
+            kind: Box::new(kind),
+            path: None,
+            key: vec![],
+            span: None,
+        }
+    }
+}
+
 impl From<SoundfontError> for CompilerError {
     fn from(source: SoundfontError) -> Self {
-        CompilerError::SoundfontError { source }
+        ErrorKind::SoundfontError { source }.into()
+    }
+}
+
+impl From<toml::de::Error> for CompilerError {
+    fn from(err: toml::de::Error) -> Self {
+        let span = err.span();
+        CompilerError::from(ErrorKind::Toml {
+            message: err.message().into(),
+        })
+        .with_span(span)
     }
 }
@@ -0,0 +1,72 @@
+//! Starting a new project
+
+use std::path::{Path, PathBuf};
+
+use super::{error::ErrorKind, CompilerError};
+
+/// Filename of the manifest in a new project
+pub(super) const MANIFEST: &str = "SoundFont.toml";
+
+/// Create a new project in `dir`: a manifest, and empty presets, instruments and samples
+/// directories. Gives the manifest path.
+///
+/// The soundfont is named after the directory. The project compiles as is, into an empty font.
+pub fn init<P>(dir: P) -> Result<PathBuf, CompilerError>
+where
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    create_project_dir(dir, &["presets", "instruments", "samples"])?;
+
+    let name = std::path::absolute(dir)
+        .ok()
+        .and_then(|dir| {
+            dir.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "A New Soundfont".into());
+    let manifest = format!(
+        "# Required\n\
+         name = {}\n\
+         \n\
+         # Optional\n\
+         #authors = \"\"\n\
+         #copyright = \"\"\n\
+         #comments = \"\"\n\
+         \n\
+         # Required. Preset files in presets/, e.g. \"Piano.toml\"\n\
+         presets = []\n",
+        toml::Value::String(name)
+    );
+
+    let path = dir.join(MANIFEST);
+    std::fs::write(&path, manifest).map_err(|err| cant_write(&path, err))?;
+    Ok(path)
+}
+
+/// Create a project directory and its subdirectories. Refuses a directory with anything in it,
+/// so that no files are overwritten.
+pub(super) fn create_project_dir<S>(dir: &Path, subdirs: &[S]) -> Result<(), CompilerError>
+where
+    S: AsRef<Path>,
+{
+    if std::fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(ErrorKind::DirectoryNotEmpty {
+            path: dir.display().to_string(),
+        }
+        .into());
+    }
+    for subdir in subdirs {
+        let path = dir.join(subdir);
+        std::fs::create_dir_all(&path).map_err(|err| cant_write(&path, err))?;
+    }
+    Ok(())
+}
+
+pub(super) fn cant_write(path: &Path, err: impl ToString) -> CompilerError {
+    ErrorKind::FileCantWrite {
+        path: path.display().to_string(),
+        reason: err.to_string(),
+    }
+    .into()
+}
@@ -1,164 +1,163 @@
+mod audio_test;
+mod auto_loop;
+mod auto_map;
+mod decompile;
+mod diagnostic;
 mod error;
+mod init;
+mod note;
+mod pitch;
 mod preprocess_formats;
+mod project;
+mod ranges;
+mod render;
+mod sample;
+#[cfg(test)]
+mod test_project;
 
-use std::path::PathBuf;
-use wavers::Wav;
+use std::path::{Path, PathBuf};
 
 use crate::soundfont::pdta::{
-    GenAmountType, InstZone, Instrument, InstrumentGen, PdtaList, PresetGen, PresetHeader,
-    PresetZone, SampleHeader,
+    InstZone, Instrument, InstrumentGen, InstrumentMod, PdtaList, PresetGen, PresetHeader,
+    PresetMod, PresetZone, SampleHeader,
 };
-use crate::soundfont::{info::InfoList, sdta::SdtaList, SoundFont2};
-pub use error::CompilerError;
-use preprocess_formats::{PreInstrument, PrePreset, PreSoundFont};
-
-#[derive(Debug, Clone)]
-pub struct SampleWrap {
-    header: SampleHeader,
-    data: Vec<i16>,
-}
+use crate::soundfont::{
+    info::{InfoList, VersionTag},
+    sdta::SdtaList,
+    SoundFont2,
+};
+pub use audio_test::{AudioTest, Outcome};
+pub use decompile::decompile;
+pub use error::{CompilerError, ErrorKind};
+pub use init::init;
+pub use note::MiddleC;
+pub use pitch::DetectedPitch;
+use preprocess_formats::PreSoundFont;
+use project::ProjectReader;
+pub use render::{render, Difference, Note, PresetSelector, RenderSettings, Rendering, Score};
+pub use sample::SampleWrap;
 
 #[derive(Debug)]
 pub struct FontData {
-    info_list: Option<InfoList>,
+    info_list: InfoList,
+    force_16_bit: bool,
+    output_path: PathBuf,
+    middle_c: MiddleC,
+    tests_dir: PathBuf,
 
     preset_headers: Vec<PresetHeader>,
     preset_zones: Vec<PresetZone>,
     preset_gens: Vec<PresetGen>,
+    preset_mods: Vec<PresetMod>,
 
     inst_headers: Vec<Instrument>,
     inst_zones: Vec<InstZone>,
     inst_gens: Vec<InstrumentGen>,
+    inst_mods: Vec<InstrumentMod>,
 
     samples: Vec<SampleWrap>,
+    detected_pitches: Vec<(String, DetectedPitch)>,
 }
 
 impl FontData {
-    pub fn read(filepath: &str) -> Result<Self, CompilerError> {
-        let pre_info = PreSoundFont::read(filepath)?;
-
-        let info_list = pre_info.generate_infolist().ok();
-
-        let mut preset_headers: Vec<PresetHeader> = vec![];
-        let mut preset_zones: Vec<PresetZone> = vec![];
-        let mut preset_gens: Vec<PresetGen> = vec![];
-
-        let mut inst_headers: Vec<Instrument> = vec![];
-        let mut inst_zones: Vec<InstZone> = vec![];
-        let mut inst_gens: Vec<InstrumentGen> = vec![];
-
-        let mut samples: Vec<SampleWrap> = vec![];
-
-        for preset_filename in pre_info.presets {
-            let pre_preset = PrePreset::read(format!("example_project/presets/{preset_filename}"));
-            let pbag_idx = inst_zones.len() as u16;
-
-            for (_pzone_name, pre_pzone) in pre_preset.zones {
-                let path = PathBuf::from(format!(
-                    "example_project/instruments/{}",
-                    pre_pzone.instrument
-                ));
-                let inst = PreInstrument::read(path);
-
-                let inst_bag_idx = inst_zones.len() as u16;
-
-                for (_izone_name, pre_izone) in inst.zones {
-                    let pre_zone = pre_izone;
-                    let path =
-                        PathBuf::from(format!("example_project/samples/{}", pre_zone.sample));
-                    let mut wav: Wav<i16> = Wav::from_path(path).unwrap();
-                    assert_eq!(wav.n_channels(), 1); // Mono only for now
-
-                    let sample_data = wav.read().unwrap().to_vec();
-                    let sample_header = SampleHeader {
-                        name: pre_zone.sample.clone(),
-                        start: 0,
-                        end: sample_data.len() as u32,
-                        startloop: 8,
-                        endloop: sample_data.len() as u32 - 8,
-                        sample_rate: wav.sample_rate() as u32,
-                        original_pitch: pre_zone.original_pitch,
-                        pitch_correction: pre_zone.pitch_correction,
-                        sample_link: 0,
-                        sample_type: 1,
-                    };
-                    samples.push(SampleWrap {
-                        header: sample_header,
-                        data: sample_data,
-                    });
-
-                    inst_gens.push(InstrumentGen {
-                        sf_gen_oper: 53, // Sample id
-                        gen_amount: GenAmountType::Unsigned(samples.len() as u16 - 1),
-                    });
-
-                    inst_zones.push(InstZone {
-                        gen_idx: inst_gens.len() as u16 - 1,
-                        mod_idx: 0, //self.inst_mods.len(),
-                    });
-                }
-
-                inst_headers.push(Instrument {
-                    name: inst.name,
-                    inst_bag_idx,
-                });
-
-                preset_gens.push(PresetGen {
-                    sf_gen_oper: 41, // Inst id
-                    gen_amount: GenAmountType::Unsigned(inst_headers.len() as u16 - 1),
-                });
-
-                preset_zones.push(PresetZone {
-                    gen_idx: preset_gens.len() as u16 - 1,
-                    mod_idx: 0, //self.inst_mods.len(),
-                });
-            }
-
-            preset_headers.push(PresetHeader {
-                name: pre_preset.name,
-                preset: pre_preset.midi_preset,
-                bank: pre_preset.midi_bank,
-                pbag_idx,
-                library: 0,
-                genre: 0,
-                morphology: 0,
-            });
-        }
-
-        Ok(Self {
+    /// Read a project from its manifest. Fails with every error found in the project.
+    pub fn read<P>(filepath: P) -> Result<Self, Vec<CompilerError>>
+    where
+        P: AsRef<Path>,
+    {
+        let manifest_path = filepath.as_ref();
+        let pre_info = PreSoundFont::read(manifest_path).map_err(|err| vec![err])?;
+        let info_list = pre_info
+            .generate_infolist()
+            .map_err(|err| vec![err.in_file(manifest_path)])?;
+
+        let font = Self {
             info_list,
+            force_16_bit: pre_info.force_16_bit,
+            output_path: pre_info.output_path(),
+            middle_c: pre_info.middle_c,
+            tests_dir: pre_info.root.join(&pre_info.tests_dir),
+
+            preset_headers: vec![],
+            preset_zones: vec![],
+            preset_gens: vec![],
+            preset_mods: vec![],
+
+            inst_headers: vec![],
+            inst_zones: vec![],
+            inst_gens: vec![],
+            inst_mods: vec![],
+
+            samples: vec![],
+            detected_pitches: vec![],
+        };
+        ProjectReader::new(&pre_info, manifest_path, font).read()
+    }
 
-            preset_headers,
-            preset_zones,
-            preset_gens,
+    /// Output path from the manifest, or the default derived from the soundfont name
+    pub fn output_path(&self) -> &Path {
+        &self.output_path
+    }
 
-            inst_headers,
-            inst_zones,
-            inst_gens,
+    /// Octave convention for note names in the project
+    pub fn middle_c(&self) -> MiddleC {
+        self.middle_c
+    }
+
+    /// Root keys and corrections detected from the audio, by sample name
+    pub fn detected_pitches(&self) -> &[(String, DetectedPitch)] {
+        &self.detected_pitches
+    }
 
-            samples,
-        })
+    /// Directory of the project's audio tests
+    pub fn tests_dir(&self) -> &Path {
+        &self.tests_dir
     }
 
-    /// TODO: Make un-mut
-    pub fn generate_soundfont(&mut self) -> SoundFont2 {
-        let info = self.info_list.as_ref().unwrap().clone();
+    pub fn generate_soundfont(&self) -> SoundFont2 {
+        let mut info = self.info_list.clone();
 
         let mut sdta = SdtaList::default();
+        let use_sm24 = !self.force_16_bit && self.samples.iter().any(|s| s.high_resolution);
+        let mut sm24 = vec![];
+        let mut sample_headers = Vec::with_capacity(self.samples.len() + 1);
 
-        for sample in &mut self.samples {
-            let data = &sample.data;
-            let header = &mut sample.header;
-
-            // Header offsets were relative until now.
+        for sample in &self.samples {
+            // Header offsets are relative to the sample's own data.
             let position = sdta.smpl.len();
+            let mut header = sample.header.clone();
             header.start += position as u32;
             header.end += position as u32;
             header.startloop += position as u32;
             header.endloop += position as u32;
+            sample_headers.push(header);
+
+            if use_sm24 {
+                sdta.smpl.extend(sample.upper_bits());
+                sm24.extend(sample.lower_bits());
+            } else if sample.high_resolution {
+                sdta.smpl.append(&mut sample.dithered());
+            } else {
+                sdta.smpl.extend(sample.upper_bits());
+            }
+
+            // 46 or more points padding required
+            sdta.smpl.extend([0; 46]);
+            if use_sm24 {
+                sm24.extend([0; 46]);
+            }
+        }
 
-            sdta.smpl.append(&mut data.clone());
-            sdta.smpl.append(&mut vec![0, 46]); // 46 or more points padding required
+        if use_sm24 {
+            // Byte count must be even
+            if !sm24.len().is_multiple_of(2) {
+                sm24.push(0);
+            }
+            sdta.sm24 = Some(sm24);
+            // sm24 was introduced in 2.04
+            info.set_ifil(VersionTag::new(2, 4));
+        } else {
+            info.set_ifil(VersionTag::new(2, 1));
         }
 
         // --- pdta
@@ -168,7 +167,7 @@ impl FontData {
             pdta.phdr.contents.push(header.clone());
         }
         pdta.phdr.contents.push(PresetHeader {
-            name: "EOI".into(),
+            name: "EOP".into(),
             preset: 0,
             bank: 0,
             pbag_idx: self.preset_zones.len() as u16,
@@ -179,11 +178,16 @@ impl FontData {
         for zone in &self.preset_zones {
             pdta.pbag.contents.push(zone.clone());
         }
-        pdta.pbag
-            .contents
-            .push(PresetZone::new(self.preset_gens.len() as u16, 0));
+        pdta.pbag.contents.push(PresetZone::new(
+            self.preset_gens.len() as u16,
+            self.preset_mods.len() as u16,
+        ));
 
         // --- pdta: pmod
+        for pmod in &self.preset_mods {
+            pdta.pmod.contents.push(pmod.clone());
+        }
+        pdta.pmod.contents.push(PresetMod::terminal());
 
         // --- pdta: pgen
         for pgen in &self.preset_gens {
@@ -197,6 +201,7 @@ impl FontData {
         }
         pdta.inst.contents.push(Instrument {
             name: "EOI".into(),
+            raw_name: None,
             inst_bag_idx: self.inst_zones.len() as u16,
         });
 
@@ -204,11 +209,16 @@ impl FontData {
         for zone in &self.inst_zones {
             pdta.ibag.contents.push(zone.clone());
         }
-        pdta.ibag
-            .contents
-            .push(InstZone::new(self.inst_gens.len() as u16, 0));
+        pdta.ibag.contents.push(InstZone::new(
+            self.inst_gens.len() as u16,
+            self.inst_mods.len() as u16,
+        ));
 
         // --- pdta: imod
+        for imod in &self.inst_mods {
+            pdta.imod.contents.push(imod.clone());
+        }
+        pdta.imod.contents.push(InstrumentMod::terminal());
 
         // --- pdta: igen
         for igen in &self.inst_gens {
@@ -217,11 +227,93 @@ impl FontData {
         pdta.igen.contents.push(InstrumentGen::terminal());
 
         // --- pdta: shdr
-        for sample in &self.samples {
-            pdta.shdr.contents.push(sample.header.clone());
-        }
-        pdta.shdr.contents.push(SampleHeader::terminal());
+        sample_headers.push(SampleHeader::terminal());
+        pdta.shdr.contents = sample_headers;
 
         SoundFont2::new(info, sdta, pdta)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::test_project::TempProject;
+    use super::*;
+    use crate::soundfont::pdta::Generator;
+
+    #[test]
+    fn test_shared_instrument() {
+        // Both example presets play the guitar instrument
+        let fontdata = FontData::read("example_project/SoundFont.toml").unwrap();
+        assert_eq!(fontdata.preset_headers.len(), 2);
+        assert_eq!(fontdata.inst_headers.len(), 1);
+        let instruments: Vec<[u8; 2]> = fontdata
+            .preset_gens
+            .iter()
+            .filter(|gen| gen.sf_gen_oper == Generator::Instrument)
+            .map(|gen| gen.gen_amount.to_bytes())
+            .collect();
+        assert_eq!(instruments, [[0, 0], [0, 0]]);
+    }
+
+    #[test]
+    fn test_manifest_relative_paths() {
+        // Run from elsewhere, with directories of its own
+        let project = TempProject::new("manifest-paths");
+        project
+            .file(
+                "SoundFont.toml",
+                "name = \"Test\"\npresets = [\"preset.toml\"]\n\
+                 presets_dir = \"banks/keys\"\ninstruments_dir = \"instruments\"\n\
+                 samples_dir = \"audio\"\n",
+            )
+            .file(
+                "banks/keys/preset.toml",
+                "name = \"Preset\"\nmidi_preset = 0\nmidi_bank = 0\n\
+                 [zones.main]\ninstrument = \"../strings/inst.toml\"\n",
+            )
+            .file("instruments/unused.toml", "name = \"Unused\"\n")
+            .file(
+                "strings/inst.toml",
+                "name = \"Inst\"\n[zones.a]\nsample = \"section/tone\"\n",
+            )
+            .wav("audio/section/tone.wav", 1, 16, 1000);
+        let manifest = project.root.join("SoundFont.toml");
+        let fontdata = FontData::read(manifest).unwrap();
+
+        assert_eq!(fontdata.inst_headers[0].name, "Inst");
+        assert_eq!(fontdata.samples[0].header.name, "tone");
+    }
+
+    #[test]
+    fn test_generate_twice() {
+        // Sample offsets are made absolute in the output, not in the font data
+        let project = TempProject::new("generate-twice");
+        project
+            .file(
+                "SoundFont.toml",
+                "name = \"Test\"\npresets = [\"preset.toml\"]\n",
+            )
+            .file(
+                "presets/preset.toml",
+                "name = \"Preset\"\nmidi_preset = 0\nmidi_bank = 0\n\
+                 [zones.main]\ninstrument = \"inst.toml\"\n",
+            )
+            .file(
+                "instruments/inst.toml",
+                "name = \"Inst\"\n\
+                 [zones.a]\nsample = \"tone\"\nkey_range = [0, 59]\n\
+                 [zones.b]\nsample = \"other\"\nkey_range = [60, 127]\n",
+            )
+            .wav("samples/tone.wav", 1, 16, 1000)
+            .wav("samples/other.wav", 1, 16, 1000);
+        let fontdata = FontData::read(project.root.join("SoundFont.toml")).unwrap();
+
+        let offsets = |font: &SoundFont2| -> Vec<(u32, u32)> {
+            let samples = &font.pdta.shdr.contents;
+            samples.iter().map(|s| (s.start, s.end)).collect()
+        };
+        let first = offsets(&fontdata.generate_soundfont());
+        assert_eq!(first[1], (1046, 2046));
+        assert_eq!(offsets(&fontdata.generate_soundfont()), first);
+    }
+}
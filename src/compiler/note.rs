@@ -0,0 +1,180 @@
+//! Note names, e.g. "C#4" or "Bb2"
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// Octave numbering convention for note names, given as the name of middle C (MIDI key 60).
+///
+/// Defaults to "C4", scientific pitch notation. Yamaha-style "C3" is also common.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MiddleC {
+    octave: i16,
+}
+
+impl Default for MiddleC {
+    fn default() -> Self {
+        Self { octave: 4 }
+    }
+}
+
+impl MiddleC {
+    /// MIDI key number of a note under this convention. None if outside 0..=127.
+    pub fn key(&self, note: NoteName) -> Option<u8> {
+        let key = 60 + 12 * (note.octave - self.octave) + note.pitch_class;
+        u8::try_from(key).ok().filter(|key| *key <= 127)
+    }
+
+    /// Name of a MIDI key under this convention
+    pub fn note(&self, key: u8) -> NoteName {
+        let key = key as i16;
+        NoteName {
+            pitch_class: key % 12,
+            octave: key / 12 - 5 + self.octave,
+        }
+    }
+}
+
+impl From<MiddleC> for String {
+    fn from(middle_c: MiddleC) -> Self {
+        format!("C{}", middle_c.octave)
+    }
+}
+
+impl TryFrom<String> for MiddleC {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match NoteName::parse(&value) {
+            Some(note) if note.pitch_class == 0 => Ok(Self {
+                octave: note.octave,
+            }),
+            _ => Err(format!(
+                "Invalid middle C '{value}'. Expected a C with an octave, e.g. \"C4\" or \"C3\"."
+            )),
+        }
+    }
+}
+
+/// A note name before any octave convention is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteName {
+    /// Semitones above C, 0..=11
+    pitch_class: i16,
+    octave: i16,
+}
+
+impl NoteName {
+    /// Parse a note name such as "C4", "f#2", "Bb-1".
+    ///
+    /// Accidentals: '#' or 's' for sharp, 'b' for flat.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut chars = value.trim().chars().peekable();
+
+        let mut pitch_class: i16 = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+        match chars.peek() {
+            Some('#') | Some('s') => {
+                pitch_class += 1;
+                chars.next();
+            }
+            Some('b') => {
+                pitch_class -= 1;
+                chars.next();
+            }
+            _ => (),
+        }
+
+        let octave: String = chars.collect();
+        if !octave
+            .trim_start_matches('-')
+            .chars()
+            .all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        let mut octave: i16 = octave.parse().ok()?;
+
+        // Cb and B# cross the octave boundary
+        if pitch_class < 0 {
+            pitch_class += 12;
+            octave -= 1;
+        } else if pitch_class > 11 {
+            pitch_class -= 12;
+            octave += 1;
+        }
+
+        Some(Self {
+            pitch_class,
+            octave,
+        })
+    }
+}
+
+impl Display for NoteName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const NAMES: [&str; 12] = [
+            "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+        ];
+        write!(f, "{}{}", NAMES[self.pitch_class as usize], self.octave)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str, middle_c: MiddleC) -> Option<u8> {
+        middle_c.key(NoteName::parse(name)?)
+    }
+
+    #[test]
+    fn test_parse() {
+        let scientific = MiddleC::default();
+        assert_eq!(key("C4", scientific), Some(60));
+        assert_eq!(key("c#4", scientific), Some(61));
+        assert_eq!(key("Db4", scientific), Some(61));
+        assert_eq!(key("Cb4", scientific), Some(59));
+        assert_eq!(key("B#3", scientific), Some(60));
+        assert_eq!(key("C-1", scientific), Some(0));
+        assert_eq!(key("G9", scientific), Some(127));
+        assert_eq!(key("G#9", scientific), None);
+        assert_eq!(key("H4", scientific), None);
+        assert_eq!(key("C", scientific), None);
+        assert_eq!(key("C4x", scientific), None);
+    }
+
+    #[test]
+    fn test_convention() {
+        let yamaha = MiddleC::try_from(String::from("C3")).unwrap();
+        assert_eq!(key("C3", yamaha), Some(60));
+        assert_eq!(key("C-2", yamaha), Some(0));
+        assert!(MiddleC::try_from(String::from("D4")).is_err());
+    }
+
+    #[test]
+    fn test_note() {
+        let scientific = MiddleC::default();
+        assert_eq!(scientific.note(60).to_string(), "C4");
+        assert_eq!(scientific.note(0).to_string(), "C-1");
+        let yamaha = MiddleC::try_from(String::from("C3")).unwrap();
+        assert_eq!(yamaha.note(61).to_string(), "C#3");
+        assert_eq!(String::from(yamaha), "C3");
+        for key in 0..=127 {
+            assert_eq!(yamaha.key(yamaha.note(key)), Some(key));
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(NoteName::parse("Db-1").unwrap().to_string(), "C#-1");
+    }
+}
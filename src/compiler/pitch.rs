@@ -0,0 +1,208 @@
+//! Root keys given by hand, or detected from the audio with the YIN algorithm
+//!
+//! de Cheveigné & Kawahara, "YIN, a fundamental frequency estimator for speech and music", 2002
+
+use serde::{Deserialize, Serialize};
+
+/// Root key of a sample.
+///
+/// Accepted forms: `60`, or `"detect"` to find it from the audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "RawPitch", into = "RawPitch")]
+pub enum PrePitch {
+    Key(u8),
+    Detect,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawPitch {
+    Key(i64),
+    Text(String),
+}
+
+impl From<PrePitch> for RawPitch {
+    fn from(pitch: PrePitch) -> Self {
+        match pitch {
+            PrePitch::Key(key) => RawPitch::Key(key as i64),
+            PrePitch::Detect => RawPitch::Text("detect".into()),
+        }
+    }
+}
+
+impl TryFrom<RawPitch> for PrePitch {
+    type Error = String;
+
+    fn try_from(value: RawPitch) -> Result<Self, Self::Error> {
+        match value {
+            RawPitch::Key(key) => u8::try_from(key)
+                .ok()
+                .filter(|key| *key <= 127)
+                .map(Self::Key)
+                .ok_or_else(|| format!("Invalid original pitch {key}. Expected 0..=127.")),
+            RawPitch::Text(text) if text == "detect" => Ok(Self::Detect),
+            RawPitch::Text(text) => Err(format!(
+                "Invalid original pitch '{text}'. Expected a MIDI key number, or \"detect\"."
+            )),
+        }
+    }
+}
+
+/// Pitch found in a sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetectedPitch {
+    /// Fundamental frequency in Hz
+    pub frequency: f64,
+    /// Nearest MIDI key
+    pub key: u8,
+    /// Cents to apply on playback to land on the key, i.e. opposite to how far off it is
+    pub correction: i8,
+    /// 0..=1. How periodic the audio is at that frequency.
+    pub confidence: f32,
+}
+
+impl DetectedPitch {
+    /// Confidence below this is worth a warning
+    pub const LOW_CONFIDENCE: f32 = 0.8;
+}
+
+/// Lowest detectable frequency, a bit below A0
+const MIN_FREQUENCY: f64 = 20.0;
+/// Highest detectable frequency, a bit above C8
+const MAX_FREQUENCY: f64 = 5000.0;
+/// Dips of the normalized difference below this count as periods
+const THRESHOLD: f64 = 0.15;
+/// Frames analysed across the audio. The median of their results is taken.
+const MAX_FRAMES: usize = 9;
+
+/// Detect the fundamental frequency of mono audio, e.g. a sample's sustain or loop. None if the
+/// audio is too short or silent.
+pub fn detect_pitch(points: &[f32], sample_rate: u32) -> Option<DetectedPitch> {
+    let min_lag = ((sample_rate as f64 / MAX_FREQUENCY) as usize).max(2);
+    // Each frame is a window, and the window shifted by up to the longest lag
+    let max_lag = ((sample_rate as f64 / MIN_FREQUENCY) as usize).min(points.len() / 2);
+    if max_lag < min_lag * 2 {
+        return None;
+    }
+    let window = max_lag;
+    let frame_len = window + max_lag;
+
+    let frame_count = ((points.len() - frame_len) / window + 1).min(MAX_FRAMES);
+    let step = match frame_count {
+        1 => 0,
+        _ => (points.len() - frame_len) / (frame_count - 1),
+    };
+    let mut results: Vec<(f64, f64)> = (0..frame_count)
+        .filter_map(|i| {
+            let frame = &points[i * step..i * step + frame_len];
+            yin(frame, window, min_lag, max_lag)
+        })
+        .collect();
+    if results.is_empty() {
+        return None;
+    }
+
+    // Median period, and its confidence
+    results.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (lag, confidence) = results[results.len() / 2];
+    let frequency = sample_rate as f64 / lag;
+    let midi = 69.0 + 12.0 * (frequency / 440.0).log2();
+    let key = midi.round().clamp(0.0, 127.0);
+    let cents = ((midi - key) * 100.0).round().clamp(-99.0, 99.0);
+    Some(DetectedPitch {
+        frequency,
+        key: key as u8,
+        correction: -cents as i8,
+        confidence: confidence as f32,
+    })
+}
+
+/// Period of one frame in points, and how confident that is. None for silence or DC.
+fn yin(frame: &[f32], window: usize, min_lag: usize, max_lag: usize) -> Option<(f64, f64)> {
+    let energy: f64 = frame[..window].iter().map(|x| (*x as f64).powi(2)).sum();
+    if energy < 1e-9 {
+        return None;
+    }
+
+    // Cumulative mean normalized difference
+    let mut normalized = vec![1.0; max_lag + 1];
+    let mut sum = 0.0;
+    for lag in 1..=max_lag {
+        let difference: f64 = frame[..window]
+            .iter()
+            .zip(&frame[lag..lag + window])
+            .map(|(a, b)| (*a as f64 - *b as f64).powi(2))
+            .sum();
+        sum += difference;
+        if sum > 0.0 {
+            normalized[lag] = difference * lag as f64 / sum;
+        }
+    }
+    if sum == 0.0 {
+        // Constant
+        return None;
+    }
+
+    // The first dip below the threshold, or failing that the deepest one. Then to its bottom.
+    let mut lag = (min_lag..=max_lag)
+        .find(|lag| normalized[*lag] < THRESHOLD)
+        .or_else(|| (min_lag..=max_lag).min_by(|a, b| normalized[*a].total_cmp(&normalized[*b])))?;
+    while lag < max_lag && normalized[lag + 1] < normalized[lag] {
+        lag += 1;
+    }
+
+    // Parabolic interpolation between points
+    let mut refined = lag as f64;
+    if lag > min_lag && lag < max_lag {
+        let (a, b, c) = (normalized[lag - 1], normalized[lag], normalized[lag + 1]);
+        let curvature = a - 2.0 * b + c;
+        if curvature > 0.0 {
+            refined += 0.5 * (a - c) / curvature;
+        }
+    }
+    Some((refined, (1.0 - normalized[lag]).clamp(0.0, 1.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(frequency: f64, harmonics: usize, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let t = i as f64 / 44100.0;
+                (1..=harmonics)
+                    .map(|h| (std::f64::consts::TAU * frequency * h as f64 * t).sin() / h as f64)
+                    .sum::<f64>() as f32
+                    * 0.3
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_pitch() {
+        // C4, 20 cents sharp
+        let frequency = 261.6256 * 2f64.powf(0.2 / 12.0);
+        let pitch = detect_pitch(&tone(frequency, 1, 20000), 44100).unwrap();
+        assert_eq!((pitch.key, pitch.correction), (60, -20));
+        assert!(pitch.confidence > 0.95);
+
+        // A1 with a strong set of harmonics
+        let pitch = detect_pitch(&tone(55.0, 8, 20000), 44100).unwrap();
+        assert_eq!((pitch.key, pitch.correction), (33, 0));
+
+        // Noise has no pitch to speak of
+        let mut state = 1u32;
+        let noise: Vec<f32> = (0..20000)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 16) as f32 / 65536.0 - 0.5
+            })
+            .collect();
+        let pitch = detect_pitch(&noise, 44100).unwrap();
+        assert!(pitch.confidence < DetectedPitch::LOW_CONFIDENCE);
+
+        assert_eq!(detect_pitch(&[0.0; 20000], 44100), None);
+        assert_eq!(detect_pitch(&[0.5; 100], 44100), None);
+    }
+}
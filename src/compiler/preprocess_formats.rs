@@ -1,16 +1,30 @@
 use chrono::Utc;
-use serde::Deserialize;
-use std::{collections::HashMap, path::Path};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use crate::soundfont::{
     info::{InfoList, VersionTag},
-    pdta::SampleHeader,
-    SoundfontError,
+    pdta::{
+        DefaultModulator, GeneralController, Generator, InstrumentGen, InstrumentMod,
+        ModDestination, ModTransform, ModulatorSource, PresetGen, PresetMod, SampleHeader,
+        SourceCurve,
+    },
 };
 
-use super::CompilerError;
+use super::{
+    auto_loop::PreLoop,
+    error::ErrorKind,
+    note::MiddleC,
+    pitch::PrePitch,
+    ranges::PreRange,
+    sample::{PreLoopPoint, SampleSettings},
+    CompilerError,
+};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PreSoundFont {
     //pub soundfont_version: Option<String>
     /// Target sound engine. Defaults to "EMU8000"
@@ -29,9 +43,39 @@ pub struct PreSoundFont {
     pub copyright: Option<String>,
     /// Soundfont comment or description
     pub comments: Option<String>,
+    /// Octave convention of note names, given as the name of middle C. Defaults to "C4"
+    #[serde(default)]
+    pub middle_c: MiddleC,
+    /// Dither 24-bit and float samples down to 16 bits instead of writing sm24
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub force_16_bit: bool,
+    /// Store different sample files with identical audio and settings only once
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub dedupe_by_content: bool,
+
+    /// Output file, relative to the manifest. Defaults to the soundfont name with ".sf2"
+    pub output: Option<PathBuf>,
+    /// Preset directory, relative to the manifest. Defaults to "presets"
+    #[serde(default = "PreSoundFont::default_presets_dir")]
+    pub presets_dir: PathBuf,
+    /// Instrument directory, relative to the manifest. Defaults to "instruments"
+    #[serde(default = "PreSoundFont::default_instruments_dir")]
+    pub instruments_dir: PathBuf,
+    /// Sample directory, relative to the manifest. Defaults to "samples"
+    #[serde(default = "PreSoundFont::default_samples_dir")]
+    pub samples_dir: PathBuf,
+    /// Audio test directory, relative to the manifest. Defaults to "tests"
+    #[serde(default = "PreSoundFont::default_tests_dir")]
+    pub tests_dir: PathBuf,
+    /// Directory of the manifest, which all other paths are relative to
+    #[serde(skip)]
+    pub root: PathBuf,
 
     /// List of preset filepaths, relative to the presets directory
     pub presets: Vec<String>,
+    /// Sample metadata by sample name. Samples can also be described by a sidecar file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub samples: BTreeMap<String, PreSample>,
 }
 
 impl PreSoundFont {
@@ -40,21 +84,99 @@ impl PreSoundFont {
     where
         P: AsRef<Path>,
     {
-        let Ok(contents) = std::fs::read_to_string(filepath) else {
-            return Err(CompilerError::ProjectManifestCantOpen);
-        };
-        let Ok(this) = toml::from_str(&contents) else {
-            return Err(CompilerError::ProjectManifestCantOpen);
+        let path = filepath.as_ref();
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return Err(CompilerError::from(ErrorKind::ProjectManifestCantOpen).in_file(path));
         };
+        let mut this: Self =
+            toml::from_str(&contents).map_err(|err| CompilerError::from(err).in_file(path))?;
+        this.root = filepath
+            .as_ref()
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
         Ok(this)
     }
 
-    pub fn generate_infolist(&self) -> Result<InfoList, SoundfontError> {
+    fn default_presets_dir() -> PathBuf {
+        "presets".into()
+    }
+
+    fn default_instruments_dir() -> PathBuf {
+        "instruments".into()
+    }
+
+    fn default_samples_dir() -> PathBuf {
+        "samples".into()
+    }
+
+    fn default_tests_dir() -> PathBuf {
+        "tests".into()
+    }
+
+    /// Where the compiled soundfont goes
+    pub fn output_path(&self) -> PathBuf {
+        match &self.output {
+            Some(output) => self.root.join(output),
+            None => {
+                let filename = sanitize_filename(&self.name);
+                self.root.join(format!("{filename}.sf2"))
+            }
+        }
+    }
+
+    /// Path of a preset file listed in the manifest
+    pub fn preset_path(&self, filename: &str) -> PathBuf {
+        self.root.join(&self.presets_dir).join(filename)
+    }
+
+    /// Path of an instrument file referenced by a preset zone
+    pub fn instrument_path(&self, filename: &str) -> PathBuf {
+        self.root.join(&self.instruments_dir).join(filename)
+    }
+
+    /// Resolve a zone's sample reference into a WAVE file path, sample name, and metadata.
+    ///
+    /// The reference is looked up from `[samples]` first. Otherwise it's a filename in the
+    /// samples directory, ".wav" optional, and may have a sidecar like `guitar.toml` next to it.
+    pub fn sample(&self, reference: &str) -> Result<(PathBuf, String, PreSample), CompilerError> {
+        let samples_dir = self.root.join(&self.samples_dir);
+        if let Some(sample) = self.samples.get(reference) {
+            let file = sample
+                .file
+                .clone()
+                .unwrap_or_else(|| format!("{reference}.wav"));
+            let name = sample.name.clone().unwrap_or_else(|| reference.into());
+            return Ok((samples_dir.join(file), name, sample.clone()));
+        }
+
+        let mut path = samples_dir.join(reference);
+        if path.extension().is_none() {
+            path.set_extension("wav");
+        }
+        let sample = PreSample::read_sidecar(path.with_extension("toml"))?.unwrap_or_default();
+        let name = match &sample.name {
+            Some(name) => name.clone(),
+            None => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_else(|| reference.into()),
+        };
+        Ok((path, name, sample))
+    }
+
+    pub fn generate_infolist(&self) -> Result<InfoList, CompilerError> {
         let mut info = InfoList::default();
 
         info.set_ifil(VersionTag::new(2, 4));
-        info.set_isng("EMU8000".into())?;
-        info.set_inam(self.name.clone())?;
+        info.set_isng(
+            self.sound_engine
+                .clone()
+                .unwrap_or_else(|| "EMU8000".into()),
+        )
+        .map_err(|err| CompilerError::from(err).at_key(&["sound_engine"]))?;
+        info.set_inam(self.name.clone())
+            .map_err(|err| CompilerError::from(err).at_key(&["name"]))?;
         //info.set_irom()
         //info.set_iver()
         let now = Utc::now();
@@ -75,10 +197,14 @@ impl PreSoundFont {
         //};
         //panic!("{month} {}, {}", now.day(), now.year());
         info.set_icrd(Some(now.to_rfc3339()))?;
-        info.set_ieng(self.authors.clone())?;
-        info.set_iprd(self.target_product.clone())?;
-        info.set_icop(self.copyright.clone())?;
-        info.set_icmt(self.comments.clone())?;
+        info.set_ieng(self.authors.clone())
+            .map_err(|err| CompilerError::from(err).at_key(&["authors"]))?;
+        info.set_iprd(self.target_product.clone())
+            .map_err(|err| CompilerError::from(err).at_key(&["target_product"]))?;
+        info.set_icop(self.copyright.clone())
+            .map_err(|err| CompilerError::from(err).at_key(&["copyright"]))?;
+        info.set_icmt(self.comments.clone())
+            .map_err(|err| CompilerError::from(err).at_key(&["comments"]))?;
         info.set_isft(Some(
             "SoundFont Compiler v0.0.0:SoundFont Compiler v0.0.0".into(),
         ))?;
@@ -87,63 +213,501 @@ impl PreSoundFont {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PrePreset {
     pub name: String,
     pub midi_preset: u16,
     pub midi_bank: u16,
-    pub zones: HashMap<String, PrePresetZone>,
+    pub zones: BTreeMap<String, PrePresetZone>,
 }
 
 impl PrePreset {
-    /// Read from a TOML file
-    pub fn read<P>(path: P) -> Self
+    /// Read from a TOML file. Failing to open it is left for the caller to locate.
+    pub fn read<P>(path: P) -> Result<Self, CompilerError>
     where
         P: AsRef<Path>,
     {
-        let contents = std::fs::read_to_string(path).unwrap();
-        toml::from_str(&contents).unwrap()
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|err| ErrorKind::FileCantOpen {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        })?;
+        toml::from_str(&contents).map_err(|err| CompilerError::from(err).in_file(path))
+    }
+
+    /// The zone without an instrument, if any, and its name. Fails if there's more than one.
+    pub fn global_zone(&self) -> Result<Option<(&String, &PrePresetZone)>, CompilerError> {
+        let mut globals = self
+            .zones
+            .iter()
+            .filter(|(_, zone)| zone.instrument.is_none());
+        let global = globals.next();
+        if globals.next().is_some() {
+            return Err(CompilerError::from(ErrorKind::MultipleGlobalZones {
+                name: self.name.clone(),
+            })
+            .at_key(&["zones"]));
+        }
+        Ok(global)
+    }
+
+    /// An error for each key in a zone that isn't one of its fields
+    pub fn unknown_keys(&self) -> Vec<CompilerError> {
+        unknown_keys(self.zones.iter().map(|(name, zone)| (name, &zone.extra)))
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct PrePresetZone {
-    /// Filename of the instrument this zone uses
-    pub instrument: String,
+    /// Filename of the instrument this zone uses. A zone without one is the global zone, and its
+    /// generators apply to every other zone in the preset.
+    pub instrument: Option<String>,
+    pub key_range: Option<PreRange>,
+    pub vel_range: Option<PreRange>,
+    /// Added on top of the instrument's values
+    #[serde(flatten)]
+    pub generators: PreZoneGenerators,
+    /// Keys that aren't fields of the zone, e.g. misspelled generators
+    #[serde(flatten, skip_serializing)]
+    pub extra: BTreeMap<String, toml::Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modulators: Vec<PreModulator>,
 }
 
-#[derive(Debug, Deserialize)]
+impl PrePresetZone {
+    /// Generators declared in this zone, excluding the instrument id.
+    ///
+    /// keyRange and velRange come first as the spec requires. The caller is expected to append
+    /// instrument last.
+    pub fn generators(&self, middle_c: MiddleC) -> Result<Vec<PresetGen>, CompilerError> {
+        let mut gens = vec![];
+        if let Some(range) = &self.key_range {
+            let amount = range.keys(middle_c);
+            gens.push(
+                amount
+                    .and_then(|amount| Ok(PresetGen::new(Generator::KeyRange, amount)?))
+                    .map_err(|err| err.at_key(&["key_range"]))?,
+            );
+        }
+        if let Some(range) = &self.vel_range {
+            let amount = range.velocities();
+            gens.push(
+                amount
+                    .and_then(|amount| Ok(PresetGen::new(Generator::VelRange, amount)?))
+                    .map_err(|err| err.at_key(&["vel_range"]))?,
+            );
+        }
+        for (generator, value) in self.generators.fields() {
+            if let Some(value) = value {
+                gens.push(
+                    PresetGen::new(generator, generator.amount_from_i16(value))
+                        .map_err(|err| CompilerError::from(err).at_key(&[generator.name()]))?,
+                );
+            }
+        }
+        Ok(gens)
+    }
+
+    pub fn modulators(&self) -> Result<Vec<PresetMod>, CompilerError> {
+        self.modulators
+            .iter()
+            .enumerate()
+            .map(|(i, modulator)| {
+                modulator
+                    .check_link(&self.modulators)
+                    .and_then(|_| modulator.to_preset_mod(self.modulators.len()))
+                    .map_err(|err| err.at_key(&["modulators", &i.to_string()]))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PreInstrument {
     pub name: String,
-    pub zones: HashMap<String, PreInstZone>,
+    #[serde(default)]
+    pub zones: BTreeMap<String, PreInstZone>,
+    /// Generate zones from sample filenames, e.g. "piano/*_{note}_{vel}.wav". See
+    /// [super::auto_map::auto_map].
+    pub auto_map: Option<String>,
+    /// Overrides for every zone. Goes into the global zone.
+    #[serde(default, skip_serializing_if = "PreDefaultModulators::is_empty")]
+    pub default_modulators: PreDefaultModulators,
 }
 
 impl PreInstrument {
-    /// Read from a TOML file
-    pub fn read<P>(path: P) -> Self
+    /// Read from a TOML file. Failing to open it is left for the caller to locate.
+    pub fn read<P>(path: P) -> Result<Self, CompilerError>
     where
         P: AsRef<Path>,
     {
-        let contents = std::fs::read_to_string(path).unwrap();
-        toml::from_str(&contents).unwrap()
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|err| ErrorKind::FileCantOpen {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        })?;
+        toml::from_str(&contents).map_err(|err| CompilerError::from(err).in_file(path))
+    }
+
+    /// The zone without a sample, if any, and its name. Fails if there's more than one.
+    pub fn global_zone(&self) -> Result<Option<(&String, &PreInstZone)>, CompilerError> {
+        let mut globals = self.zones.iter().filter(|(_, zone)| zone.sample.is_none());
+        let global = globals.next();
+        if globals.next().is_some() {
+            return Err(CompilerError::from(ErrorKind::MultipleGlobalZones {
+                name: self.name.clone(),
+            })
+            .at_key(&["zones"]));
+        }
+        Ok(global)
+    }
+
+    /// An error for each key in a zone that isn't one of its fields
+    pub fn unknown_keys(&self) -> Vec<CompilerError> {
+        unknown_keys(self.zones.iter().map(|(name, zone)| (name, &zone.extra)))
+    }
+
+    /// Generators and modulators of the global zone. Instrument-level default modulator
+    /// overrides go first, so that the global zone's own modulators win. Links of the global
+    /// zone's modulators are moved along to match.
+    pub fn global_zone_contents(
+        &self,
+        middle_c: MiddleC,
+    ) -> Result<(Vec<InstrumentGen>, Vec<InstrumentMod>), CompilerError> {
+        let mut gens = vec![];
+        let mut mods = self
+            .default_modulators
+            .modulators()
+            .map_err(|err| err.at_key(&["default_modulators"]))?;
+        if let Some((name, global)) = self.global_zone()? {
+            let located = |err: CompilerError| err.at_key(&["zones", name]);
+            gens.append(&mut global.generators(middle_c).map_err(located)?);
+            let mut global_mods = global.modulators().map_err(located)?;
+            shift_links(&mut global_mods, mods.len());
+            mods.append(&mut global_mods);
+        }
+        Ok((gens, mods))
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct PreInstZone {
-    /// Filename of the sample this voice uses
-    pub sample: String,
+    /// Filename of the sample this voice uses. A zone without one is the global zone, and its
+    /// generators apply to every other zone in the instrument.
+    pub sample: Option<String>,
     // pub start: u32,
     // pub end: u32,
-    // pub startloop: u32,
-    // pub endloop: u32,
-    pub original_pitch: u8,
-    pub pitch_correction: i8,
+    /// Overrides the sample's loop start, through loop offset generators
+    pub startloop: Option<PreLoopPoint>,
+    /// Overrides the sample's loop end, through loop offset generators
+    pub endloop: Option<PreLoopPoint>,
+    /// Overrides the sample's root key, through overridingRootKey. "detect" has the sample's
+    /// root key detected instead.
+    pub original_pitch: Option<PrePitch>,
+    /// Overrides the sample's pitch correction, through fineTune
+    pub pitch_correction: Option<i8>,
     // pub sample_link: u16,
     // pub sample_type: u16, //SFSampleLink enum
-    // Loop Mode
+    // Loop Mode. Defaults to the sample's.
     pub sample_modes: Option<i16>,
+
+    pub key_range: Option<PreRange>,
+    pub vel_range: Option<PreRange>,
+    pub keynum: Option<i16>,
+    pub velocity: Option<i16>,
+    pub exclusive_class: Option<i16>,
+    pub overriding_root_key: Option<i16>,
+    /// Raw sample address offsets, in points. For loops, startloop and endloop are simpler.
+    pub start_addrs_offset: Option<i16>,
+    pub end_addrs_offset: Option<i16>,
+    pub start_addrs_coarse_offset: Option<i16>,
+    pub end_addrs_coarse_offset: Option<i16>,
+    pub startloop_addrs_offset: Option<i16>,
+    pub endloop_addrs_offset: Option<i16>,
+    pub startloop_addrs_coarse_offset: Option<i16>,
+    pub endloop_addrs_coarse_offset: Option<i16>,
+    #[serde(flatten)]
+    pub generators: PreZoneGenerators,
+    /// Keys that aren't fields of the zone, e.g. misspelled generators
+    #[serde(flatten, skip_serializing)]
+    pub extra: BTreeMap<String, toml::Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modulators: Vec<PreModulator>,
+    #[serde(default, skip_serializing_if = "PreDefaultModulators::is_empty")]
+    pub default_modulators: PreDefaultModulators,
+}
+
+impl PreInstZone {
+    /// Generators declared in this zone, excluding the sample id.
     ///
+    /// keyRange and velRange come first as the spec requires. The caller is expected to append
+    /// sampleID last.
+    pub fn generators(&self, middle_c: MiddleC) -> Result<Vec<InstrumentGen>, CompilerError> {
+        let instrument_only = self.instrument_only_fields();
+
+        let mut gens = vec![];
+        if let Some(range) = &self.key_range {
+            let amount = range.keys(middle_c);
+            gens.push(
+                amount
+                    .and_then(|amount| Ok(InstrumentGen::new(Generator::KeyRange, amount)?))
+                    .map_err(|err| err.at_key(&["key_range"]))?,
+            );
+        }
+        if let Some(range) = &self.vel_range {
+            let amount = range.velocities();
+            gens.push(
+                amount
+                    .and_then(|amount| Ok(InstrumentGen::new(Generator::VelRange, amount)?))
+                    .map_err(|err| err.at_key(&["vel_range"]))?,
+            );
+        }
+        for (generator, value) in self.generators.fields().into_iter().chain(instrument_only) {
+            if let Some(value) = value {
+                gens.push(
+                    InstrumentGen::new(generator, generator.amount_from_i16(value))
+                        .map_err(|err| CompilerError::from(err).at_key(&[generator.name()]))?,
+                );
+            }
+        }
+        Ok(gens)
+    }
+
+    /// Fields of generators only allowed in instrument zones, paired with their generator.
+    fn instrument_only_fields(&self) -> [(Generator, Option<i16>); 13] {
+        [
+            (Generator::Keynum, self.keynum),
+            (Generator::Velocity, self.velocity),
+            (Generator::SampleModes, self.sample_modes),
+            (Generator::ExclusiveClass, self.exclusive_class),
+            (Generator::OverridingRootKey, self.overriding_root_key),
+            (Generator::StartAddrsOffset, self.start_addrs_offset),
+            (Generator::EndAddrsOffset, self.end_addrs_offset),
+            (
+                Generator::StartAddrsCoarseOffset,
+                self.start_addrs_coarse_offset,
+            ),
+            (
+                Generator::EndAddrsCoarseOffset,
+                self.end_addrs_coarse_offset,
+            ),
+            (Generator::StartloopAddrsOffset, self.startloop_addrs_offset),
+            (Generator::EndloopAddrsOffset, self.endloop_addrs_offset),
+            (
+                Generator::StartloopAddrsCoarseOffset,
+                self.startloop_addrs_coarse_offset,
+            ),
+            (
+                Generator::EndloopAddrsCoarseOffset,
+                self.endloop_addrs_coarse_offset,
+            ),
+        ]
+    }
+
+    /// Field of a generator, apart from key and velocity ranges and the sample id.
+    pub fn field_mut(&mut self, generator: Generator) -> Option<&mut Option<i16>> {
+        match generator {
+            Generator::Keynum => Some(&mut self.keynum),
+            Generator::Velocity => Some(&mut self.velocity),
+            Generator::SampleModes => Some(&mut self.sample_modes),
+            Generator::ExclusiveClass => Some(&mut self.exclusive_class),
+            Generator::OverridingRootKey => Some(&mut self.overriding_root_key),
+            Generator::StartAddrsOffset => Some(&mut self.start_addrs_offset),
+            Generator::EndAddrsOffset => Some(&mut self.end_addrs_offset),
+            Generator::StartAddrsCoarseOffset => Some(&mut self.start_addrs_coarse_offset),
+            Generator::EndAddrsCoarseOffset => Some(&mut self.end_addrs_coarse_offset),
+            Generator::StartloopAddrsOffset => Some(&mut self.startloop_addrs_offset),
+            Generator::EndloopAddrsOffset => Some(&mut self.endloop_addrs_offset),
+            Generator::StartloopAddrsCoarseOffset => Some(&mut self.startloop_addrs_coarse_offset),
+            Generator::EndloopAddrsCoarseOffset => Some(&mut self.endloop_addrs_coarse_offset),
+            _ => self.generators.field_mut(generator),
+        }
+    }
+
+    /// Default modulator overrides first, so that explicitly declared modulators win. Links
+    /// are moved along past the overrides.
+    pub fn modulators(&self) -> Result<Vec<InstrumentMod>, CompilerError> {
+        let mut mods = self
+            .default_modulators
+            .modulators()
+            .map_err(|err| err.at_key(&["default_modulators"]))?;
+        let mut explicit = vec![];
+        for (i, modulator) in self.modulators.iter().enumerate() {
+            explicit.push(
+                modulator
+                    .check_link(&self.modulators)
+                    .and_then(|_| modulator.to_instrument_mod(self.modulators.len()))
+                    .map_err(|err| err.at_key(&["modulators", &i.to_string()]))?,
+            );
+        }
+        shift_links(&mut explicit, mods.len());
+        mods.append(&mut explicit);
+        Ok(mods)
+    }
+
+    /// Generators for the sample settings this zone overrides. The sample header is shared by
+    /// every zone, so a zone can only differ from it in the ways generators allow.
+    ///
+    /// These replace any of the same generators from [Self::generators].
+    pub fn sample_overrides(
+        &self,
+        header: &SampleHeader,
+        sample: &PreSample,
+    ) -> Result<Vec<InstrumentGen>, CompilerError> {
+        let mut overrides: Vec<(Generator, i16)> = vec![];
+
+        if self.overriding_root_key.is_none() {
+            if let Some(PrePitch::Key(pitch)) = self.original_pitch {
+                if pitch != header.original_pitch {
+                    overrides.push((Generator::OverridingRootKey, pitch as i16));
+                }
+            }
+        }
+        if let Some(correction) = self.pitch_correction {
+            let difference = correction as i16 - header.pitch_correction as i16;
+            if difference != 0 {
+                let fine_tune = self.generators.fine_tune.unwrap_or(0) + difference;
+                overrides.push((Generator::FineTune, fine_tune));
+            }
+        }
+        if self.sample_modes.is_none() {
+            if let Some(modes) = sample.sample_modes {
+                overrides.push((Generator::SampleModes, modes));
+            }
+        }
+
+        // Loop offsets, split into 32768 point coarse steps
+        let mut looped = header.clone();
+        let loop_points = [
+            (
+                self.startloop,
+                &mut looped.startloop,
+                Generator::StartloopAddrsOffset,
+                Generator::StartloopAddrsCoarseOffset,
+            ),
+            (
+                self.endloop,
+                &mut looped.endloop,
+                Generator::EndloopAddrsOffset,
+                Generator::EndloopAddrsCoarseOffset,
+            ),
+        ];
+        for (point, header_point, fine, coarse) in loop_points {
+            let Some(point) = point else {
+                continue;
+            };
+            let frames = point.frames(header.sample_rate);
+            let offset = frames as i64 - *header_point as i64;
+            *header_point = frames;
+            if offset % 32768 != 0 {
+                overrides.push((fine, (offset % 32768) as i16));
+            }
+            if offset / 32768 != 0 {
+                overrides.push((coarse, (offset / 32768) as i16));
+            }
+        }
+        // The header's own loop was checked when the sample was read
+        if self.startloop.is_some() || self.endloop.is_some() {
+            if let Err(source) = looped.validate() {
+                let key = if self.endloop.is_some() {
+                    "endloop"
+                } else {
+                    "startloop"
+                };
+                return Err(CompilerError::from(ErrorKind::InvalidSampleLoop {
+                    name: header.name.clone(),
+                    len: header.end - header.start,
+                    startloop: looped.startloop,
+                    endloop: looped.endloop,
+                    source,
+                })
+                .at_key(&[key]));
+            }
+        }
+
+        let mut gens = vec![];
+        for (generator, value) in overrides {
+            gens.push(InstrumentGen::new(
+                generator,
+                generator.amount_from_i16(value),
+            )?);
+        }
+        Ok(gens)
+    }
+}
+
+/// Sample metadata, declared once for every zone that uses the sample.
+///
+/// Either a `[samples.<name>]` table in the manifest, or a sidecar TOML file next to the WAVE
+/// file with the same stem.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PreSample {
+    /// WAVE file in the samples directory. Defaults to "<name>.wav" in the manifest.
+    pub file: Option<String>,
+    /// Sample name in the soundfont. Defaults to the table key or file stem.
+    pub name: Option<String>,
+    /// MIDI key the sample was recorded at, or "detect" to find it from the audio. Defaults
+    /// to 60
+    pub original_pitch: Option<PrePitch>,
+    /// Pitch correction in cents. Defaults to 0, or the detected correction
+    pub pitch_correction: Option<i8>,
+    /// "auto" finds loop points from the audio, where startloop and endloop don't give them.
+    /// The embedded loop is then ignored.
+    #[serde(rename = "loop")]
+    pub looping: Option<PreLoop>,
+    /// Loop start in frames, or seconds as "0.5s" / "500ms". Defaults to the loop embedded in
+    /// the WAVE file, or 8 points in.
+    pub startloop: Option<PreLoopPoint>,
+    /// Loop end, exclusive. Defaults to the loop embedded in the WAVE file, or 8 points
+    /// before the end.
+    pub endloop: Option<PreLoopPoint>,
+    /// Loop mode of zones using this sample, unless they set their own.
+    pub sample_modes: Option<i16>,
+}
+
+impl PreSample {
+    /// Read a sidecar file. None if there isn't one.
+    pub fn read_sidecar<P>(path: P) -> Result<Option<Self>, CompilerError>
+    where
+        P: AsRef<Path>,
+    {
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return Ok(None);
+        };
+        toml::from_str(&contents)
+            .map(Some)
+            .map_err(|err| CompilerError::from(err).in_file(path.as_ref()))
+    }
+
+    /// Settings for the sample header
+    pub fn settings(&self) -> SampleSettings {
+        SampleSettings {
+            original_pitch: self.original_pitch.unwrap_or(PrePitch::Key(60)),
+            pitch_correction: self.pitch_correction,
+            startloop: self.startloop,
+            endloop: self.endloop,
+            auto_loop: self.looping == Some(PreLoop::Auto),
+        }
+    }
+}
+
+/// Errors for the leftover keys of each zone, located at the key
+fn unknown_keys<'a>(
+    zones: impl Iterator<Item = (&'a String, &'a BTreeMap<String, toml::Value>)>,
+) -> Vec<CompilerError> {
+    zones
+        .flat_map(|(name, extra)| {
+            extra.keys().map(move |key| {
+                CompilerError::from(ErrorKind::UnknownKey { key: key.clone() })
+                    .at_key(&["zones", name, key])
+            })
+        })
+        .collect()
+}
+
+/// Generators allowed in both preset and instrument zones, apart from key and velocity ranges.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PreZoneGenerators {
     pub mod_lfo_to_pitch: Option<i16>,
     pub vib_lfo_to_pitch: Option<i16>,
     pub mod_env_to_pitch: Option<i16>,
@@ -155,6 +719,7 @@ pub struct PreInstZone {
     pub chorus_effects_send: Option<i16>,
     pub reverb_effects_send: Option<i16>,
     pub pan: Option<i16>,
+    pub delay_mod_lfo: Option<i16>,
     pub freq_mod_lfo: Option<i16>,
     pub delay_vib_lfo: Option<i16>,
     pub freq_vib_lfo: Option<i16>,
@@ -165,6 +730,7 @@ pub struct PreInstZone {
     pub sustain_mod_env: Option<i16>,
     pub release_mod_env: Option<i16>,
     pub keynum_to_mod_env_hold: Option<i16>,
+    pub keynum_to_mod_env_decay: Option<i16>,
     pub delay_vol_env: Option<i16>,
     pub attack_vol_env: Option<i16>,
     pub hold_vol_env: Option<i16>,
@@ -173,16 +739,602 @@ pub struct PreInstZone {
     pub release_vol_env: Option<i16>,
     pub keynum_to_vol_env_hold: Option<i16>,
     pub keynum_to_vol_env_decay: Option<i16>,
-    pub key_range: Option<i16>,
-    pub vel_range: Option<i16>,
-    pub keynum: Option<i16>,
-    pub velocity: Option<i16>,
     pub initial_attenuation: Option<i16>,
     pub coarse_tune: Option<i16>,
     pub fine_tune: Option<i16>,
     pub scale_tuning: Option<i16>,
-    pub exclusive_class: Option<i16>,
-    pub overriding_root_key: Option<i16>,
 }
 
-impl PreInstZone {}
+impl PreZoneGenerators {
+    /// All fields paired with their generator, in operator order.
+    pub fn fields(&self) -> [(Generator, Option<i16>); 35] {
+        [
+            (Generator::ModLfoToPitch, self.mod_lfo_to_pitch),
+            (Generator::VibLfoToPitch, self.vib_lfo_to_pitch),
+            (Generator::ModEnvToPitch, self.mod_env_to_pitch),
+            (Generator::InitialFilterFc, self.initial_filter_fc),
+            (Generator::InitialFilterQ, self.initial_filter_q),
+            (Generator::ModLfoToFilterFc, self.mod_lfo_to_filter_fc),
+            (Generator::ModEnvToFilterFc, self.mod_env_to_filter_fc),
+            (Generator::ModLfoToVolume, self.mod_lfo_to_volume),
+            (Generator::ChorusEffectsSend, self.chorus_effects_send),
+            (Generator::ReverbEffectsSend, self.reverb_effects_send),
+            (Generator::Pan, self.pan),
+            (Generator::DelayModLfo, self.delay_mod_lfo),
+            (Generator::FreqModLfo, self.freq_mod_lfo),
+            (Generator::DelayVibLfo, self.delay_vib_lfo),
+            (Generator::FreqVibLfo, self.freq_vib_lfo),
+            (Generator::DelayModEnv, self.delay_mod_env),
+            (Generator::AttackModEnv, self.attack_mod_env),
+            (Generator::HoldModEnv, self.hold_mod_env),
+            (Generator::DecayModEnv, self.decay_mod_env),
+            (Generator::SustainModEnv, self.sustain_mod_env),
+            (Generator::ReleaseModEnv, self.release_mod_env),
+            (Generator::KeynumToModEnvHold, self.keynum_to_mod_env_hold),
+            (Generator::KeynumToModEnvDecay, self.keynum_to_mod_env_decay),
+            (Generator::DelayVolEnv, self.delay_vol_env),
+            (Generator::AttackVolEnv, self.attack_vol_env),
+            (Generator::HoldVolEnv, self.hold_vol_env),
+            (Generator::DecayVolEnv, self.decay_vol_env),
+            (Generator::SustainVolEnv, self.sustain_vol_env),
+            (Generator::ReleaseVolEnv, self.release_vol_env),
+            (Generator::KeynumToVolEnvHold, self.keynum_to_vol_env_hold),
+            (Generator::KeynumToVolEnvDecay, self.keynum_to_vol_env_decay),
+            (Generator::InitialAttenuation, self.initial_attenuation),
+            (Generator::CoarseTune, self.coarse_tune),
+            (Generator::FineTune, self.fine_tune),
+            (Generator::ScaleTuning, self.scale_tuning),
+        ]
+    }
+
+    /// Field of a generator. None if the generator isn't one of these.
+    pub fn field_mut(&mut self, generator: Generator) -> Option<&mut Option<i16>> {
+        match generator {
+            Generator::ModLfoToPitch => Some(&mut self.mod_lfo_to_pitch),
+            Generator::VibLfoToPitch => Some(&mut self.vib_lfo_to_pitch),
+            Generator::ModEnvToPitch => Some(&mut self.mod_env_to_pitch),
+            Generator::InitialFilterFc => Some(&mut self.initial_filter_fc),
+            Generator::InitialFilterQ => Some(&mut self.initial_filter_q),
+            Generator::ModLfoToFilterFc => Some(&mut self.mod_lfo_to_filter_fc),
+            Generator::ModEnvToFilterFc => Some(&mut self.mod_env_to_filter_fc),
+            Generator::ModLfoToVolume => Some(&mut self.mod_lfo_to_volume),
+            Generator::ChorusEffectsSend => Some(&mut self.chorus_effects_send),
+            Generator::ReverbEffectsSend => Some(&mut self.reverb_effects_send),
+            Generator::Pan => Some(&mut self.pan),
+            Generator::DelayModLfo => Some(&mut self.delay_mod_lfo),
+            Generator::FreqModLfo => Some(&mut self.freq_mod_lfo),
+            Generator::DelayVibLfo => Some(&mut self.delay_vib_lfo),
+            Generator::FreqVibLfo => Some(&mut self.freq_vib_lfo),
+            Generator::DelayModEnv => Some(&mut self.delay_mod_env),
+            Generator::AttackModEnv => Some(&mut self.attack_mod_env),
+            Generator::HoldModEnv => Some(&mut self.hold_mod_env),
+            Generator::DecayModEnv => Some(&mut self.decay_mod_env),
+            Generator::SustainModEnv => Some(&mut self.sustain_mod_env),
+            Generator::ReleaseModEnv => Some(&mut self.release_mod_env),
+            Generator::KeynumToModEnvHold => Some(&mut self.keynum_to_mod_env_hold),
+            Generator::KeynumToModEnvDecay => Some(&mut self.keynum_to_mod_env_decay),
+            Generator::DelayVolEnv => Some(&mut self.delay_vol_env),
+            Generator::AttackVolEnv => Some(&mut self.attack_vol_env),
+            Generator::HoldVolEnv => Some(&mut self.hold_vol_env),
+            Generator::DecayVolEnv => Some(&mut self.decay_vol_env),
+            Generator::SustainVolEnv => Some(&mut self.sustain_vol_env),
+            Generator::ReleaseVolEnv => Some(&mut self.release_vol_env),
+            Generator::KeynumToVolEnvHold => Some(&mut self.keynum_to_vol_env_hold),
+            Generator::KeynumToVolEnvDecay => Some(&mut self.keynum_to_vol_env_decay),
+            Generator::InitialAttenuation => Some(&mut self.initial_attenuation),
+            Generator::CoarseTune => Some(&mut self.coarse_tune),
+            Generator::FineTune => Some(&mut self.fine_tune),
+            Generator::ScaleTuning => Some(&mut self.scale_tuning),
+            _ => None,
+        }
+    }
+}
+
+/// A zone modulator.
+///
+/// ```toml
+/// [[zones.lead.modulators]]
+/// source = "cc1"
+/// dest = "vib_lfo_to_pitch"
+/// amount = 50
+/// curve = "concave"
+/// ```
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PreModulator {
+    /// Controller name such as "velocity" or "pitch_wheel", or "ccN" for a MIDI CC
+    pub source: String,
+    /// "linear", "concave", "convex" or "switch". Defaults to linear.
+    pub curve: Option<String>,
+    /// Map the source to -1..1 instead of 0..1
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub bipolar: bool,
+    /// Map the source from max to min
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub negative: bool,
+
+    /// Generator name, or "modulator:N" to feed the Nth modulator of this zone
+    pub dest: String,
+    pub amount: i16,
+
+    /// Controller that scales the amount. The amount is fixed if left out.
+    pub amount_source: Option<String>,
+    pub amount_curve: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub amount_bipolar: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub amount_negative: bool,
+
+    /// "linear" or "absolute". Defaults to linear.
+    pub transform: Option<String>,
+}
+
+impl PreModulator {
+    /// `zone_len` is the number of modulators in the zone, for checking links.
+    pub fn to_instrument_mod(&self, zone_len: usize) -> Result<InstrumentMod, CompilerError> {
+        let sf_mod_src_oper =
+            parse_mod_source(&self.source, &self.curve, self.bipolar, self.negative)
+                .map_err(|err| err.at_key(&["source"]))?;
+        let sf_mod_amt_src_oper = match &self.amount_source {
+            Some(source) => parse_mod_source(
+                source,
+                &self.amount_curve,
+                self.amount_bipolar,
+                self.amount_negative,
+            )
+            .map_err(|err| err.at_key(&["amount_source"]))?,
+            None => ModulatorSource::NONE,
+        };
+        if sf_mod_amt_src_oper.index == GeneralController::Link as u8
+            && !sf_mod_amt_src_oper.midi_cc
+        {
+            return Err(CompilerError::from(ErrorKind::InvalidModulator {
+                reason: "The amount source can't be a link.".into(),
+            })
+            .at_key(&["amount_source"]));
+        }
+
+        let sf_mod_dest_oper = match self.dest.strip_prefix("modulator:") {
+            Some(index) => match index.parse::<u16>() {
+                Ok(index) if (index as usize) < zone_len => ModDestination::Link(index),
+                _ => {
+                    return Err(CompilerError::from(ErrorKind::InvalidModulator {
+                        reason: format!(
+                            "'{}' doesn't point to a modulator in this zone. Indices start from 0.",
+                            self.dest
+                        ),
+                    })
+                    .at_key(&["dest"]))
+                }
+            },
+            None => match Generator::from_name(&self.dest) {
+                Some(generator) if is_modulatable(generator) => {
+                    ModDestination::Generator(generator)
+                }
+                _ => {
+                    return Err(CompilerError::from(ErrorKind::InvalidModulator {
+                        reason: format!(
+                            "'{}' is not a generator that can be modulated.",
+                            self.dest
+                        ),
+                    })
+                    .at_key(&["dest"]))
+                }
+            },
+        };
+
+        let sf_mod_trans_oper = match &self.transform {
+            Some(name) => ModTransform::from_name(name).ok_or_else(|| {
+                CompilerError::from(ErrorKind::InvalidModulator {
+                    reason: format!(
+                        "Unknown transform '{name}'. Expected \"linear\" or \"absolute\"."
+                    ),
+                })
+                .at_key(&["transform"])
+            })?,
+            None => ModTransform::Linear,
+        };
+
+        Ok(InstrumentMod {
+            sf_mod_src_oper,
+            sf_mod_dest_oper,
+            mod_amount: self.amount,
+            sf_mod_amt_src_oper,
+            sf_mod_trans_oper,
+        })
+    }
+
+    /// A link must feed a modulator of the zone whose source is "link". Other destinations are
+    /// checked by [Self::to_instrument_mod].
+    pub fn check_link(&self, zone: &[PreModulator]) -> Result<(), CompilerError> {
+        let Some(index) = self.dest.strip_prefix("modulator:") else {
+            return Ok(());
+        };
+        let Some(target) = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| zone.get(index))
+        else {
+            // Reported by to_instrument_mod
+            return Ok(());
+        };
+        if target.source != GeneralController::Link.name() {
+            return Err(CompilerError::from(ErrorKind::InvalidModulator {
+                reason: format!(
+                    "'{}' has source '{}'. A modulator fed by another needs source = \"link\".",
+                    self.dest, target.source
+                ),
+            })
+            .at_key(&["dest"]));
+        }
+        Ok(())
+    }
+
+    /// `zone_len` is the number of modulators in the zone, for checking links.
+    pub fn to_preset_mod(&self, zone_len: usize) -> Result<PresetMod, CompilerError> {
+        let modulator = self.to_instrument_mod(zone_len)?;
+        if let ModDestination::Generator(generator) = modulator.sf_mod_dest_oper {
+            if !generator.allowed_in_preset() {
+                return Err(CompilerError::from(ErrorKind::InvalidModulator {
+                    reason: format!("'{generator}' can't be modulated in preset zones."),
+                })
+                .at_key(&["dest"]));
+            }
+        }
+        Ok(PresetMod {
+            sf_mod_src_oper: modulator.sf_mod_src_oper,
+            sf_mod_dest_oper: modulator.sf_mod_dest_oper,
+            mod_amount: modulator.mod_amount,
+            sf_mod_amt_src_oper: modulator.sf_mod_amt_src_oper,
+            sf_mod_trans_oper: modulator.sf_mod_trans_oper,
+        })
+    }
+}
+
+/// Move links to other modulators along by `by` records, for modulators placed after others in
+/// their zone.
+fn shift_links(mods: &mut [InstrumentMod], by: usize) {
+    for modulator in mods {
+        if let ModDestination::Link(index) = &mut modulator.sf_mod_dest_oper {
+            *index += by as u16;
+        }
+    }
+}
+
+/// Ranges, indices and unused generators can't be modulated.
+fn is_modulatable(generator: Generator) -> bool {
+    generator.allowed_in_instrument()
+        && !matches!(
+            generator,
+            Generator::KeyRange | Generator::VelRange | Generator::SampleId
+        )
+}
+
+fn parse_mod_source(
+    name: &str,
+    curve: &Option<String>,
+    bipolar: bool,
+    negative: bool,
+) -> Result<ModulatorSource, CompilerError> {
+    let mut source = match name.strip_prefix("cc") {
+        Some(number) => {
+            let number = number.parse::<u8>().ok().filter(|number| *number <= 127);
+            let Some(number) = number else {
+                return Err(ErrorKind::InvalidModulator {
+                    reason: format!("'{name}' is not a valid CC. Expected cc0 to cc127."),
+                }
+                .into());
+            };
+            ModulatorSource::cc(number)
+        }
+        None => match GeneralController::from_name(name) {
+            Some(controller) => ModulatorSource::general(controller),
+            None => {
+                return Err(ErrorKind::InvalidModulator {
+                    reason: format!("Unknown modulator source '{name}'."),
+                }
+                .into())
+            }
+        },
+    };
+    if !source.is_legal() {
+        return Err(ErrorKind::InvalidModulator {
+            reason: format!("'{name}' is not allowed as a modulator source."),
+        }
+        .into());
+    }
+
+    if let Some(curve) = curve {
+        source.curve = SourceCurve::from_name(curve).ok_or_else(|| {
+            CompilerError::from(ErrorKind::InvalidModulator {
+                reason: format!(
+                    "Unknown curve '{curve}'. Expected \"linear\", \"concave\", \"convex\" or \"switch\"."
+                ),
+            })
+        })?;
+    }
+    source.bipolar = bipolar;
+    source.negative = negative;
+    Ok(source)
+}
+
+/// Overrides for the spec's default modulators, keyed by [DefaultModulator] name.
+///
+/// ```toml
+/// [default_modulators]
+/// velocity_to_filter_fc = false # Disable
+/// velocity_to_attenuation = 480 # Replace amount
+/// ```
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PreDefaultModulators(BTreeMap<String, PreDefaultModulator>);
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum PreDefaultModulator {
+    /// true keeps the default, false disables it
+    Enabled(bool),
+    Amount(i16),
+}
+
+impl PreDefaultModulators {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Overriding modulator records
+    pub fn modulators(&self) -> Result<Vec<InstrumentMod>, CompilerError> {
+        let mut mods = vec![];
+        for (name, value) in &self.0 {
+            let Some(default) = DefaultModulator::from_name(name) else {
+                let known: Vec<&str> = DefaultModulator::ALL.iter().map(|m| m.name()).collect();
+                return Err(CompilerError::from(ErrorKind::InvalidModulator {
+                    reason: format!(
+                        "Unknown default modulator '{name}'. Expected one of: {}",
+                        known.join(", ")
+                    ),
+                })
+                .at_key(&[name]));
+            };
+            let mod_amount = match value {
+                PreDefaultModulator::Enabled(true) => continue,
+                PreDefaultModulator::Enabled(false) => 0,
+                PreDefaultModulator::Amount(amount) => *amount,
+            };
+            mods.push(InstrumentMod {
+                mod_amount,
+                ..default.modulator()
+            });
+        }
+        Ok(mods)
+    }
+}
+
+/// Keep the name, but nothing that would make it a path
+pub fn sanitize_filename(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{sample::WavSamples, test_project::TempProject};
+    use super::*;
+
+    #[test]
+    fn test_generators() {
+        let zone: PreInstZone = toml::from_str(
+            "sample = \"tone\"\noriginal_pitch = 60\npitch_correction = 0\n\
+             initial_filter_fc = 5000\npan = -250\nexclusive_class = 3\n\
+             vel_range = [10, 100]\nkey_range = [20, 90]\n",
+        )
+        .unwrap();
+
+        // Key and velocity ranges come first, as the spec requires
+        let gens: Vec<(Generator, [u8; 2])> = zone
+            .generators(MiddleC::default())
+            .unwrap()
+            .iter()
+            .map(|gen| (gen.sf_gen_oper, gen.gen_amount.to_bytes()))
+            .collect();
+        assert_eq!(
+            gens,
+            [
+                (Generator::KeyRange, [20, 90]),
+                (Generator::VelRange, [10, 100]),
+                (Generator::InitialFilterFc, 5000_i16.to_le_bytes()),
+                (Generator::Pan, (-250_i16).to_le_bytes()),
+                (Generator::ExclusiveClass, [3, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_global_zone() {
+        let inst: PreInstrument = toml::from_str(
+            "name = \"Inst\"\n\
+             [zones.a]\nsample = \"tone\"\n\
+             [zones.shared]\nrelease_vol_env = 1200\n",
+        )
+        .unwrap();
+        let (name, global) = inst.global_zone().unwrap().unwrap();
+        assert_eq!(name, "shared");
+        assert_eq!(global.generators.release_vol_env, Some(1200));
+
+        let preset: PrePreset = toml::from_str(
+            "name = \"Preset\"\nmidi_preset = 0\nmidi_bank = 0\n\
+             [zones.a]\ninstrument = \"inst.toml\"\n",
+        )
+        .unwrap();
+        assert!(preset.global_zone().unwrap().is_none());
+
+        let preset: PrePreset = toml::from_str(
+            "name = \"Preset\"\nmidi_preset = 0\nmidi_bank = 0\n\
+             [zones.a]\npan = 100\n[zones.b]\npan = -100\n",
+        )
+        .unwrap();
+        let err = preset.global_zone().unwrap_err();
+        assert!(matches!(*err.kind, ErrorKind::MultipleGlobalZones { .. }));
+    }
+
+    #[test]
+    fn test_global_zone_contents() {
+        let inst: PreInstrument = toml::from_str(
+            "name = \"Inst\"\n\
+             [default_modulators]\nvelocity_to_filter_fc = false\n\
+             [zones.a]\nsample = \"tone\"\n\
+             [zones.shared]\nrelease_vol_env = 1200\n\
+             [[zones.shared.modulators]]\nsource = \"cc1\"\ndest = \"vib_lfo_to_pitch\"\namount = 50\n",
+        )
+        .unwrap();
+        let (gens, mods) = inst.global_zone_contents(MiddleC::default()).unwrap();
+        assert_eq!(gens.len(), 1);
+        assert_eq!(gens[0].sf_gen_oper, Generator::ReleaseVolEnv);
+
+        // The default modulator override goes before the zone's own modulator
+        let dests: Vec<(ModDestination, i16)> = mods
+            .iter()
+            .map(|m| (m.sf_mod_dest_oper, m.mod_amount))
+            .collect();
+        assert_eq!(
+            dests,
+            [
+                (ModDestination::Generator(Generator::InitialFilterFc), 0),
+                (ModDestination::Generator(Generator::VibLfoToPitch), 50),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sample_metadata() {
+        let project = TempProject::new("sample-metadata");
+        project
+            .wav("samples/other.wav", 1, 16, 1000)
+            .file("samples/tone.toml", "original_pitch = 70\n")
+            .file(
+                "samples/other.toml",
+                "name = \"Sidecar\"\noriginal_pitch = 70\n",
+            );
+        let mut manifest: PreSoundFont = toml::from_str(
+            "name = \"Test\"\npresets = []\n\
+             [samples.tone]\nname = \"Manifest\"\noriginal_pitch = 50\n",
+        )
+        .unwrap();
+        manifest.root = project.root.clone();
+
+        // The manifest's entry wins over a sidecar
+        let (path, name, sample) = manifest.sample("tone").unwrap();
+        assert_eq!(path, project.root.join("samples/tone.wav"));
+        assert_eq!(
+            (name.as_str(), sample.original_pitch),
+            ("Manifest", Some(PrePitch::Key(50)))
+        );
+        let (path, name, sample) = manifest.sample("other").unwrap();
+        assert_eq!(path, project.root.join("samples/other.wav"));
+        assert_eq!(
+            (name.as_str(), sample.original_pitch),
+            ("Sidecar", Some(PrePitch::Key(70)))
+        );
+
+        // A zone overrides the header through a generator
+        let (WavSamples::Mono(wav), _) = WavSamples::read(path, &name, &sample.settings()).unwrap()
+        else {
+            panic!("expected a mono sample");
+        };
+        assert_eq!(wav.header.original_pitch, 70);
+        let zone: PreInstZone =
+            toml::from_str("sample = \"other\"\noriginal_pitch = 40\n").unwrap();
+        let gens = zone.sample_overrides(&wav.header, &sample).unwrap();
+        assert_eq!(gens.len(), 1);
+        assert_eq!(gens[0].sf_gen_oper, Generator::OverridingRootKey);
+        assert_eq!(gens[0].gen_amount.to_bytes(), 40_i16.to_le_bytes());
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let inst: PreInstrument = toml::from_str(
+            "name = \"Inst\"\n\
+             [zones.a]\nsample = \"tone\"\ninitial_filter_fq = 100\ncorse_tune = 1\n",
+        )
+        .unwrap();
+        let preset: PrePreset = toml::from_str(
+            "name = \"Preset\"\nmidi_preset = 0\nmidi_bank = 0\n\
+             [zones.main]\ninstrument = \"inst.toml\"\nreverb_send = 200\n",
+        )
+        .unwrap();
+
+        let keys = |errors: Vec<CompilerError>| -> Vec<Vec<String>> {
+            errors.into_iter().map(|err| err.key).collect()
+        };
+        assert_eq!(
+            keys(inst.unknown_keys()),
+            [
+                ["zones", "a", "corse_tune"],
+                ["zones", "a", "initial_filter_fq"],
+            ]
+        );
+        assert_eq!(
+            keys(preset.unknown_keys()),
+            [["zones", "main", "reverb_send"]]
+        );
+    }
+
+    #[test]
+    fn test_modulator_links() {
+        let zone: PreInstZone = toml::from_str(
+            r#"
+            sample = "pop"
+            default_modulators = { velocity_to_filter_fc = false }
+
+            [[modulators]]
+            source = "cc2"
+            dest = "modulator:1"
+            amount = 100
+
+            [[modulators]]
+            source = "link"
+            dest = "pan"
+            amount = 500
+            "#,
+        )
+        .unwrap();
+        let mods = zone.modulators().unwrap();
+        assert_eq!(mods.len(), 3);
+        assert_eq!(
+            mods[0].sf_mod_dest_oper,
+            DefaultModulator::VelocityToFilterFc
+                .modulator()
+                .sf_mod_dest_oper
+        );
+        // Past the override, onto the modulator fed by the link
+        assert_eq!(mods[1].sf_mod_dest_oper, ModDestination::Link(2));
+        assert_eq!(
+            mods[2].sf_mod_dest_oper,
+            ModDestination::Generator(Generator::Pan)
+        );
+
+        let zone: PreInstZone = toml::from_str(
+            r#"
+            [[modulators]]
+            source = "cc2"
+            dest = "modulator:1"
+            amount = 100
+
+            [[modulators]]
+            source = "velocity"
+            dest = "pan"
+            amount = 500
+            "#,
+        )
+        .unwrap();
+        let err = zone.modulators().unwrap_err();
+        assert_eq!(err.key, ["modulators", "0", "dest"]);
+    }
+}
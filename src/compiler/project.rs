@@ -0,0 +1,418 @@
+//! Reading a whole project into [FontData], collecting every error on the way
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    auto_map::auto_map,
+    pitch::PrePitch,
+    preprocess_formats::{
+        PreInstZone, PreInstrument, PrePreset, PrePresetZone, PreSample, PreSoundFont,
+    },
+    sample::{SamplePool, Voice},
+    CompilerError, ErrorKind, FontData,
+};
+use crate::soundfont::{
+    info::validate_string,
+    pdta::{
+        GenAmountType, Generator, InstZone, Instrument, InstrumentGen, PresetGen, PresetHeader,
+        PresetZone,
+    },
+};
+
+pub struct ProjectReader<'a> {
+    project: &'a PreSoundFont,
+    manifest_path: &'a Path,
+    font: FontData,
+    samples: SamplePool,
+    /// Instrument index by canonical path. None if the instrument failed.
+    inst_indices: HashMap<PathBuf, Option<u16>>,
+    errors: Vec<CompilerError>,
+}
+
+impl<'a> ProjectReader<'a> {
+    pub fn new(project: &'a PreSoundFont, manifest_path: &'a Path, font: FontData) -> Self {
+        Self {
+            project,
+            manifest_path,
+            font,
+            samples: SamplePool::new(project.dedupe_by_content),
+            inst_indices: HashMap::new(),
+            errors: vec![],
+        }
+    }
+
+    /// Read every preset, and everything they use. Fails with all errors found.
+    pub fn read(mut self) -> Result<FontData, Vec<CompilerError>> {
+        for (i, filename) in self.project.presets.iter().enumerate() {
+            if let Err(err) = self.read_preset(filename) {
+                let err = err.at_key(&["presets", &i.to_string()]);
+                self.errors.push(err.in_file(self.manifest_path));
+            }
+        }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        self.font.samples = self.samples.samples;
+        self.font.detected_pitches = self.samples.detected_pitches;
+        Ok(self.font)
+    }
+
+    /// Errors within the preset file are collected. Only failing to read it is returned.
+    fn read_preset(&mut self, filename: &str) -> Result<(), CompilerError> {
+        let path = self.project.preset_path(filename);
+        let preset = PrePreset::read(&path)?;
+        let middle_c = self.project.middle_c;
+        for err in preset.unknown_keys() {
+            self.errors.push(err.in_file(&path));
+        }
+
+        if let Err(err) = validate_string(&preset.name, 20) {
+            let err = CompilerError::from(err).at_key(&["name"]);
+            self.errors.push(err.in_file(&path));
+        }
+
+        let pbag_idx = self.font.preset_zones.len() as u16;
+
+        // The global zone goes first, and has no terminal instrument generator.
+        let global = preset.global_zone().and_then(|global| {
+            let Some((name, zone)) = global else {
+                return Ok(None);
+            };
+            let located = |err: CompilerError| err.at_key(&["zones", name]);
+            let gens = zone.generators(middle_c).map_err(located)?;
+            let mods = zone.modulators().map_err(located)?;
+            Ok(Some((gens, mods)))
+        });
+        match global {
+            Ok(Some((mut gens, mut mods))) if !gens.is_empty() || !mods.is_empty() => {
+                self.font.preset_zones.push(PresetZone::new(
+                    self.font.preset_gens.len() as u16,
+                    self.font.preset_mods.len() as u16,
+                ));
+                self.font.preset_gens.append(&mut gens);
+                self.font.preset_mods.append(&mut mods);
+            }
+            Ok(_) => (),
+            Err(err) => self.errors.push(err.in_file(&path)),
+        }
+
+        for (zone_name, zone) in &preset.zones {
+            let Some(inst_filename) = &zone.instrument else {
+                // Global zone, handled above
+                continue;
+            };
+            if let Err(err) = self.read_preset_zone(zone, inst_filename) {
+                let err = err.at_key(&["zones", zone_name]);
+                self.errors.push(err.in_file(&path));
+            }
+        }
+
+        self.font.preset_headers.push(PresetHeader {
+            name: preset.name.clone(),
+            raw_name: None,
+            preset: preset.midi_preset,
+            bank: preset.midi_bank,
+            pbag_idx,
+            library: 0,
+            genre: 0,
+            morphology: 0,
+        });
+        Ok(())
+    }
+
+    fn read_preset_zone(
+        &mut self,
+        zone: &PrePresetZone,
+        inst_filename: &str,
+    ) -> Result<(), CompilerError> {
+        let mut gens = zone.generators(self.project.middle_c)?;
+        let mut mods = zone.modulators()?;
+        let inst_idx = self
+            .read_instrument(inst_filename)
+            .map_err(|err| err.at_key(&["instrument"]))?;
+        let Some(inst_idx) = inst_idx else {
+            // The instrument failed, and its errors are already collected.
+            return Ok(());
+        };
+
+        self.font.preset_zones.push(PresetZone::new(
+            self.font.preset_gens.len() as u16,
+            self.font.preset_mods.len() as u16,
+        ));
+        self.font.preset_gens.append(&mut gens);
+        self.font.preset_mods.append(&mut mods);
+        self.font.preset_gens.push(PresetGen::new(
+            Generator::Instrument,
+            GenAmountType::Unsigned(inst_idx),
+        )?);
+        Ok(())
+    }
+
+    /// Each instrument file is compiled once, and shared by every zone using it.
+    ///
+    /// Errors within the instrument file are collected. Only failing to read it is returned.
+    fn read_instrument(&mut self, filename: &str) -> Result<Option<u16>, CompilerError> {
+        let path = self.project.instrument_path(filename);
+        let key = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if let Some(idx) = self.inst_indices.get(&key) {
+            return Ok(*idx);
+        }
+        // Don't try again, or report the same errors again
+        self.inst_indices.insert(key.clone(), None);
+
+        let inst = PreInstrument::read(&path)?;
+        let error_count = self.errors.len();
+        let middle_c = self.project.middle_c;
+
+        for err in inst.unknown_keys() {
+            self.errors.push(err.in_file(&path));
+        }
+        if let Err(err) = validate_string(&inst.name, 20) {
+            let err = CompilerError::from(err).at_key(&["name"]);
+            self.errors.push(err.in_file(&path));
+        }
+
+        let inst_bag_idx = self.font.inst_zones.len() as u16;
+
+        // The global zone goes first, and has no terminal sample generator.
+        match inst.global_zone_contents(middle_c) {
+            Ok((mut gens, mut mods)) => {
+                if !gens.is_empty() || !mods.is_empty() {
+                    self.font.inst_zones.push(InstZone::new(
+                        self.font.inst_gens.len() as u16,
+                        self.font.inst_mods.len() as u16,
+                    ));
+                    self.font.inst_gens.append(&mut gens);
+                    self.font.inst_mods.append(&mut mods);
+                }
+            }
+            Err(err) => self.errors.push(err.in_file(&path)),
+        }
+
+        for (zone_name, zone) in &inst.zones {
+            let Some(sample_ref) = &zone.sample else {
+                // Global zone, handled above
+                continue;
+            };
+            if let Err(err) = self.read_inst_zone(zone, sample_ref, None) {
+                let err = err.at_key(&["zones", zone_name]);
+                self.errors.push(err.in_file(&path));
+            }
+        }
+
+        if let Some(pattern) = &inst.auto_map {
+            let samples_dir = self.project.root.join(&self.project.samples_dir);
+            match auto_map(&samples_dir, pattern, middle_c) {
+                Ok(mapped) => {
+                    for sample in mapped {
+                        let zone = sample.zone();
+                        if let Err(err) =
+                            self.read_inst_zone(&zone, &sample.reference, Some(sample.root))
+                        {
+                            // There's no zone table to point at, so name the file instead
+                            let err = match err.path {
+                                Some(_) => err,
+                                None => CompilerError::from(ErrorKind::AutoMappedZone {
+                                    file: sample.reference.clone(),
+                                    source: err.kind,
+                                })
+                                .at_key(&["auto_map"]),
+                            };
+                            self.errors.push(err.in_file(&path));
+                        }
+                    }
+                }
+                Err(err) => self.errors.push(err.at_key(&["auto_map"]).in_file(&path)),
+            }
+        }
+
+        if self.errors.len() != error_count {
+            return Ok(None);
+        }
+
+        self.font.inst_headers.push(Instrument {
+            name: inst.name.clone(),
+            raw_name: None,
+            inst_bag_idx,
+        });
+        let idx = self.font.inst_headers.len() as u16 - 1;
+        self.inst_indices.insert(key, Some(idx));
+        Ok(Some(idx))
+    }
+
+    /// `root` is the sample's root key, unless its metadata says otherwise.
+    fn read_inst_zone(
+        &mut self,
+        zone: &PreInstZone,
+        sample_ref: &str,
+        root: Option<u8>,
+    ) -> Result<(), CompilerError> {
+        let (path, name, mut sample) = self
+            .project
+            .sample(sample_ref)
+            .map_err(|err| err.at_key(&["sample"]))?;
+        sample.original_pitch = match zone.original_pitch {
+            Some(PrePitch::Detect) => Some(PrePitch::Detect),
+            _ => sample.original_pitch.or(root.map(PrePitch::Key)),
+        };
+        let voices = match self.samples.insert(path.clone(), &name, &sample.settings()) {
+            Ok(voices) => voices,
+            Err(err) => return Err(self.sample_error(err, sample_ref, &path, &sample)),
+        };
+        let header = &self.samples.samples[voices[0].sample_idx as usize].header;
+        let overrides = zone.sample_overrides(header, &sample)?;
+
+        let mut gens = zone.generators(self.project.middle_c)?;
+        for gen in &overrides {
+            gens.retain(|other| other.sf_gen_oper != gen.sf_gen_oper);
+        }
+        gens.extend(overrides);
+        let mods = zone.modulators()?;
+
+        for Voice { sample_idx, pan } in voices {
+            self.font.inst_zones.push(InstZone::new(
+                self.font.inst_gens.len() as u16,
+                self.font.inst_mods.len() as u16,
+            ));
+
+            let mut gens = gens.clone();
+            if let Some(pan) = pan {
+                gens.retain(|gen| gen.sf_gen_oper != Generator::Pan);
+                gens.push(InstrumentGen::new(
+                    Generator::Pan,
+                    GenAmountType::Signed(pan),
+                )?);
+            }
+            self.font.inst_gens.append(&mut gens);
+            self.font.inst_mods.extend(mods.iter().cloned());
+            self.font.inst_gens.push(InstrumentGen::new(
+                Generator::SampleId,
+                GenAmountType::Unsigned(sample_idx),
+            )?);
+        }
+        Ok(())
+    }
+
+    /// A loop that doesn't fit the sample is an error in the sample's metadata, where the loop
+    /// points come from, not in the zone that uses it.
+    fn sample_error(
+        &self,
+        err: CompilerError,
+        reference: &str,
+        path: &Path,
+        sample: &PreSample,
+    ) -> CompilerError {
+        if !matches!(*err.kind, ErrorKind::InvalidSampleLoop { .. }) {
+            return err.at_key(&["sample"]);
+        }
+        let key = if sample.endloop.is_some() {
+            "endloop"
+        } else if sample.startloop.is_some() {
+            "startloop"
+        } else if sample.looping.is_some() {
+            "loop"
+        } else {
+            // The loop embedded in the WAVE file
+            return err.in_file(path);
+        };
+        if self.project.samples.contains_key(reference) {
+            err.at_key(&["samples", reference, key])
+                .in_file(self.manifest_path)
+        } else {
+            err.at_key(&[key]).in_file(path.with_extension("toml"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::super::{test_project::TempProject, ErrorKind};
+
+    #[test]
+    fn test_shared_sample_settings() {
+        // Only the second zone detects the root key of the 220 Hz tone, A3
+        let project = TempProject::with_instrument(
+            "shared-sample",
+            "name = \"Inst\"\n\
+             [zones.a]\nsample = \"tone\"\nkey_range = [0, 59]\n\
+             [zones.b]\nsample = \"tone\"\nkey_range = [60, 127]\noriginal_pitch = \"detect\"\n",
+        );
+        project.wav("samples/tone.wav", 1, 16, 8000);
+        let font = project.read().unwrap().generate_soundfont();
+
+        let samples = &font.pdta.shdr.contents;
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0].original_pitch, 60);
+        assert_eq!(samples[1].original_pitch, 57);
+        assert_eq!(samples[0].name, samples[1].name);
+    }
+
+    #[test]
+    fn test_auto_map_errors() {
+        // Neither file is a WAVE file
+        let project = TempProject::with_instrument(
+            "auto-map-errors",
+            "name = \"Inst\"\nauto_map = \"p_{note}.wav\"\n",
+        );
+        project
+            .file("samples/p_C4.wav", "not audio")
+            .file("samples/p_C5.WAV", "not audio");
+        let errors = project.read().unwrap_err();
+
+        let keys: Vec<&[String]> = errors.iter().map(|err| &err.key[..]).collect();
+        assert_eq!(keys, [["auto_map"], ["auto_map"]]);
+        let files: Vec<&str> = errors
+            .iter()
+            .map(|err| match &*err.kind {
+                ErrorKind::AutoMappedZone { file, .. } => file.as_str(),
+                kind => panic!("{kind:?}"),
+            })
+            .collect();
+        assert_eq!(files, ["p_C4.wav", "p_C5.WAV"]);
+        assert!(errors[0].to_string().contains("'p_C4.wav'"));
+    }
+
+    #[test]
+    fn test_sample_loop_errors() {
+        // A bad loop from sample metadata is reported there, one from a zone at the zone
+        let project = TempProject::with_instrument(
+            "sample-loop-errors",
+            "name = \"Inst\"\n\
+             [zones.a]\nsample = \"tone\"\nkey_range = [0, 31]\n\
+             [zones.b]\nsample = \"other\"\nkey_range = [32, 63]\n\
+             [zones.c]\nsample = \"fine\"\nkey_range = [64, 95]\nendloop = 20\n\
+             [zones.d]\nsample = \"fine\"\nkey_range = [96, 127]\n",
+        );
+        project
+            .wav("samples/tone.wav", 1, 16, 1000)
+            .wav("samples/other.wav", 1, 16, 1000)
+            .wav("samples/fine.wav", 1, 16, 1000)
+            .file("samples/other.toml", "startloop = 980\n")
+            .file(
+                "SoundFont.toml",
+                "name = \"Test\"\npresets = [\"preset.toml\"]\n\
+                 [samples.tone]\nendloop = 995\n",
+            );
+        let errors = project.read().unwrap_err();
+
+        let locations: Vec<(PathBuf, String)> = errors
+            .into_iter()
+            .map(|err| (err.path.unwrap(), err.key.join(".")))
+            .collect();
+        let inst = project.root.join("instruments/inst.toml");
+        assert_eq!(
+            locations,
+            [
+                (project.manifest(), "samples.tone.endloop".into()),
+                (project.root.join("samples/other.toml"), "startloop".into()),
+                (inst, "zones.c.endloop".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Key and velocity ranges in zone files
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+use super::note::{MiddleC, NoteName};
+use super::{CompilerError, ErrorKind};
+use crate::soundfont::pdta::GenAmountType;
+
+/// One end of a range, before note names are resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeBound {
+    Number(u8),
+    Note(NoteName),
+}
+
+impl RangeBound {
+    fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if let Ok(number) = value.parse::<i64>() {
+            return Self::from_number(number);
+        }
+        NoteName::parse(value)
+            .map(Self::Note)
+            .ok_or_else(|| format!("'{value}' is neither a number nor a note name."))
+    }
+
+    fn from_number(value: i64) -> Result<Self, String> {
+        match u8::try_from(value) {
+            Ok(value) if value <= 127 => Ok(Self::Number(value)),
+            _ => Err(format!("{value} is outside 0..=127.")),
+        }
+    }
+}
+
+impl Display for RangeBound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RangeBound::Number(value) => write!(f, "{value}"),
+            RangeBound::Note(note) => write!(f, "{note}"),
+        }
+    }
+}
+
+/// Inclusive range of keys or velocities.
+///
+/// Accepted forms:
+/// - `"C2..G4"` or `"36..=67"`. Both bounds are always inclusive.
+/// - `[36, 67]` or `["C2", "G4"]`
+/// - `60` or `"C4"` for a single value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "RawRange", into = "RawRange")]
+pub struct PreRange {
+    pub lo: RangeBound,
+    pub hi: RangeBound,
+}
+
+impl PreRange {
+    /// Resolve as a key range. Note names are allowed.
+    pub fn keys(&self, middle_c: MiddleC) -> Result<GenAmountType, CompilerError> {
+        let resolve = |bound: RangeBound| match bound {
+            RangeBound::Number(value) => Some(value),
+            RangeBound::Note(note) => middle_c.key(note),
+        };
+        match (resolve(self.lo), resolve(self.hi)) {
+            (Some(lo), Some(hi)) if lo <= hi => Ok(GenAmountType::Range { lo, hi }),
+            _ => Err(ErrorKind::InvalidRange {
+                range: self.to_string(),
+            }
+            .into()),
+        }
+    }
+
+    /// Resolve as a velocity range. Note names make no sense here.
+    pub fn velocities(&self) -> Result<GenAmountType, CompilerError> {
+        match (self.lo, self.hi) {
+            (RangeBound::Number(lo), RangeBound::Number(hi)) if lo <= hi => {
+                Ok(GenAmountType::Range { lo, hi })
+            }
+            _ => Err(ErrorKind::InvalidRange {
+                range: self.to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+impl Display for PreRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..={}", self.lo, self.hi)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawBound {
+    Number(i64),
+    Text(String),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawRange {
+    Single(i64),
+    Pair([RawBound; 2]),
+    Text(String),
+}
+
+impl From<PreRange> for RawRange {
+    fn from(range: PreRange) -> Self {
+        if range.lo == range.hi {
+            return RawRange::Text(range.lo.to_string());
+        }
+        RawRange::Text(range.to_string())
+    }
+}
+
+impl TryFrom<RawRange> for PreRange {
+    type Error = String;
+
+    fn try_from(value: RawRange) -> Result<Self, Self::Error> {
+        let bound = |raw: RawBound| match raw {
+            RawBound::Number(value) => RangeBound::from_number(value),
+            RawBound::Text(value) => RangeBound::parse(&value),
+        };
+
+        match value {
+            RawRange::Single(value) => {
+                let bound = RangeBound::from_number(value)?;
+                Ok(Self {
+                    lo: bound,
+                    hi: bound,
+                })
+            }
+            RawRange::Pair([lo, hi]) => Ok(Self {
+                lo: bound(lo)?,
+                hi: bound(hi)?,
+            }),
+            RawRange::Text(value) => {
+                let parts = value.split_once("..=").or_else(|| value.split_once(".."));
+                match parts {
+                    Some((lo, hi)) => Ok(Self {
+                        lo: RangeBound::parse(lo)?,
+                        hi: RangeBound::parse(hi)?,
+                    }),
+                    None => {
+                        let bound = RangeBound::parse(&value)?;
+                        Ok(Self {
+                            lo: bound,
+                            hi: bound,
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Zone {
+        range: PreRange,
+    }
+
+    fn keys(toml_value: &str) -> Option<(u8, u8)> {
+        let zone: Zone = toml::from_str(&format!("range = {toml_value}")).ok()?;
+        match zone.range.keys(MiddleC::default()).ok()? {
+            GenAmountType::Range { lo, hi } => Some((lo, hi)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_forms() {
+        assert_eq!(keys(r#""C2..G4""#), Some((36, 67)));
+        assert_eq!(keys(r#""36..=67""#), Some((36, 67)));
+        assert_eq!(keys(r#"" C2 .. 67 ""#), Some((36, 67)));
+        assert_eq!(keys("[36, 67]"), Some((36, 67)));
+        assert_eq!(keys(r#"["C2", 67]"#), Some((36, 67)));
+        assert_eq!(keys("60"), Some((60, 60)));
+        assert_eq!(keys(r#""C4""#), Some((60, 60)));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(keys(r#""G4..C2""#), None);
+        assert_eq!(keys("[0, 128]"), None);
+        assert_eq!(keys(r#""C2..x""#), None);
+        assert_eq!(keys("-1"), None);
+    }
+
+    #[test]
+    fn test_velocities() {
+        let zone: Zone = toml::from_str(r#"range = "0..=100""#).unwrap();
+        assert!(zone.range.velocities().is_ok());
+        let zone: Zone = toml::from_str(r#"range = "C2..G4""#).unwrap();
+        assert!(zone.range.velocities().is_err());
+    }
+}
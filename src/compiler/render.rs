@@ -0,0 +1,454 @@
+//! Rendering presets to audio through rustysynth, for previews and regression tests
+
+use riff::{ChunkContents, ChunkId};
+use rustysynth::{MidiFile, MidiFileSequencer, SoundFont, Synthesizer, SynthesizerSettings};
+use std::{fmt::Display, io::Cursor, path::Path, sync::Arc};
+use wavers::Wav;
+
+use super::{
+    note::{MiddleC, NoteName},
+    CompilerError, ErrorKind,
+};
+use crate::soundfont::{pdta::PdtaList, SoundFont2};
+
+/// A preset by "bank:program", e.g. "0:25", or by name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PresetSelector {
+    Number { bank: u16, program: u16 },
+    Name(String),
+}
+
+impl PresetSelector {
+    /// Anything not of the form "bank:program" is taken as a name.
+    pub fn parse(value: &str) -> Self {
+        if let Some((bank, program)) = value.split_once(':') {
+            if let (Ok(bank), Ok(program)) = (bank.trim().parse(), program.trim().parse()) {
+                return Self::Number { bank, program };
+            }
+        }
+        Self::Name(value.to_string())
+    }
+}
+
+impl Display for PresetSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PresetSelector::Number { bank, program } => write!(f, "{bank}:{program}"),
+            PresetSelector::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// A note to play. Times are in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Note {
+    pub key: u8,
+    pub velocity: u8,
+    pub start: f64,
+    pub duration: f64,
+}
+
+impl Note {
+    pub const DEFAULT_VELOCITY: u8 = 100;
+    pub const DEFAULT_DURATION: f64 = 1.0;
+
+    /// Parse a sequence of notes played one after another, e.g. "C4 E4:80 G4:100:2 C4+E4+G4".
+    ///
+    /// Each item is a note name, or a chord of names joined by '+'. It may be followed by a
+    /// velocity and a duration in seconds, which default to 100 and 1.
+    pub fn parse_sequence(text: &str, middle_c: MiddleC) -> Result<Vec<Note>, CompilerError> {
+        let mut notes = vec![];
+        let mut start = 0.0;
+        for item in text.split_whitespace() {
+            let invalid = || CompilerError::from(ErrorKind::InvalidNote { note: item.into() });
+
+            let mut parts = item.split(':');
+            let names = parts.next().unwrap_or_default();
+            let velocity = match parts.next() {
+                Some(velocity) => velocity
+                    .parse()
+                    .ok()
+                    .filter(|velocity| (1..=127).contains(velocity))
+                    .ok_or_else(invalid)?,
+                None => Self::DEFAULT_VELOCITY,
+            };
+            let duration = match parts.next() {
+                Some(duration) => duration
+                    .parse()
+                    .ok()
+                    .filter(|duration: &f64| duration.is_finite() && *duration > 0.0)
+                    .ok_or_else(invalid)?,
+                None => Self::DEFAULT_DURATION,
+            };
+            if parts.next().is_some() {
+                return Err(invalid());
+            }
+
+            let keys = Self::parse_chord(names, middle_c).ok_or_else(invalid)?;
+            notes.extend(keys.into_iter().map(|key| Note {
+                key,
+                velocity,
+                start,
+                duration,
+            }));
+            start += duration;
+        }
+        Ok(notes)
+    }
+
+    /// Keys of a note name, or of a chord of names joined by '+'
+    pub fn parse_chord(names: &str, middle_c: MiddleC) -> Option<Vec<u8>> {
+        names
+            .split('+')
+            .map(|name| NoteName::parse(name).and_then(|note| middle_c.key(note)))
+            .collect()
+    }
+
+    fn end(&self) -> f64 {
+        self.start + self.duration
+    }
+}
+
+/// What to play
+#[derive(Debug, Clone)]
+pub enum Score {
+    Notes(Vec<Note>),
+    /// Program changes in the file pick presets, unless one is given to [render].
+    Midi(Arc<MidiFile>),
+}
+
+impl Score {
+    pub fn read_midi<P>(path: P) -> Result<Self, CompilerError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let cant_read = |reason: String| ErrorKind::MidiCantRead {
+            path: path.display().to_string(),
+            reason,
+        };
+        let bytes = std::fs::read(path).map_err(|e| cant_read(e.to_string()))?;
+        let midi = MidiFile::new(&mut Cursor::new(bytes)).map_err(|e| cant_read(e.to_string()))?;
+        Ok(Self::Midi(Arc::new(midi)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    /// 16000..=192000
+    pub sample_rate: u32,
+    /// Seconds to keep rendering after the last note, for releases and reverb to ring out. Zero
+    /// or more.
+    pub tail: f64,
+    pub reverb_and_chorus: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100,
+            tail: 1.0,
+            reverb_and_chorus: true,
+        }
+    }
+}
+
+/// Stereo audio, full scale being -1.0..=1.0
+#[derive(Debug, Clone)]
+pub struct Rendering {
+    pub sample_rate: u32,
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+}
+
+impl Rendering {
+    fn silence(sample_rate: u32, len: usize) -> Self {
+        Self {
+            sample_rate,
+            left: vec![0.0; len],
+            right: vec![0.0; len],
+        }
+    }
+
+    /// Read a stereo WAVE file, e.g. one written by [Rendering::save]
+    pub fn read<P>(path: P) -> Result<Self, CompilerError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let wav_error = |err: wavers::WaversError| ErrorKind::WavCantRead {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        };
+        let mut wav: Wav<f32> = Wav::from_path(path).map_err(wav_error)?;
+        let channels = wav.n_channels();
+        if channels != 2 {
+            return Err(ErrorKind::UnsupportedChannelCount {
+                path: path.display().to_string(),
+                channels,
+            }
+            .into());
+        }
+        let sample_rate = wav.sample_rate() as u32;
+        let points = wav.read().map_err(wav_error)?;
+        Ok(Self {
+            sample_rate,
+            left: points.iter().step_by(2).copied().collect(),
+            right: points.iter().skip(1).step_by(2).copied().collect(),
+        })
+    }
+
+    /// How far apart two renderings are. None if their lengths or sample rates differ.
+    pub fn difference(&self, other: &Rendering) -> Option<Difference> {
+        if self.sample_rate != other.sample_rate || self.left.len() != other.left.len() {
+            return None;
+        }
+        let differences: Vec<f32> = self
+            .left
+            .iter()
+            .zip(&other.left)
+            .chain(self.right.iter().zip(&other.right))
+            .map(|(a, b)| (a - b).abs())
+            .collect();
+        let squares: f64 = differences.iter().map(|d| (*d as f64).powi(2)).sum();
+        Some(Difference {
+            rms: (squares / differences.len().max(1) as f64).sqrt() as f32,
+            peak: differences.iter().copied().fold(0.0, f32::max),
+        })
+    }
+
+    /// Write a 16-bit stereo WAVE file. Points beyond full scale are clipped.
+    pub fn save<P>(&self, path: P) -> Result<(), CompilerError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let points: Vec<i16> = self
+            .left
+            .iter()
+            .zip(&self.right)
+            .flat_map(|(left, right)| [*left, *right])
+            .map(|point| (point.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16)
+            .collect();
+        wavers::write(path, &points, self.sample_rate as i32, 2).map_err(|e| {
+            ErrorKind::FileCantWrite {
+                path: path.display().to_string(),
+                reason: e.to_string(),
+            }
+            .into()
+        })
+    }
+}
+
+/// Difference between two renderings, full scale being 1.0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difference {
+    /// Root mean square of the point-by-point difference
+    pub rms: f32,
+    /// Largest point-by-point difference
+    pub peak: f32,
+}
+
+/// Render a score with a soundfont.
+///
+/// Given a preset, the synthesizer only sees that one, so every channel and program plays it.
+/// Otherwise notes play the lowest bank and program, and MIDI files pick their own.
+pub fn render(
+    font: &SoundFont2,
+    preset: Option<&PresetSelector>,
+    score: &Score,
+    settings: &RenderSettings,
+) -> Result<Rendering, CompilerError> {
+    let failed = |reason: String| CompilerError::from(ErrorKind::RenderFailed { reason });
+    if !(settings.tail.is_finite() && settings.tail >= 0.0) {
+        return Err(failed(format!(
+            "The tail must be zero or more seconds, not {}.",
+            settings.tail
+        )));
+    }
+
+    let pdta = match preset {
+        Some(preset) => only_preset(&font.pdta, preset)?,
+        None => font.pdta.clone(),
+    };
+    let riff = ChunkContents::Children(
+        riff::RIFF_ID,
+        ChunkId { value: *b"sfbk" },
+        vec![font.info.to_riff(), font.sdta.to_riff(), pdta.to_riff()?],
+    );
+    let mut bytes = Cursor::new(vec![]);
+    riff.write(&mut bytes).map_err(|e| failed(e.to_string()))?;
+    bytes.set_position(0);
+    let sound_font = Arc::new(SoundFont::new(&mut bytes).map_err(|e| failed(e.to_string()))?);
+
+    let mut synth_settings = SynthesizerSettings::new(settings.sample_rate as i32);
+    synth_settings.enable_reverb_and_chorus = settings.reverb_and_chorus;
+    let synth =
+        Synthesizer::new(&sound_font, &synth_settings).map_err(|e| failed(e.to_string()))?;
+
+    let points = |seconds: f64| (seconds.max(0.0) * settings.sample_rate as f64).round() as usize;
+    match score {
+        Score::Notes(notes) => {
+            let end = notes.iter().map(Note::end).fold(0.0, f64::max);
+            // Never shorter than the last note off
+            let len = points(end).max(points(end + settings.tail));
+            let mut rendering = Rendering::silence(settings.sample_rate, len);
+            play_notes(synth, notes, &mut rendering, points);
+            Ok(rendering)
+        }
+        Score::Midi(midi) => {
+            let len = points(midi.get_length() + settings.tail);
+            let mut rendering = Rendering::silence(settings.sample_rate, len);
+            let mut sequencer = MidiFileSequencer::new(synth);
+            sequencer.play(midi, false);
+            sequencer.render(&mut rendering.left, &mut rendering.right);
+            Ok(rendering)
+        }
+    }
+}
+
+/// Render between note events, on channel 0
+fn play_notes(
+    mut synth: Synthesizer,
+    notes: &[Note],
+    rendering: &mut Rendering,
+    points: impl Fn(f64) -> usize,
+) {
+    // Note offs sort before note ons at the same point, so that a repeated key restarts
+    let mut events: Vec<(usize, bool, &Note)> = notes
+        .iter()
+        .flat_map(|note| {
+            [
+                (points(note.start), true, note),
+                (points(note.end()), false, note),
+            ]
+        })
+        .collect();
+    events.sort_by_key(|(at, on, _)| (*at, *on));
+
+    let mut position = 0;
+    for (at, on, note) in events {
+        synth.render(
+            &mut rendering.left[position..at],
+            &mut rendering.right[position..at],
+        );
+        position = at;
+        if on {
+            synth.note_on(0, note.key as i32, note.velocity as i32);
+        } else {
+            synth.note_off(0, note.key as i32);
+        }
+    }
+    synth.render(
+        &mut rendering.left[position..],
+        &mut rendering.right[position..],
+    );
+}
+
+/// The hydra with the preset headers narrowed down to one. Zones are found by index, so the
+/// rest can stay as is.
+fn only_preset(pdta: &PdtaList, preset: &PresetSelector) -> Result<PdtaList, CompilerError> {
+    // The last record is the terminal
+    let headers = &pdta.phdr.contents;
+    let found = headers.windows(2).find(|pair| match preset {
+        PresetSelector::Number { bank, program } => {
+            pair[0].bank == *bank && pair[0].preset == *program
+        }
+        PresetSelector::Name(name) => pair[0].name == *name,
+    });
+    let Some([header, next]) = found else {
+        return Err(ErrorKind::PresetNotFound {
+            preset: preset.to_string(),
+        }
+        .into());
+    };
+
+    let mut terminal = headers[headers.len() - 1].clone();
+    terminal.pbag_idx = next.pbag_idx;
+    let mut pdta = pdta.clone();
+    pdta.phdr.contents = vec![header.clone(), terminal];
+    Ok(pdta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::FontData;
+
+    #[test]
+    fn test_parse_sequence() {
+        let notes = Note::parse_sequence("C4 E4:80 G4+C5:100:0.5 a3", MiddleC::default()).unwrap();
+        let summary: Vec<(u8, u8, f64, f64)> = notes
+            .iter()
+            .map(|note| (note.key, note.velocity, note.start, note.duration))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (60, 100, 0.0, 1.0),
+                (64, 80, 1.0, 1.0),
+                (67, 100, 2.0, 0.5),
+                (72, 100, 2.0, 0.5),
+                (57, 100, 2.5, 1.0),
+            ]
+        );
+
+        for invalid in ["H4", "C4:0", "C4:100:-1", "C4:100:1:1", "C4+"] {
+            assert!(
+                Note::parse_sequence(invalid, MiddleC::default()).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let manifest = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/example_project/SoundFont.toml"
+        );
+        let font = FontData::read(manifest).unwrap().generate_soundfont();
+        let score = Score::Notes(Note::parse_sequence("C4:127:0.5", MiddleC::default()).unwrap());
+        let settings = RenderSettings {
+            sample_rate: 22050,
+            tail: 0.5,
+            reverb_and_chorus: false,
+        };
+
+        let by_name = PresetSelector::parse("Guitar");
+        let by_number = PresetSelector::parse("0:0");
+        assert_eq!(
+            by_number,
+            PresetSelector::Number {
+                bank: 0,
+                program: 0
+            }
+        );
+        let rendering = render(&font, Some(&by_name), &score, &settings).unwrap();
+        assert_eq!(rendering.left.len(), 22050);
+        assert!(rendering.left.iter().any(|point| point.abs() > 0.01));
+        let same = render(&font, Some(&by_number), &score, &settings).unwrap();
+        assert_eq!(rendering.left, same.left);
+
+        let missing = render(
+            &font,
+            Some(&PresetSelector::parse("1:5")),
+            &score,
+            &settings,
+        );
+        assert!(matches!(
+            *missing.unwrap_err().kind,
+            ErrorKind::PresetNotFound { .. }
+        ));
+
+        let negative_tail = RenderSettings {
+            tail: -5.0,
+            ..settings
+        };
+        let negative_tail = render(&font, Some(&by_name), &score, &negative_tail);
+        assert!(matches!(
+            *negative_tail.unwrap_err().kind,
+            ErrorKind::RenderFailed { .. }
+        ));
+    }
+}
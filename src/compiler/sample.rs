@@ -0,0 +1,675 @@
+//! Reading sample data from WAVE files
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::File,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+use wavers::{Wav, WavType};
+
+use super::{
+    auto_loop::find_loop,
+    pitch::{detect_pitch, DetectedPitch, PrePitch},
+    CompilerError, ErrorKind,
+};
+use crate::soundfont::{
+    info::validate_string,
+    pdta::{SFSampleLink, SampleHeader},
+};
+
+#[derive(Debug, Clone)]
+pub struct SampleWrap {
+    pub header: SampleHeader,
+    /// 24-bit sample points. 16-bit sources have the low byte zeroed.
+    pub data: Vec<i32>,
+    /// Source had more than 16 bits of resolution
+    pub high_resolution: bool,
+}
+
+impl SampleWrap {
+    /// Upper 16 bits of each point, which go into smpl
+    pub fn upper_bits(&self) -> impl Iterator<Item = i16> + '_ {
+        self.data.iter().map(|point| (point >> 8) as i16)
+    }
+
+    /// Low byte of each point, which goes into sm24
+    pub fn lower_bits(&self) -> impl Iterator<Item = u8> + '_ {
+        self.data.iter().map(|point| *point as u8)
+    }
+
+    /// Reduce to 16 bits with TPDF dither.
+    ///
+    /// The noise is seeded from the sample data, so builds stay reproducible.
+    pub fn dithered(&self) -> Vec<i16> {
+        let mut state = self.data.iter().fold(0x9e37_79b9_u32, |acc, point| {
+            acc.rotate_left(5) ^ *point as u32
+        }) | 1;
+        let mut random = move || {
+            // xorshift32
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state & 0xff) as i32
+        };
+
+        self.data
+            .iter()
+            .map(|point| {
+                // Triangular noise of +-1 LSB in 16-bit terms
+                let noise = random() - random();
+                let value = (point + noise + 0x80) >> 8;
+                value.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+            })
+            .collect()
+    }
+}
+
+/// A loop point in sample frames, or in seconds.
+///
+/// Accepted forms: `1200`, `"1200"`, `"0.5s"`, `"500ms"`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "RawLoopPoint", into = "RawLoopPoint")]
+pub enum PreLoopPoint {
+    Frames(u32),
+    Seconds(f64),
+}
+
+impl PreLoopPoint {
+    pub fn frames(&self, sample_rate: u32) -> u32 {
+        match self {
+            PreLoopPoint::Frames(frames) => *frames,
+            PreLoopPoint::Seconds(seconds) => (seconds * sample_rate as f64).round() as u32,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawLoopPoint {
+    Frames(i64),
+    Text(String),
+}
+
+impl From<PreLoopPoint> for RawLoopPoint {
+    fn from(point: PreLoopPoint) -> Self {
+        match point {
+            PreLoopPoint::Frames(frames) => RawLoopPoint::Frames(frames as i64),
+            PreLoopPoint::Seconds(seconds) => RawLoopPoint::Text(format!("{seconds}s")),
+        }
+    }
+}
+
+impl TryFrom<RawLoopPoint> for PreLoopPoint {
+    type Error = String;
+
+    fn try_from(value: RawLoopPoint) -> Result<Self, Self::Error> {
+        let invalid = |value: &str| {
+            format!(
+                "Invalid loop point '{value}'. Expected frames, e.g. 1200, or seconds, e.g. \"0.5s\" or \"500ms\"."
+            )
+        };
+
+        let text = match value {
+            RawLoopPoint::Frames(frames) => {
+                return u32::try_from(frames)
+                    .map(Self::Frames)
+                    .map_err(|_| invalid(&frames.to_string()))
+            }
+            RawLoopPoint::Text(text) => text,
+        };
+
+        let trimmed = text.trim();
+        let seconds = if let Some(ms) = trimmed.strip_suffix("ms") {
+            ms.trim().parse::<f64>().ok().map(|ms| ms / 1000.0)
+        } else if let Some(seconds) = trimmed.strip_suffix('s') {
+            seconds.trim().parse::<f64>().ok()
+        } else {
+            return trimmed
+                .parse::<u32>()
+                .map(Self::Frames)
+                .map_err(|_| invalid(&text));
+        };
+        match seconds {
+            Some(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(Self::Seconds(seconds)),
+            _ => Err(invalid(&text)),
+        }
+    }
+}
+
+/// Settings that end up in the sample header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleSettings {
+    pub original_pitch: PrePitch,
+    /// Defaults to 0, or the detected correction
+    pub pitch_correction: Option<i8>,
+    pub startloop: Option<PreLoopPoint>,
+    pub endloop: Option<PreLoopPoint>,
+    /// Find loop points missing from the settings in the audio, instead of using the embedded
+    /// loop
+    pub auto_loop: bool,
+}
+
+/// Samples read from one WAVE file. Stereo files are split into a left/right pair.
+#[derive(Debug, Clone)]
+pub enum WavSamples {
+    Mono(SampleWrap),
+    /// Headers are typed, but not yet linked to each other.
+    Stereo {
+        left: SampleWrap,
+        right: SampleWrap,
+    },
+}
+
+impl WavSamples {
+    /// Loop points are taken from the settings first, then found from the audio with
+    /// `auto_loop`, or else from the loop embedded in the file. Failing those, the loop spans
+    /// the whole sample minus the required 8 point lead and tail.
+    ///
+    /// Also gives the pitch, if it was to be detected.
+    pub fn read<P>(
+        path: P,
+        name: &str,
+        settings: &SampleSettings,
+    ) -> Result<(Self, Option<DetectedPitch>), CompilerError>
+    where
+        P: AsRef<Path>,
+    {
+        let wav_error = |err: wavers::WaversError| ErrorKind::WavCantRead {
+            path: path.as_ref().display().to_string(),
+            reason: err.to_string(),
+        };
+        let mut wav: Wav<i32> = Wav::from_path(&path).map_err(wav_error)?;
+        let sample_rate = wav.sample_rate() as u32;
+        let channels = wav.n_channels();
+        let high_resolution = high_resolution(wav.encoding());
+        // Full 32-bit scale down to 24
+        let data: Vec<i32> = wav
+            .read()
+            .map_err(wav_error)?
+            .iter()
+            .map(|point| point >> 8)
+            .collect();
+
+        let len = (data.len() / channels.max(1) as usize) as u32;
+        let embedded = match settings.auto_loop {
+            true => None,
+            false => read_embedded_loop(&path),
+        };
+        let mut startloop = settings
+            .startloop
+            .map(|point| point.frames(sample_rate))
+            .or(embedded.map(|(start, _)| start));
+        let mut endloop = settings
+            .endloop
+            .map(|point| point.frames(sample_rate))
+            .or(embedded.map(|(_, end)| end));
+
+        let detect = settings.original_pitch == PrePitch::Detect;
+        let find = settings.auto_loop && (startloop.is_none() || endloop.is_none());
+        let points = match detect || find {
+            true => channel_mix(&data, channels.max(1) as usize),
+            false => vec![],
+        };
+        // Past the attack, which ends about where the sample first peaks
+        let peak = points.iter().map(|point| point.abs()).fold(0.0, f32::max);
+        let attack_end = points
+            .iter()
+            .position(|point| point.abs() >= peak)
+            .unwrap_or(0);
+
+        let detected = match detect {
+            false => None,
+            true => {
+                let region = match (startloop, endloop) {
+                    (Some(start), Some(end)) if start < end && end <= len => {
+                        &points[start as usize..end as usize]
+                    }
+                    _ => &points[attack_end..],
+                };
+                let detected = detect_pitch(region, sample_rate)
+                    .or_else(|| detect_pitch(&points, sample_rate))
+                    .ok_or_else(|| ErrorKind::PitchNotDetected { name: name.into() })?;
+                Some(detected)
+            }
+        };
+        if find {
+            // Loop lengths snap to whole periods, if the pitch is clear
+            let period = detected
+                .or_else(|| detect_pitch(&points[attack_end..], sample_rate))
+                .filter(|pitch| pitch.confidence >= DetectedPitch::LOW_CONFIDENCE)
+                .map(|pitch| sample_rate as f64 / pitch.frequency);
+            let (start, end) = find_loop(&points, attack_end, period)
+                .ok_or_else(|| ErrorKind::LoopNotFound { name: name.into() })?;
+            startloop = startloop.or(Some(start));
+            endloop = endloop.or(Some(end));
+        }
+        let startloop = startloop.unwrap_or(8);
+        let endloop = endloop.unwrap_or(len.saturating_sub(8));
+
+        let original_pitch = match (settings.original_pitch, detected) {
+            (PrePitch::Key(key), _) => key,
+            (PrePitch::Detect, detected) => detected.map_or(60, |detected| detected.key),
+        };
+        let pitch_correction = settings
+            .pitch_correction
+            .or(detected.map(|detected| detected.correction))
+            .unwrap_or(0);
+
+        let wrap = |name: String, data: Vec<i32>, sample_type: SFSampleLink| {
+            let header = SampleHeader {
+                name,
+                raw_name: None,
+                start: 0,
+                end: data.len() as u32,
+                startloop,
+                endloop,
+                sample_rate,
+                original_pitch,
+                pitch_correction,
+                sample_link: 0,
+                sample_type: sample_type as u16,
+            };
+            validate_string(&header.name, 20)?;
+            if let Err(source) = header.validate() {
+                return Err(CompilerError::from(ErrorKind::InvalidSampleLoop {
+                    name: header.name,
+                    len: header.end,
+                    startloop,
+                    endloop,
+                    source,
+                }));
+            }
+            Ok(SampleWrap {
+                header,
+                data,
+                high_resolution,
+            })
+        };
+
+        let samples = match channels {
+            1 => Self::Mono(wrap(name.into(), data, SFSampleLink::Mono)?),
+            2 => {
+                let left = data.iter().step_by(2).copied().collect();
+                let right = data.iter().skip(1).step_by(2).copied().collect();
+                Self::Stereo {
+                    left: wrap(format!("{name}_L"), left, SFSampleLink::Left)?,
+                    right: wrap(format!("{name}_R"), right, SFSampleLink::Right)?,
+                }
+            }
+            channels => {
+                return Err(ErrorKind::UnsupportedChannelCount {
+                    path: path.as_ref().display().to_string(),
+                    channels,
+                }
+                .into())
+            }
+        };
+        Ok((samples, detected))
+    }
+}
+
+/// Whether the encoding holds more than 16 bits, so the sample keeps its low byte
+fn high_resolution(encoding: WavType) -> bool {
+    matches!(
+        encoding,
+        WavType::Pcm24
+            | WavType::EPcm24
+            | WavType::Pcm32
+            | WavType::EPcm32
+            | WavType::Float32
+            | WavType::EFloat32
+            | WavType::Float64
+            | WavType::EFloat64
+    )
+}
+
+/// Interleaved 24-bit points mixed down to mono, full scale being 1.0
+fn channel_mix(data: &[i32], channels: usize) -> Vec<f32> {
+    data.chunks_exact(channels)
+        .map(|frame| frame.iter().map(|point| *point as f32).sum::<f32>())
+        .map(|sum| sum / (channels as f32 * 8_388_608.0))
+        .collect()
+}
+
+/// One sample of a zone's WAVE file. Stereo files give two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Voice {
+    pub sample_idx: u16,
+    /// Pan of a stereo pair's channel
+    pub pan: Option<i16>,
+}
+
+/// Voices of a WAVE file, and the settings they were read with
+type ReadSample = (SampleSettings, Vec<Voice>);
+
+/// Samples of the whole soundfont, each stored once no matter how many zones use them.
+#[derive(Debug, Default)]
+pub struct SamplePool {
+    pub samples: Vec<SampleWrap>,
+    /// Keyed by canonical path and sample name. Zones asking for different settings, such as
+    /// one detecting the root key, get samples of their own.
+    by_path: HashMap<(PathBuf, String), Vec<ReadSample>>,
+    /// Also merge different files with identical audio and settings
+    by_content: Option<HashMap<u64, Vec<Vec<Voice>>>>,
+    /// Pitches detected, by sample name
+    pub detected_pitches: Vec<(String, DetectedPitch)>,
+}
+
+impl SamplePool {
+    pub fn new(dedupe_by_content: bool) -> Self {
+        Self {
+            by_content: dedupe_by_content.then(HashMap::new),
+            ..Default::default()
+        }
+    }
+
+    /// Read a WAVE file into the pool, unless it's already there.
+    pub fn insert<P>(
+        &mut self,
+        path: P,
+        name: &str,
+        settings: &SampleSettings,
+    ) -> Result<Vec<Voice>, CompilerError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let key = (canonical, name.to_string());
+        let read = self.by_path.get(&key).into_iter().flatten();
+        if let Some((_, voices)) = { read }.find(|(other, _)| other == settings) {
+            return Ok(voices.clone());
+        }
+
+        let (wav_samples, detected) = WavSamples::read(path, name, settings)?;
+        if let Some(detected) = detected {
+            self.detected_pitches.push((name.to_string(), detected));
+        }
+        let channels = match wav_samples {
+            WavSamples::Mono(sample) => vec![sample],
+            WavSamples::Stereo { left, right } => vec![left, right],
+        };
+
+        let hash = content_hash(&channels);
+        let duplicate = self.by_content.as_ref().and_then(|by_content| {
+            by_content.get(&hash)?.iter().find(|voices| {
+                voices.len() == channels.len()
+                    && voices.iter().zip(&channels).all(|(voice, sample)| {
+                        same_content(&self.samples[voice.sample_idx as usize], sample)
+                    })
+            })
+        });
+        if let Some(voices) = duplicate.cloned() {
+            let read = self.by_path.entry(key).or_default();
+            read.push((*settings, voices.clone()));
+            return Ok(voices);
+        }
+
+        let first_idx = self.samples.len() as u16;
+        let stereo = channels.len() == 2;
+        let mut voices = vec![];
+        for (channel, mut sample) in channels.into_iter().enumerate() {
+            let channel = channel as u16;
+            if stereo {
+                // Link each channel of a pair to the other one
+                sample.header.sample_link = first_idx + 1 - channel;
+            }
+            voices.push(Voice {
+                sample_idx: first_idx + channel,
+                pan: stereo.then_some(if channel == 0 { -500 } else { 500 }),
+            });
+            self.samples.push(sample);
+        }
+
+        if let Some(by_content) = &mut self.by_content {
+            by_content.entry(hash).or_default().push(voices.clone());
+        }
+        let read = self.by_path.entry(key).or_default();
+        read.push((*settings, voices.clone()));
+        Ok(voices)
+    }
+}
+
+/// Everything that ends up in sdta and shdr, apart from the name and link.
+fn content_hash(channels: &[SampleWrap]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for sample in channels {
+        let header = &sample.header;
+        (header.end - header.start).hash(&mut hasher);
+        (header.startloop, header.endloop, header.sample_rate).hash(&mut hasher);
+        (
+            header.original_pitch,
+            header.pitch_correction,
+            header.sample_type,
+        )
+            .hash(&mut hasher);
+        sample.data.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn same_content(a: &SampleWrap, b: &SampleWrap) -> bool {
+    let (x, y) = (&a.header, &b.header);
+    x.end - x.start == y.end - y.start
+        && x.startloop == y.startloop
+        && x.endloop == y.endloop
+        && x.sample_rate == y.sample_rate
+        && x.original_pitch == y.original_pitch
+        && x.pitch_correction == y.pitch_correction
+        && x.sample_type == y.sample_type
+        && a.high_resolution == b.high_resolution
+        && a.data == b.data
+}
+
+/// Loop written into the WAVE file by an editor, as (startloop, endloop) in frames.
+///
+/// The first `smpl` chunk loop is preferred. Otherwise the first two `cue ` markers are used.
+fn read_embedded_loop<P: AsRef<Path>>(path: P) -> Option<(u32, u32)> {
+    let mut file = File::open(path).ok()?;
+    let riff = riff::Chunk::read(&mut file, 0).ok()?;
+
+    let mut smpl = None;
+    let mut cue = None;
+    let chunks: Vec<riff::Chunk> = riff.iter(&mut file).filter_map(Result::ok).collect();
+    for chunk in chunks {
+        match &chunk.id().value {
+            b"smpl" => smpl = chunk.read_contents(&mut file).ok(),
+            b"cue " => cue = chunk.read_contents(&mut file).ok(),
+            _ => (),
+        }
+    }
+
+    smpl.as_deref()
+        .and_then(smpl_loop)
+        .or_else(|| cue.as_deref().and_then(cue_loop))
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> Option<u32> {
+    let bytes = bytes.get(pos..pos + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// First loop of a `smpl` chunk. Its end point is inclusive, unlike endloop.
+fn smpl_loop(contents: &[u8]) -> Option<(u32, u32)> {
+    // 36 byte header, the loop count is at offset 28
+    if read_u32(contents, 28)? == 0 {
+        return None;
+    }
+    // 24 byte loop records: cue id, type, start, end, fraction, play count
+    let start = read_u32(contents, 36 + 8)?;
+    let end = read_u32(contents, 36 + 12)?;
+    Some((start, end.checked_add(1)?))
+}
+
+/// The two earliest markers of a `cue ` chunk.
+fn cue_loop(contents: &[u8]) -> Option<(u32, u32)> {
+    let count = read_u32(contents, 0)? as usize;
+    // 24 byte cue points, the sample offset is the last field
+    let mut offsets: Vec<u32> = (0..count)
+        .map_while(|i| read_u32(contents, 4 + i * 24 + 20))
+        .collect();
+    offsets.sort_unstable();
+    match offsets[..] {
+        [start, end, ..] => Some((start, end)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_project::TempProject;
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Zone {
+        point: PreLoopPoint,
+    }
+
+    fn frames(toml_value: &str) -> Option<u32> {
+        let zone: Zone = toml::from_str(&format!("point = {toml_value}")).ok()?;
+        Some(zone.point.frames(44100))
+    }
+
+    #[test]
+    fn test_loop_point_forms() {
+        assert_eq!(frames("1200"), Some(1200));
+        assert_eq!(frames(r#""1200""#), Some(1200));
+        assert_eq!(frames(r#""0.5s""#), Some(22050));
+        assert_eq!(frames(r#""250 ms""#), Some(11025));
+        assert_eq!(frames("-1"), None);
+        assert_eq!(frames(r#""-0.5s""#), None);
+        assert_eq!(frames(r#""12 frames""#), None);
+    }
+
+    #[test]
+    fn test_embedded_loops() {
+        let mut smpl = vec![0u8; 36 + 24];
+        smpl[28..32].copy_from_slice(&1u32.to_le_bytes());
+        smpl[44..48].copy_from_slice(&100u32.to_le_bytes());
+        smpl[48..52].copy_from_slice(&199u32.to_le_bytes());
+        assert_eq!(smpl_loop(&smpl), Some((100, 200)));
+        smpl[28..32].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(smpl_loop(&smpl), None);
+
+        let mut cue = vec![0u8; 4 + 2 * 24];
+        cue[0..4].copy_from_slice(&2u32.to_le_bytes());
+        cue[24..28].copy_from_slice(&300u32.to_le_bytes());
+        cue[48..52].copy_from_slice(&100u32.to_le_bytes());
+        assert_eq!(cue_loop(&cue), Some((100, 300)));
+        assert_eq!(cue_loop(&cue[..28]), None);
+    }
+
+    #[test]
+    fn test_stereo() {
+        let project = TempProject::new("stereo");
+        project.wav("tone.wav", 2, 16, 1000);
+        let settings = SampleSettings {
+            original_pitch: PrePitch::Key(60),
+            pitch_correction: None,
+            startloop: None,
+            endloop: None,
+            auto_loop: false,
+        };
+        let (samples, _) =
+            WavSamples::read(project.root.join("tone.wav"), "tone", &settings).unwrap();
+
+        // Each channel is its own typed sample, the right one quieter
+        let WavSamples::Stereo { left, right } = samples else {
+            panic!("expected a stereo pair");
+        };
+        assert_eq!(left.header.name, "tone_L");
+        assert_eq!(left.header.sample_type, SFSampleLink::Left as u16);
+        assert_eq!(right.header.name, "tone_R");
+        assert_eq!(right.header.sample_type, SFSampleLink::Right as u16);
+        assert_eq!((left.data.len(), right.data.len()), (1000, 1000));
+        assert!(right.data[10].abs() < left.data[10].abs());
+    }
+
+    #[test]
+    fn test_sample_pool() {
+        let project = TempProject::new("sample-pool");
+        project
+            .wav("a.wav", 1, 16, 1000)
+            .wav("b.wav", 1, 16, 1000)
+            .wav("c.wav", 1, 16, 2000)
+            .wav("d.wav", 2, 16, 1000);
+        let settings = SampleSettings {
+            original_pitch: PrePitch::Key(60),
+            pitch_correction: None,
+            startloop: None,
+            endloop: None,
+            auto_loop: false,
+        };
+        let insert = |pool: &mut SamplePool, name: &str| {
+            let path = project.root.join(format!("{name}.wav"));
+            pool.insert(path, name, &settings).unwrap()
+        };
+
+        // b has the same audio as a, but c doesn't
+        let mut pool = SamplePool::new(true);
+        let indices = ["a", "b", "a", "c"].map(|name| insert(&mut pool, name)[0].sample_idx);
+        assert_eq!(indices, [0, 0, 0, 1]);
+        assert_eq!(pool.samples.len(), 2);
+
+        // A stereo pair is linked both ways, and panned hard to each side
+        let voices = insert(&mut pool, "d");
+        let pair = [
+            Voice {
+                sample_idx: 2,
+                pan: Some(-500),
+            },
+            Voice {
+                sample_idx: 3,
+                pan: Some(500),
+            },
+        ];
+        assert_eq!(voices, pair);
+        assert_eq!(pool.samples[2].header.sample_link, 3);
+        assert_eq!(pool.samples[3].header.sample_link, 2);
+
+        let mut pool = SamplePool::new(false);
+        let indices = ["a", "b", "a"].map(|name| insert(&mut pool, name)[0].sample_idx);
+        assert_eq!(indices, [0, 1, 0]);
+    }
+
+    #[test]
+    fn test_high_resolution() {
+        assert!(!high_resolution(WavType::Pcm16));
+        assert!(!high_resolution(WavType::EPcm16));
+        for encoding in [
+            WavType::Pcm24,
+            WavType::EPcm24,
+            WavType::Pcm32,
+            WavType::EPcm32,
+            WavType::Float32,
+            WavType::EFloat32,
+            WavType::Float64,
+            WavType::EFloat64,
+        ] {
+            assert!(high_resolution(encoding), "{encoding:?}");
+        }
+
+        let project = TempProject::new("high-resolution");
+        project
+            .wav("16.wav", 1, 16, 1000)
+            .wav("24.wav", 1, 24, 1000);
+        let settings = SampleSettings {
+            original_pitch: PrePitch::Key(60),
+            pitch_correction: None,
+            startloop: None,
+            endloop: None,
+            auto_loop: false,
+        };
+        let read = |bits: &str| {
+            let path = project.root.join(format!("{bits}.wav"));
+            match WavSamples::read(path, bits, &settings).unwrap().0 {
+                WavSamples::Mono(sample) => sample.high_resolution,
+                WavSamples::Stereo { left, .. } => left.high_resolution,
+            }
+        };
+        assert!(!read("16"));
+        assert!(read("24"));
+    }
+}
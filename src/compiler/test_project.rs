@@ -0,0 +1,97 @@
+//! Small projects written to a temporary directory, for tests
+
+use std::path::PathBuf;
+
+use super::{CompilerError, FontData};
+
+/// A project directory, removed when dropped
+pub struct TempProject {
+    pub root: PathBuf,
+}
+
+impl TempProject {
+    /// An empty directory. `name` keeps tests running in parallel apart.
+    pub fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("sfc-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        Self { root }
+    }
+
+    /// A manifest with one preset, playing an instrument with the given contents, at
+    /// `instruments/inst.toml`
+    pub fn with_instrument(name: &str, instrument: &str) -> Self {
+        let project = Self::new(name);
+        project
+            .file(
+                "SoundFont.toml",
+                "name = \"Test\"\npresets = [\"preset.toml\"]\n",
+            )
+            .file(
+                "presets/preset.toml",
+                "name = \"Preset\"\nmidi_preset = 0\nmidi_bank = 0\n\
+                 [zones.main]\ninstrument = \"inst.toml\"\n",
+            )
+            .file("instruments/inst.toml", instrument);
+        project
+    }
+
+    /// Write a file, creating its directory
+    pub fn file(&self, path: &str, contents: impl AsRef<[u8]>) -> &Self {
+        let path = self.root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+        self
+    }
+
+    /// Write a PCM WAVE file of a 220 Hz tone at 44.1 kHz. Stereo files have the right channel
+    /// at half the level.
+    pub fn wav(&self, path: &str, channels: u16, bits: u16, frames: usize) -> &Self {
+        let bytes_per_point = bits as usize / 8;
+        let full_scale = (1i64 << (bits - 1)) - 1;
+        let mut data: Vec<u8> = vec![];
+        for i in 0..frames {
+            let value = (std::f64::consts::TAU * 220.0 * i as f64 / 44100.0).sin() * 0.5;
+            for channel in 0..channels {
+                let point = (value / (channel + 1) as f64 * full_scale as f64) as i64;
+                let point = match bits {
+                    // 8-bit WAVs are unsigned
+                    8 => point + 128,
+                    _ => point,
+                };
+                data.extend(&point.to_le_bytes()[..bytes_per_point]);
+            }
+        }
+
+        let block_align = channels * bits / 8;
+        let mut bytes: Vec<u8> = vec![];
+        bytes.extend(b"RIFF");
+        bytes.extend((36 + data.len() as u32).to_le_bytes());
+        bytes.extend(b"WAVEfmt ");
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(channels.to_le_bytes());
+        bytes.extend(44100u32.to_le_bytes());
+        bytes.extend((44100 * block_align as u32).to_le_bytes());
+        bytes.extend(block_align.to_le_bytes());
+        bytes.extend(bits.to_le_bytes());
+        bytes.extend(b"data");
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        self.file(path, bytes)
+    }
+
+    pub fn manifest(&self) -> PathBuf {
+        self.root.join("SoundFont.toml")
+    }
+
+    pub fn read(&self) -> Result<FontData, Vec<CompilerError>> {
+        FontData::read(self.manifest())
+    }
+}
+
+impl Drop for TempProject {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
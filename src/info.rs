@@ -0,0 +1,129 @@
+use soundfont_compiler::soundfont::{info::VersionTag, pdta::SFSampleLink, SoundFont2};
+
+/// The INFO list, and tables of presets, instruments and samples
+pub fn format_info(font: &SoundFont2) -> String {
+    let info = &font.info;
+    let version = |version: &VersionTag| format!("{}.{:02}", version.major, version.minor);
+    let fields = [
+        ("Version", Some(version(info.ifil()))),
+        ("Sound engine", Some(info.isng().clone())),
+        ("Name", Some(info.inam().clone())),
+        ("ROM", info.irom().map(String::from)),
+        ("ROM version", info.iver().map(version)),
+        ("Created", info.icrd().map(String::from)),
+        ("Authors", info.ieng().map(String::from)),
+        ("Product", info.iprd().map(String::from)),
+        ("Copyright", info.icop().map(String::from)),
+        ("Comments", info.icmt().map(String::from)),
+        ("Software", info.isft().map(String::from)),
+    ];
+    let mut out = String::new();
+    for (label, value) in fields {
+        if let Some(value) = value {
+            out += &format!("{:<14}{value}\n", format!("{label}:"));
+        }
+    }
+
+    // The last record of each list is the terminal
+    let pdta = &font.pdta;
+    let presets = &pdta.phdr.contents;
+    let rows = presets
+        .windows(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            vec![
+                i.to_string(),
+                pair[0].bank.to_string(),
+                pair[0].preset.to_string(),
+                pair[0].name.clone(),
+                pair[1]
+                    .pbag_idx
+                    .saturating_sub(pair[0].pbag_idx)
+                    .to_string(),
+            ]
+        })
+        .collect();
+    out += &table("Presets", &["#", "Bank", "Preset", "Name", "Zones"], rows);
+
+    let instruments = &pdta.inst.contents;
+    let rows = instruments
+        .windows(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            vec![
+                i.to_string(),
+                pair[0].name.clone(),
+                pair[1]
+                    .inst_bag_idx
+                    .saturating_sub(pair[0].inst_bag_idx)
+                    .to_string(),
+            ]
+        })
+        .collect();
+    out += &table("Instruments", &["#", "Name", "Zones"], rows);
+
+    let samples = &pdta.shdr.contents;
+    let rows = samples[..samples.len().saturating_sub(1)]
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            let sample_type = match header.sample_type {
+                t if t == SFSampleLink::Mono as u16 => "mono".into(),
+                t if t == SFSampleLink::Left as u16 => "left".into(),
+                t if t == SFSampleLink::Right as u16 => "right".into(),
+                t if t == SFSampleLink::Linked as u16 => "linked".into(),
+                t if t & 0x8000 != 0 => format!("rom {}", t & 0x7fff),
+                t => format!("unknown {t}"),
+            };
+            let start = header.start;
+            vec![
+                i.to_string(),
+                header.name.clone(),
+                sample_type,
+                header.end.saturating_sub(start).to_string(),
+                format!(
+                    "{}..{}",
+                    header.startloop.wrapping_sub(start) as i32,
+                    header.endloop.wrapping_sub(start) as i32
+                ),
+                header.sample_rate.to_string(),
+                header.original_pitch.to_string(),
+                header.pitch_correction.to_string(),
+                header.sample_link.to_string(),
+            ]
+        })
+        .collect();
+    out += &table(
+        "Samples",
+        &[
+            "#", "Name", "Type", "Length", "Loop", "Rate", "Pitch", "Cents", "Link",
+        ],
+        rows,
+    );
+    out
+}
+
+/// A titled table with columns padded to their widest cell
+fn table(title: &str, headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        padded.join("  ").trim_end().to_string() + "\n"
+    };
+
+    let mut out = format!("\n{title} ({})\n", rows.len());
+    out += &line(headers.to_vec());
+    for row in &rows {
+        out += &line(row.iter().map(String::as_str).collect());
+    }
+    out
+}
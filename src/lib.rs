@@ -0,0 +1,2 @@
+pub mod compiler;
+pub mod soundfont;
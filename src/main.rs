@@ -1,123 +1,281 @@
 mod args;
-pub mod compiler;
-pub mod soundfont;
+mod info;
+mod output;
 
 use clap::Parser;
-use std::fs::File;
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-use args::Args;
-use compiler::FontData;
+use args::{Args, Command, RenderArgs};
+use info::format_info;
+use output::Output;
+use soundfont_compiler::{
+    compiler::{
+        decompile, init, render, AudioTest, CompilerError, DetectedPitch, ErrorKind, FontData,
+        MiddleC, Note, Outcome, PresetSelector, RenderSettings, Score,
+    },
+    soundfont::{check, Severity, SoundFont2},
+};
 
-fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
-    println!("Compiling project from given path: {}", args.path);
+    let out = Output::new(args.quiet, args.color);
+    match &args.command {
+        Command::Build {
+            path,
+            output,
+            check,
+        } => build(&out, path, output.as_deref(), *check),
+        Command::Check { input } => check_file(&out, Path::new(input)),
+        Command::Info { input } => match read_font(Path::new(input)) {
+            Ok(font) => {
+                print!("{}", format_info(&font));
+                ExitCode::SUCCESS
+            }
+            Err(error) => {
+                out.compile_error(&error);
+                ExitCode::FAILURE
+            }
+        },
+        Command::Init { dir } => match init(dir) {
+            Ok(manifest) => {
+                out.status(format!("Created: {}", manifest.display()));
+                ExitCode::SUCCESS
+            }
+            Err(error) => {
+                out.compile_error(&error);
+                ExitCode::FAILURE
+            }
+        },
+        Command::Decompile { input, dir } => {
+            let dir = match dir {
+                Some(dir) => PathBuf::from(dir),
+                None => PathBuf::from(input).with_extension(""),
+            };
+            match decompile(input, &dir) {
+                Ok(manifest) => {
+                    out.status(format!("Finished: {}", manifest.display()));
+                    ExitCode::SUCCESS
+                }
+                Err(error) => {
+                    out.compile_error(&error);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Command::Render(args) => render_file(&out, args),
+        Command::Test { path, bless } => run_tests(&out, Path::new(path), *bless),
+    }
+}
 
-    print!("Parsing project.........");
-    let mut fontdata = match FontData::read(&args.path) {
+fn build(out: &Output, path: &str, output: Option<&str>, check: bool) -> ExitCode {
+    out.status(format!("Compiling project from given path: {path}"));
+
+    out.progress("Parsing project.........");
+    let fontdata = match FontData::read(path) {
         Ok(fontdata) => {
-            println!("OK");
+            out.status("OK");
+            report_pitches(out, &fontdata);
             fontdata
         }
-        Err(e) => {
-            println!("ERR");
-            println!("{e}");
-            return;
+        Err(errors) => {
+            out.status("ERR");
+            return compile_failed(out, &errors);
         }
     };
 
-    print!("Generating soundfont....");
+    out.progress("Generating soundfont....");
     let soundfont = fontdata.generate_soundfont();
-    println!("OK");
+    out.status("OK");
 
-    print!("Packing into RIFF.......");
-    let riff = soundfont.to_riff();
-    println!("OK");
-
-    print!("Saving file.............");
-    let mut file = File::create("test_output.sf2").expect("filecreatefail");
-    riff.write(&mut file).expect("fail");
-    println!("OK");
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => fontdata.output_path().to_path_buf(),
+    };
 
-    println!("Finished");
+    out.progress("Saving file.............");
+    if let Err(e) = soundfont.save(&output) {
+        out.status("ERR");
+        out.error(format!("Can't write '{}': {e}", output.display()));
+        return ExitCode::FAILURE;
+    }
+    out.status("OK");
 
-    let mut open_file = File::open("test_output.sf2").unwrap();
-    // open_file = File::open("SM64SF_V2.sf2").unwrap();
+    out.status(format!("Finished: {}", output.display()));
 
-    if !args.check {
-        return;
+    if !check {
+        return ExitCode::SUCCESS;
     }
+    check_file(out, &output)
+}
 
-    println!("Result:");
-    let font = match rustysynth::SoundFont::new(&mut open_file) {
-        Ok(val) => {
-            println!("was ok");
-            val
+fn report_pitches(out: &Output, fontdata: &FontData) {
+    for (name, pitch) in fontdata.detected_pitches() {
+        let report = format!(
+            "'{name}' is {} {:+} cents ({:.1} Hz), confidence {:.2}",
+            fontdata.middle_c().note(pitch.key),
+            -(pitch.correction as i16),
+            pitch.frequency,
+            pitch.confidence
+        );
+        if pitch.confidence < DetectedPitch::LOW_CONFIDENCE {
+            out.warning(format!("Unsure pitch detection: {report}"));
+        } else {
+            out.status(format!("  Detected pitch: {report}"));
         }
+    }
+}
+
+/// Print what checking a file found. Fails if there were errors.
+fn check_file(out: &Output, path: &Path) -> ExitCode {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
         Err(e) => {
-            println!("ERR: {e}");
-            return;
+            out.compile_error(&cant_read(path, e));
+            return ExitCode::FAILURE;
         }
     };
-
-    if !args.verbose {
-        return;
+    let findings = check(&bytes);
+    for finding in &findings {
+        out.finding(finding);
     }
-
-    for preset in font.get_presets() {
-        log_preset(preset);
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.severity() == Severity::Error)
+        .count();
+    out.status(format!(
+        "{}: {errors} error(s), {} warning(s).",
+        path.display(),
+        findings.len() - errors
+    ));
+    if errors == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
     }
+}
 
-    for inst in font.get_instruments() {
-        log_instrument(inst);
+fn render_file(out: &Output, args: &RenderArgs) -> ExitCode {
+    let Some((font, middle_c)) = load_font(out, &args.input) else {
+        return ExitCode::FAILURE;
+    };
+    let score = match &args.midi {
+        Some(midi) => Score::read_midi(midi),
+        None => Note::parse_sequence(&args.notes, middle_c).map(Score::Notes),
+    };
+    let settings = RenderSettings {
+        sample_rate: args.sample_rate,
+        tail: args.tail,
+        ..Default::default()
+    };
+    let preset = args.preset.as_deref().map(PresetSelector::parse);
+    let result = score
+        .and_then(|score| render(&font, preset.as_ref(), &score, &settings))
+        .and_then(|rendering| rendering.save(&args.output));
+    match result {
+        Ok(()) => {
+            out.status(format!("Finished: {}", args.output.display()));
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            out.compile_error(&error);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    for sample in font.get_sample_headers() {
-        log_sample(sample);
+/// Run every audio test in the project. Fails if any test fails or can't run.
+fn run_tests(out: &Output, path: &Path, bless: bool) -> ExitCode {
+    let fontdata = match FontData::read(path) {
+        Ok(fontdata) => fontdata,
+        Err(errors) => return compile_failed(out, &errors),
+    };
+    let tests = match AudioTest::read_dir(fontdata.tests_dir()) {
+        Ok(tests) => tests,
+        Err(errors) => return compile_failed(out, &errors),
+    };
+    let font = fontdata.generate_soundfont();
+
+    let mut failed = 0;
+    for test in &tests {
+        let name = test.name();
+        out.progress(format!("test {name} ... "));
+        let outcome = match test.run(&font, fontdata.middle_c(), bless) {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                out.status("ERR");
+                out.compile_error(&error);
+                failed += 1;
+                continue;
+            }
+        };
+        let result = match &outcome {
+            Outcome::Passed(difference) | Outcome::Failed(difference) => {
+                format!("rms {:.6}, peak {:.6}", difference.rms, difference.peak)
+            }
+            Outcome::Mismatched => "length or sample rate differs from the reference".into(),
+            Outcome::NoReference => "no reference, --bless to save one".into(),
+            Outcome::Blessed => format!("saved {}", test.reference_path().display()),
+        };
+        if outcome.passed() {
+            out.status(format!("ok ({result})"));
+        } else {
+            out.status("FAILED");
+            out.error(format!("{name}: {result}"));
+            failed += 1;
+        }
     }
 
-    //println!("{:02x?}", font.get_wave_data())
+    out.status(format!("{} passed, {failed} failed.", tests.len() - failed));
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
 
-fn log_sample(sample: &rustysynth::SampleHeader) {
-    print!("Sample - ");
-    println!("{}", sample.get_name());
-    println!("  start: {}", sample.get_start());
-    println!("  end: {}", sample.get_end());
-    println!("  start_loop: {}", sample.get_start_loop());
-    println!("  end_loop: {}", sample.get_end_loop());
-    println!("  sample_rate: {}", sample.get_sample_rate());
-    println!("  original_pitch: {}", sample.get_original_pitch());
-    println!("  pitch_correction: {}", sample.get_pitch_correction());
-    println!("  link: {}", sample.get_link());
-    println!("  sample_type: {}", sample.get_sample_type());
+/// A .sf2 file as is, or a project compiled in memory. Prints the errors if that fails.
+fn load_font(out: &Output, path: &Path) -> Option<(SoundFont2, MiddleC)> {
+    let is_sf2 = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("sf2"));
+    if is_sf2 {
+        return match read_font(path) {
+            Ok(font) => Some((font, MiddleC::default())),
+            Err(error) => {
+                out.compile_error(&error);
+                None
+            }
+        };
+    }
+    match FontData::read(path) {
+        Ok(fontdata) => Some((fontdata.generate_soundfont(), fontdata.middle_c())),
+        Err(errors) => {
+            compile_failed(out, &errors);
+            None
+        }
+    }
 }
 
-fn log_instrument(inst: &rustysynth::Instrument) {
-    print!("Instrument - ");
-    println!("{}", inst.get_name());
-    println!("  Zones: {}", inst.get_regions().len());
-    for region in inst.get_regions() {
-        println!("      i-zone: ");
-        println!("          sample_id: {}", region.get_sample_id());
-        println!("          sample_start: {}", region.get_sample_start());
-        println!("          sample_end: {}", region.get_sample_end());
-        println!(
-            "          sample_start_loop: {}",
-            region.get_sample_start_loop()
-        );
-        println!(
-            "          sample_end_loop: {}",
-            region.get_sample_end_loop()
-        );
+fn compile_failed(out: &Output, errors: &[CompilerError]) -> ExitCode {
+    for error in errors {
+        out.compile_error(error);
     }
+    out.error_summary(format!("Compiling failed with {} error(s).", errors.len()));
+    ExitCode::FAILURE
+}
+
+fn read_font(path: &Path) -> Result<SoundFont2, CompilerError> {
+    let bytes = std::fs::read(path).map_err(|e| cant_read(path, e))?;
+    SoundFont2::from_bytes(&bytes).map_err(|e| cant_read(path, e))
 }
 
-fn log_preset(preset: &rustysynth::Preset) {
-    print!("Preset - ");
-    println!("{}", preset.get_name());
-    println!("  Zones: {}", preset.get_regions().len());
-    for region in preset.get_regions() {
-        println!("      p-zone: ");
-        println!("          inst_id: {}", region.get_instrument_id());
+fn cant_read(path: &Path, reason: impl ToString) -> CompilerError {
+    ErrorKind::SoundFontCantRead {
+        path: path.display().to_string(),
+        reason: reason.to_string(),
     }
+    .into()
 }
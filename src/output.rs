@@ -0,0 +1,88 @@
+use clap::ColorChoice;
+use std::{
+    fmt::Display,
+    io::{IsTerminal, Write},
+};
+
+use soundfont_compiler::{
+    compiler::CompilerError,
+    soundfont::{Finding, Severity},
+};
+
+/// Printing that follows `--quiet` and `--color`. Progress and results go to stdout, errors and
+/// warnings to stderr.
+pub struct Output {
+    quiet: bool,
+    color: bool,
+}
+
+impl Output {
+    /// Auto colors only a terminal, and respects `NO_COLOR`.
+    pub fn new(quiet: bool, color: ColorChoice) -> Self {
+        let color = match color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+            }
+        };
+        Self { quiet, color }
+    }
+
+    /// A line of progress or results, unless quiet
+    pub fn status(&self, text: impl Display) {
+        if !self.quiet {
+            println!("{text}");
+        }
+    }
+
+    /// The start of a line of progress, finished by [Output::status]
+    pub fn progress(&self, text: impl Display) {
+        if !self.quiet {
+            print!("{text}");
+            let _ = std::io::stdout().flush();
+        }
+    }
+
+    pub fn compile_error(&self, error: &CompilerError) {
+        eprint!("{}", error.render_styled(self.color));
+    }
+
+    /// A closing line after errors, e.g. how many there were. Left out if quiet.
+    pub fn error_summary(&self, text: impl Display) {
+        if !self.quiet {
+            eprintln!("{text}");
+        }
+    }
+
+    pub fn error(&self, text: impl Display) {
+        eprintln!("{}: {text}", self.paint(Severity::Error));
+    }
+
+    /// Left out if quiet
+    pub fn warning(&self, text: impl Display) {
+        if !self.quiet {
+            eprintln!("{}: {text}", self.paint(Severity::Warning));
+        }
+    }
+
+    /// Warnings are left out if quiet.
+    pub fn finding(&self, finding: &Finding) {
+        if self.quiet && finding.severity() == Severity::Warning {
+            return;
+        }
+        eprintln!("{}: {finding}", self.paint(finding.severity()));
+    }
+
+    fn paint(&self, severity: Severity) -> String {
+        let style = match severity {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33",
+        };
+        if self.color {
+            format!("\x1b[{style}m{severity}\x1b[0m")
+        } else {
+            severity.to_string()
+        }
+    }
+}
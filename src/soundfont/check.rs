@@ -0,0 +1,871 @@
+//! Structural validation of whole SoundFont 2 files
+
+use riff::ChunkId;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    ops::Range,
+};
+
+use super::{
+    pdta::{
+        GenAmountType, Generator, GeneratorLevel, InstrumentMod, PresetMod, SFSampleLink,
+        SampleHeader,
+    },
+    subchunks, ParseError, SoundFont2, SoundfontError,
+};
+
+/// How bad a finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Synths are likely to cope, but the file bends the spec.
+    Warning,
+    /// The file breaks the spec. Synths may reject it or play it wrong.
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Something wrong with a SoundFont file
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub chunk: ChunkId,
+    /// Index of the record within the chunk, if it's about one
+    pub record: Option<usize>,
+    /// Name of the preset, instrument or sample it's about
+    pub name: Option<String>,
+    pub kind: FindingKind,
+}
+
+impl Finding {
+    fn new(chunk: [u8; 4], kind: FindingKind) -> Self {
+        Self {
+            chunk: ChunkId { value: chunk },
+            record: None,
+            name: None,
+            kind,
+        }
+    }
+
+    fn at(mut self, record: usize, name: &str) -> Self {
+        self.record = Some(record);
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "In chunk {}", self.chunk)?;
+        if let Some(record) = self.record {
+            write!(f, " record {record}")?;
+        }
+        if let Some(name) = &self.name {
+            write!(f, " ('{name}')")?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FindingKind {
+    /// The file couldn't be read, so nothing else was checked.
+    Parse(ParseError),
+    /// Bytes after the RIFF chunk
+    TrailingData {
+        len: usize,
+    },
+
+    StringUnterminated,
+    StringOddLength {
+        len: usize,
+    },
+    StringTooLong {
+        len: usize,
+        limit: usize,
+    },
+    StringNonAscii,
+    VersionUnsupported {
+        major: u16,
+        minor: u16,
+    },
+    /// sm24 is only defined from version 2.04 on
+    Sm24Version,
+    Sm24Length {
+        len: usize,
+        points: usize,
+    },
+
+    MissingTerminal,
+    TerminalName {
+        name: String,
+        expected: &'static str,
+    },
+    TerminalNotZero,
+    IndexDecreasing {
+        index: u16,
+        previous: u16,
+    },
+    IndexOutOfBounds {
+        index: usize,
+        len: usize,
+    },
+    /// The terminal record must point at the terminal record of the next list
+    TerminalIndex {
+        index: u16,
+        expected: usize,
+    },
+
+    GeneratorNotFirst {
+        generator: Generator,
+    },
+    GeneratorNotLast {
+        generator: Generator,
+    },
+    GeneratorDuplicate {
+        generator: Generator,
+    },
+    Generator(SoundfontError),
+    /// A zone after the first without an instrument or sample. Synths ignore it.
+    ZoneWithoutTarget {
+        target: Generator,
+    },
+    TargetOutOfBounds {
+        target: Generator,
+        index: u16,
+        len: usize,
+    },
+    DuplicatePreset {
+        bank: u16,
+        preset: u16,
+        first: usize,
+    },
+
+    Sample(SoundfontError),
+    SampleOutOfBounds {
+        start: u32,
+        end: u32,
+        len: usize,
+    },
+    SamplePadding,
+    SampleRate {
+        rate: u32,
+    },
+    OriginalPitch {
+        pitch: u8,
+    },
+    SampleType {
+        value: u16,
+    },
+    RomWithoutIrom,
+    LinkOutOfBounds {
+        link: u16,
+        len: usize,
+    },
+    LinkMismatch {
+        link: u16,
+    },
+}
+
+impl FindingKind {
+    pub fn severity(&self) -> Severity {
+        use FindingKind::*;
+        match self {
+            TrailingData { .. }
+            | StringOddLength { .. }
+            | StringNonAscii
+            | VersionUnsupported { .. }
+            | Sm24Version
+            | Sm24Length { .. }
+            | TerminalName { .. }
+            | TerminalNotZero
+            | GeneratorDuplicate { .. }
+            | ZoneWithoutTarget { .. }
+            | SamplePadding
+            | OriginalPitch { .. }
+            | SampleType { .. }
+            | RomWithoutIrom
+            | LinkMismatch { .. } => Severity::Warning,
+            // Synths skip operators the spec doesn't define
+            Generator(SoundfontError::GeneratorNotAllowed {
+                generator: super::pdta::Generator::Unknown(_),
+                ..
+            }) => Severity::Warning,
+            // Synths clamp these
+            Generator(SoundfontError::GeneratorOutOfRange { .. }) => Severity::Warning,
+            Sample(SoundfontError::SampleTooShort) => Severity::Error,
+            // Loop rules only matter if the sample is looped
+            Sample(_) => Severity::Warning,
+            SampleRate { rate } if *rate != 0 => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl Display for FindingKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FindingKind::Parse(err) => write!(f, "Can't read the file. {err}"),
+            FindingKind::TrailingData { len } => {
+                write!(f, "{len} bytes after the end of the RIFF chunk.")
+            }
+            FindingKind::StringUnterminated => write!(f, "String isn't null terminated."),
+            FindingKind::StringOddLength { len } => {
+                write!(f, "String is {len} bytes long. It must be even.")
+            }
+            FindingKind::StringTooLong { len, limit } => write!(
+                f,
+                "String is {len} bytes long. It must fit into {limit} bytes, terminator included."
+            ),
+            FindingKind::StringNonAscii => write!(f, "Non-ascii string."),
+            FindingKind::VersionUnsupported { major, minor } => write!(
+                f,
+                "Version {major}.{minor:02} isn't SoundFont 2. Major version must be 2."
+            ),
+            FindingKind::Sm24Version => {
+                write!(f, "sm24 requires version 2.04 or later. Synths may ignore it.")
+            }
+            FindingKind::Sm24Length { len, points } => write!(
+                f,
+                "sm24 is {len} bytes long, but smpl has {points} points. Synths will ignore it."
+            ),
+            FindingKind::MissingTerminal => write!(f, "The terminal record is missing."),
+            FindingKind::TerminalName { name, expected } => {
+                write!(f, "Terminal record is named '{name}', expected '{expected}'.")
+            }
+            FindingKind::TerminalNotZero => write!(f, "Terminal record must be all zero."),
+            FindingKind::IndexDecreasing { index, previous } => write!(
+                f,
+                "Index {index} is less than the previous record's {previous}. Indices must not decrease."
+            ),
+            FindingKind::IndexOutOfBounds { index, len } => {
+                write!(f, "Index {index} is out of bounds. There are {len} records.")
+            }
+            FindingKind::TerminalIndex { index, expected } => write!(
+                f,
+                "Terminal record points at {index}, but must point at the last record, {expected}."
+            ),
+            FindingKind::GeneratorNotFirst { generator } => write!(
+                f,
+                "Generator '{generator}' must come first in its zone, only preceded by key_range."
+            ),
+            FindingKind::GeneratorNotLast { generator } => {
+                write!(f, "Generator '{generator}' must be the last in its zone.")
+            }
+            FindingKind::GeneratorDuplicate { generator } => write!(
+                f,
+                "Generator '{generator}' appears more than once in its zone. Only the last counts."
+            ),
+            FindingKind::Generator(err) | FindingKind::Sample(err) => err.fmt(f),
+            FindingKind::ZoneWithoutTarget { target } => write!(
+                f,
+                "Zone has no '{target}' generator, and isn't the first. Synths ignore it."
+            ),
+            FindingKind::TargetOutOfBounds { target, index, len } => write!(
+                f,
+                "Generator '{target}' points at {index}, but there are only {len}."
+            ),
+            FindingKind::DuplicatePreset {
+                bank,
+                preset,
+                first,
+            } => write!(
+                f,
+                "Bank {bank} preset {preset} is already taken by record {first}."
+            ),
+            FindingKind::SampleOutOfBounds { start, end, len } => write!(
+                f,
+                "Sample spans {start}..{end}, but smpl only has {len} points."
+            ),
+            FindingKind::SamplePadding => {
+                write!(f, "Sample must be followed by at least 46 zero points.")
+            }
+            FindingKind::SampleRate { rate: 0 } => write!(f, "Sample rate must not be zero."),
+            FindingKind::SampleRate { rate } => write!(
+                f,
+                "Sample rate {rate} Hz is outside 400..=50000. Synths may not reproduce it."
+            ),
+            FindingKind::OriginalPitch { pitch } => {
+                write!(f, "Original pitch {pitch} must be 0..=127, or 255 if unpitched.")
+            }
+            FindingKind::SampleType { value } => write!(f, "Unknown sample type {value}."),
+            FindingKind::RomWithoutIrom => write!(
+                f,
+                "ROM sample, but the INFO list doesn't name a ROM with irom."
+            ),
+            FindingKind::LinkOutOfBounds { link, len } => write!(
+                f,
+                "Sample links to {link}, but there are only {len} samples."
+            ),
+            FindingKind::LinkMismatch { link } => write!(
+                f,
+                "Sample links to {link}, which isn't the other channel or doesn't link back."
+            ),
+        }
+    }
+}
+
+/// Check a whole SoundFont 2 file, including the parts that don't survive reading it into a
+/// [SoundFont2], like string terminators. Findings come in file order.
+pub fn check(bytes: &[u8]) -> Vec<Finding> {
+    let font = match SoundFont2::from_bytes(bytes) {
+        Ok(font) => font,
+        Err(err) => return vec![Finding::new(err.chunk.value, FindingKind::Parse(err))],
+    };
+
+    // Reading succeeded, so the RIFF header is there and its length fits.
+    let mut findings = vec![];
+    let riff_len = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+    let riff_end = 8 + riff_len + riff_len % 2;
+    if bytes.len() > riff_end {
+        let kind = FindingKind::TrailingData {
+            len: bytes.len() - riff_end,
+        };
+        findings.push(Finding::new(riff::RIFF_ID.value, kind));
+    }
+
+    let lists = subchunks(&bytes[12..8 + riff_len]).unwrap_or_default();
+    let info = lists
+        .iter()
+        .find(|list| list.id == riff::LIST_ID && list.contents.starts_with(b"INFO"));
+    if let Some(info) = info {
+        for chunk in subchunks(&info.contents[4..]).unwrap_or_default() {
+            let limit = match &chunk.id.value {
+                b"ICMT" => 65536,
+                b"isng" | b"INAM" | b"irom" | b"ICRD" | b"IENG" | b"IPRD" | b"ICOP" | b"ISFT" => {
+                    256
+                }
+                _ => continue,
+            };
+            check_string(chunk.contents, limit)
+                .into_iter()
+                .for_each(|kind| findings.push(Finding::new(chunk.id.value, kind)));
+        }
+    }
+
+    findings.extend(font.check());
+    findings
+}
+
+fn check_string(bytes: &[u8], limit: usize) -> Vec<FindingKind> {
+    let mut kinds = vec![];
+    if bytes.last() != Some(&0) {
+        kinds.push(FindingKind::StringUnterminated);
+    }
+    if !bytes.len().is_multiple_of(2) {
+        kinds.push(FindingKind::StringOddLength { len: bytes.len() });
+    }
+    if bytes.len() > limit {
+        kinds.push(FindingKind::StringTooLong {
+            len: bytes.len(),
+            limit,
+        });
+    }
+    if !bytes.is_ascii() {
+        kinds.push(FindingKind::StringNonAscii);
+    }
+    kinds
+}
+
+impl SoundFont2 {
+    /// Check the font against the rules of the spec. See [check] for checking a file.
+    pub fn check(&self) -> Vec<Finding> {
+        let mut findings = vec![];
+        self.check_info(&mut findings);
+
+        let pdta = &self.pdta;
+        let instrument_count = pdta.inst.contents.len().saturating_sub(1);
+        let sample_count = pdta.shdr.contents.len().saturating_sub(1);
+        let presets = Hydra {
+            level: GeneratorLevel::Preset,
+            chunks: [*b"phdr", *b"pbag", *b"pgen", *b"pmod"],
+            terminal_name: "EOP",
+            headers: pdta
+                .phdr
+                .contents
+                .iter()
+                .map(|header| (header.name.as_str(), header.pbag_idx))
+                .collect(),
+            bags: pdta
+                .pbag
+                .contents
+                .iter()
+                .map(|bag| (bag.gen_idx, bag.mod_idx))
+                .collect(),
+            gens: pdta
+                .pgen
+                .contents
+                .iter()
+                .map(|gen| (gen.sf_gen_oper, gen.gen_amount))
+                .collect(),
+            mods_terminal_zero: pdta
+                .pmod
+                .contents
+                .last()
+                .map(|m| *m == PresetMod::terminal()),
+            mod_count: pdta.pmod.contents.len(),
+            target: Generator::Instrument,
+            target_count: instrument_count,
+        };
+        let instruments = Hydra {
+            level: GeneratorLevel::Instrument,
+            chunks: [*b"inst", *b"ibag", *b"igen", *b"imod"],
+            terminal_name: "EOI",
+            headers: pdta
+                .inst
+                .contents
+                .iter()
+                .map(|header| (header.name.as_str(), header.inst_bag_idx))
+                .collect(),
+            bags: pdta
+                .ibag
+                .contents
+                .iter()
+                .map(|bag| (bag.gen_idx, bag.mod_idx))
+                .collect(),
+            gens: pdta
+                .igen
+                .contents
+                .iter()
+                .map(|gen| (gen.sf_gen_oper, gen.gen_amount))
+                .collect(),
+            mods_terminal_zero: pdta
+                .imod
+                .contents
+                .last()
+                .map(|m| *m == InstrumentMod::terminal()),
+            mod_count: pdta.imod.contents.len(),
+            target: Generator::SampleId,
+            target_count: sample_count,
+        };
+        presets.check(&mut findings);
+        instruments.check(&mut findings);
+
+        let mut taken = HashMap::new();
+        let preset_count = pdta.phdr.contents.len().saturating_sub(1);
+        for (i, header) in pdta.phdr.contents[..preset_count].iter().enumerate() {
+            if let Some(&first) = taken.get(&(header.bank, header.preset)) {
+                let kind = FindingKind::DuplicatePreset {
+                    bank: header.bank,
+                    preset: header.preset,
+                    first,
+                };
+                findings.push(Finding::new(*b"phdr", kind).at(i, &header.name));
+            } else {
+                taken.insert((header.bank, header.preset), i);
+            }
+        }
+
+        self.check_samples(&mut findings);
+        findings
+    }
+
+    fn check_info(&self, findings: &mut Vec<Finding>) {
+        let version = self.info.ifil();
+        if version.major != 2 {
+            let kind = FindingKind::VersionUnsupported {
+                major: version.major,
+                minor: version.minor,
+            };
+            findings.push(Finding::new(*b"ifil", kind));
+        }
+
+        if let Some(sm24) = &self.sdta.sm24 {
+            if version.major == 2 && version.minor < 4 {
+                findings.push(Finding::new(*b"sm24", FindingKind::Sm24Version));
+            }
+            // The pad byte may be counted
+            let points = self.sdta.smpl.len();
+            if sm24.len() != points && sm24.len() != points + points % 2 {
+                let kind = FindingKind::Sm24Length {
+                    len: sm24.len(),
+                    points,
+                };
+                findings.push(Finding::new(*b"sm24", kind));
+            }
+        }
+    }
+
+    fn check_samples(&self, findings: &mut Vec<Finding>) {
+        let headers = &self.pdta.shdr.contents;
+        let Some((terminal, headers)) = headers.split_last() else {
+            findings.push(Finding::new(*b"shdr", FindingKind::MissingTerminal));
+            return;
+        };
+        let terminal_at = |kind| Finding::new(*b"shdr", kind).at(headers.len(), &terminal.name);
+        if terminal.name != "EOS" {
+            findings.push(terminal_at(FindingKind::TerminalName {
+                name: terminal.name.clone(),
+                expected: "EOS",
+            }));
+        } else if let Err(err) = terminal.validate() {
+            findings.push(terminal_at(FindingKind::Sample(err)));
+        }
+
+        let smpl = &self.sdta.smpl;
+        let mut padded = HashSet::new();
+        for (i, header) in headers.iter().enumerate() {
+            let mut push = |kind| findings.push(Finding::new(*b"shdr", kind).at(i, &header.name));
+
+            if header.sample_rate == 0 || !(400..=50000).contains(&header.sample_rate) {
+                push(FindingKind::SampleRate {
+                    rate: header.sample_rate,
+                });
+            }
+            if header.original_pitch > 127 && header.original_pitch != 255 {
+                push(FindingKind::OriginalPitch {
+                    pitch: header.original_pitch,
+                });
+            }
+            if let Some(kind) = check_link(header, i, headers) {
+                push(kind);
+            }
+
+            // ROM samples aren't in smpl
+            if header.sample_type & 0x8000 != 0 {
+                if self.info.irom().is_none() {
+                    push(FindingKind::RomWithoutIrom);
+                }
+                continue;
+            }
+            if header.start > header.end || header.end as usize > smpl.len() {
+                push(FindingKind::SampleOutOfBounds {
+                    start: header.start,
+                    end: header.end,
+                    len: smpl.len(),
+                });
+                continue;
+            }
+            if let Err(err) = header.validate() {
+                push(FindingKind::Sample(err));
+            }
+            // Samples may share data. Check the padding once.
+            if padded.insert(header.end) {
+                let padding = smpl.get(header.end as usize..header.end as usize + 46);
+                if !padding.is_some_and(|points| points.iter().all(|point| *point == 0)) {
+                    push(FindingKind::SamplePadding);
+                }
+            }
+        }
+    }
+}
+
+/// Type and link of a sample. Left and right channels must link to each other.
+fn check_link(header: &SampleHeader, idx: usize, headers: &[SampleHeader]) -> Option<FindingKind> {
+    // The ROM bit doesn't matter here
+    let sample_type = header.sample_type & 0x7fff;
+    let partner_type = match sample_type {
+        t if t == SFSampleLink::Mono as u16 => return None,
+        t if t == SFSampleLink::Left as u16 => SFSampleLink::Right as u16,
+        t if t == SFSampleLink::Right as u16 => SFSampleLink::Left as u16,
+        t if t == SFSampleLink::Linked as u16 => SFSampleLink::Linked as u16,
+        _ => {
+            return Some(FindingKind::SampleType {
+                value: header.sample_type,
+            })
+        }
+    };
+
+    let link = header.sample_link;
+    let Some(partner) = headers.get(link as usize) else {
+        return Some(FindingKind::LinkOutOfBounds {
+            link,
+            len: headers.len(),
+        });
+    };
+    // Linked samples form chains, which aren't followed
+    if sample_type == SFSampleLink::Linked as u16 {
+        return None;
+    }
+    if partner.sample_type & 0x7fff != partner_type || partner.sample_link as usize != idx {
+        return Some(FindingKind::LinkMismatch { link });
+    }
+    None
+}
+
+/// One level of the hydra: preset or instrument headers, their zones, generators and modulators.
+struct Hydra<'a> {
+    level: GeneratorLevel,
+    /// Header, bag, generator and modulator chunk ids
+    chunks: [[u8; 4]; 4],
+    terminal_name: &'static str,
+    /// Name and first zone of each header
+    headers: Vec<(&'a str, u16)>,
+    /// First generator and modulator of each zone
+    bags: Vec<(u16, u16)>,
+    gens: Vec<(Generator, GenAmountType)>,
+    /// Whether the terminal modulator is all zero
+    mods_terminal_zero: Option<bool>,
+    mod_count: usize,
+    /// Generator that ends a zone, pointing at an instrument or sample
+    target: Generator,
+    target_count: usize,
+}
+
+impl Hydra<'_> {
+    fn check(&self, findings: &mut Vec<Finding>) {
+        let [header_chunk, bag_chunk, gen_chunk, mod_chunk] = self.chunks;
+
+        let lists = [
+            (header_chunk, self.headers.len()),
+            (bag_chunk, self.bags.len()),
+            (gen_chunk, self.gens.len()),
+            (mod_chunk, self.mod_count),
+        ];
+        let missing: Vec<Finding> = lists
+            .iter()
+            .filter(|(_, len)| *len == 0)
+            .map(|(chunk, _)| Finding::new(*chunk, FindingKind::MissingTerminal))
+            .collect();
+        if !missing.is_empty() {
+            findings.extend(missing);
+            return;
+        }
+
+        let (terminal_name, _) = self.headers[self.headers.len() - 1];
+        if terminal_name != self.terminal_name {
+            let kind = FindingKind::TerminalName {
+                name: terminal_name.to_string(),
+                expected: self.terminal_name,
+            };
+            findings
+                .push(Finding::new(header_chunk, kind).at(self.headers.len() - 1, terminal_name));
+        }
+        let (terminal_gen, terminal_amount) = self.gens[self.gens.len() - 1];
+        if terminal_gen.to_u16() != 0 || terminal_amount.to_i16() != 0 {
+            let finding = Finding::new(gen_chunk, FindingKind::TerminalNotZero);
+            findings.push(Finding {
+                record: Some(self.gens.len() - 1),
+                ..finding
+            });
+        }
+        if self.mods_terminal_zero == Some(false) {
+            let finding = Finding::new(mod_chunk, FindingKind::TerminalNotZero);
+            findings.push(Finding {
+                record: Some(self.mod_count - 1),
+                ..finding
+            });
+        }
+
+        // Zones can only be walked if all the indices are sound
+        let header_indices: Vec<u16> = self.headers.iter().map(|(_, bag)| *bag).collect();
+        let gen_indices: Vec<u16> = self.bags.iter().map(|(gen, _)| *gen).collect();
+        let mod_indices: Vec<u16> = self.bags.iter().map(|(_, m)| *m).collect();
+        let before = findings.len();
+        check_indices(header_chunk, &header_indices, self.bags.len(), findings);
+        check_indices(bag_chunk, &gen_indices, self.gens.len(), findings);
+        check_indices(bag_chunk, &mod_indices, self.mod_count, findings);
+        if findings.len() > before {
+            return;
+        }
+
+        for pair in self.headers.windows(2) {
+            let [(name, first), (_, next)] = pair else {
+                unreachable!()
+            };
+            for bag in *first as usize..*next as usize {
+                let gens = self.bags[bag].0 as usize..self.bags[bag + 1].0 as usize;
+                self.check_zone(gens, bag == *first as usize, name, findings);
+            }
+        }
+    }
+
+    /// Generator rules of one zone
+    fn check_zone(
+        &self,
+        range: Range<usize>,
+        first_zone: bool,
+        owner: &str,
+        findings: &mut Vec<Finding>,
+    ) {
+        let gens = &self.gens[range.clone()];
+        let mut push = |offset: usize, kind| {
+            findings.push(Finding::new(self.chunks[2], kind).at(range.start + offset, owner));
+        };
+
+        let mut seen = HashSet::new();
+        for (i, (generator, amount)) in gens.iter().enumerate() {
+            let generator = *generator;
+            let first = match generator {
+                Generator::KeyRange => i == 0,
+                Generator::VelRange => i == 0 || (i == 1 && gens[0].0 == Generator::KeyRange),
+                _ => true,
+            };
+            if !first {
+                push(i, FindingKind::GeneratorNotFirst { generator });
+            }
+            if generator == self.target && i != gens.len() - 1 {
+                push(i, FindingKind::GeneratorNotLast { generator });
+            }
+            if !seen.insert(generator) {
+                push(i, FindingKind::GeneratorDuplicate { generator });
+            }
+            if let Err(err) = generator.validate(amount, self.level) {
+                push(i, FindingKind::Generator(err));
+            }
+        }
+
+        match gens.last() {
+            Some((generator, amount)) if *generator == self.target => {
+                let index = amount.to_i16() as u16;
+                if index as usize >= self.target_count {
+                    let kind = FindingKind::TargetOutOfBounds {
+                        target: self.target,
+                        index,
+                        len: self.target_count,
+                    };
+                    push(gens.len() - 1, kind);
+                }
+            }
+            // The first zone may be global
+            _ if first_zone => (),
+            _ => push(
+                0,
+                FindingKind::ZoneWithoutTarget {
+                    target: self.target,
+                },
+            ),
+        }
+    }
+}
+
+/// Indices into the next list must not decrease, and the terminal record must point at the
+/// terminal record of the next list.
+fn check_indices(chunk: [u8; 4], indices: &[u16], next_len: usize, findings: &mut Vec<Finding>) {
+    let mut push = |record: usize, kind| {
+        let finding = Finding::new(chunk, kind);
+        findings.push(Finding {
+            record: Some(record),
+            ..finding
+        });
+    };
+
+    let terminal = indices.len() - 1;
+    for (i, &index) in indices.iter().enumerate() {
+        if i > 0 && index < indices[i - 1] {
+            let previous = indices[i - 1];
+            push(i, FindingKind::IndexDecreasing { index, previous });
+        } else if i == terminal && index as usize != next_len - 1 {
+            let kind = FindingKind::TerminalIndex {
+                index,
+                expected: next_len - 1,
+            };
+            push(i, kind);
+        } else if index as usize >= next_len {
+            let kind = FindingKind::IndexOutOfBounds {
+                index: index as usize,
+                len: next_len,
+            };
+            push(i, kind);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::soundfont::tests::{font, to_bytes};
+
+    /// The test font, with the padding the spec asks for
+    fn clean_font() -> SoundFont2 {
+        let mut font = font();
+        font.sdta.smpl[64..].fill(0);
+        font
+    }
+
+    fn kinds(font: &SoundFont2) -> Vec<FindingKind> {
+        check(&to_bytes(font))
+            .into_iter()
+            .map(|finding| finding.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_clean() {
+        assert!(kinds(&clean_font()).is_empty());
+        assert!(matches!(kinds(&font())[..], [FindingKind::SamplePadding]));
+    }
+
+    #[test]
+    fn test_hydra() {
+        let mut font = clean_font();
+        let header = font.pdta.phdr.contents[0].clone();
+        font.pdta.phdr.contents.insert(1, header);
+        font.pdta.phdr.contents[2].pbag_idx = 0;
+        font.pdta.igen.contents.swap(0, 1);
+        let kinds = kinds(&font);
+
+        assert!(kinds
+            .iter()
+            .any(|kind| matches!(kind, FindingKind::DuplicatePreset { first: 0, .. })));
+        assert!(kinds
+            .iter()
+            .any(|kind| matches!(kind, FindingKind::TerminalIndex { index: 0, .. })));
+        assert!(kinds.iter().any(|kind| matches!(
+            kind,
+            FindingKind::GeneratorNotLast {
+                generator: Generator::SampleId
+            }
+        )));
+        assert!(kinds.iter().any(|kind| matches!(
+            kind,
+            FindingKind::GeneratorNotFirst {
+                generator: Generator::KeyRange
+            }
+        )));
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut font = clean_font();
+        let sample = &mut font.pdta.shdr.contents[0];
+        sample.sample_type = SFSampleLink::Left as u16;
+        sample.sample_link = 5;
+        sample.end = 500;
+        let findings = check(&to_bytes(&font));
+
+        assert!(findings
+            .iter()
+            .all(|finding| finding.name.as_deref() == Some("Sine")));
+        assert!(matches!(
+            findings[..],
+            [
+                Finding {
+                    kind: FindingKind::LinkOutOfBounds { link: 5, len: 1 },
+                    ..
+                },
+                Finding {
+                    kind: FindingKind::SampleOutOfBounds { end: 500, .. },
+                    ..
+                }
+            ]
+        ));
+        assert!(findings
+            .iter()
+            .all(|finding| finding.severity() == Severity::Error));
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut bytes = to_bytes(&clean_font());
+        let inam = bytes.windows(4).position(|id| id == b"INAM").unwrap() + 8;
+        // "Round Trip\0\0"
+        bytes[inam + 10..inam + 12].copy_from_slice(b"!!");
+        let findings = check(&bytes);
+
+        assert_eq!(findings.len(), 1);
+        assert!(matches!(findings[0].kind, FindingKind::StringUnterminated));
+        assert_eq!(findings[0].chunk.value, *b"INAM");
+    }
+}
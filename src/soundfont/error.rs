@@ -1,15 +1,44 @@
+use riff::ChunkId;
 use std::{error::Error, fmt::Display};
 
+use super::pdta::{Generator, GeneratorLevel};
+
 #[derive(Debug, Clone)]
 pub enum SoundfontError {
     StringNonAscii,
-    StringLimit { limit: usize, len: usize },
+    StringLimit {
+        limit: usize,
+        len: usize,
+    },
+    /// A list chunk without records, not even the terminal one
+    ChunkEmpty {
+        id: ChunkId,
+    },
 
     SampleTooShort,
     SampleLoopTooShort,
     SampleLoopNotEnoughLead,
     SampleLoopNotEnoughTail,
     SampleTerminalNotNull,
+
+    GeneratorNotAllowed {
+        generator: Generator,
+        level: GeneratorLevel,
+    },
+    GeneratorAmountType {
+        generator: Generator,
+    },
+    GeneratorOutOfRange {
+        generator: Generator,
+        value: i32,
+        min: i32,
+        max: i32,
+    },
+    GeneratorInvalidRange {
+        generator: Generator,
+        lo: u8,
+        hi: u8,
+    },
 }
 
 impl Display for SoundfontError {
@@ -22,6 +51,12 @@ impl Display for SoundfontError {
                     "This string is too long. It must fit into {limit} bytes, but was {len} bytes long."
                 )
             }
+            SoundfontError::ChunkEmpty { id } => {
+                write!(
+                    f,
+                    "The {id} chunk is empty. It must end in a terminal record."
+                )
+            }
 
             SoundfontError::SampleTooShort => {
                 write!(f, "Sample data must be at least 48 data points long.")
@@ -30,16 +65,136 @@ impl Display for SoundfontError {
                 write!(f, "The loop must be at least 32 data points long.")
             }
             SoundfontError::SampleLoopNotEnoughLead => {
-                write!(f, "There must be at least 8 data points before startloop")
+                write!(f, "There must be at least 8 data points before startloop.")
             }
             SoundfontError::SampleLoopNotEnoughTail => {
-                write!(f, "There must be at least 8 data points after endloop")
+                write!(f, "There must be at least 8 data points after endloop.")
             }
             SoundfontError::SampleTerminalNotNull => {
                 write!(f, "Terminal sample must be null.")
             }
+
+            SoundfontError::GeneratorNotAllowed { generator, level } => {
+                write!(
+                    f,
+                    "Generator '{generator}' is not allowed in {level} zones."
+                )
+            }
+            SoundfontError::GeneratorAmountType { generator } => {
+                write!(
+                    f,
+                    "Generator '{generator}' was given the wrong type of amount."
+                )
+            }
+            SoundfontError::GeneratorOutOfRange {
+                generator,
+                value,
+                min,
+                max,
+            } => {
+                write!(
+                    f,
+                    "Generator '{generator}' value {value} is out of range. It must be within {min}..={max}."
+                )
+            }
+            SoundfontError::GeneratorInvalidRange { generator, lo, hi } => {
+                write!(
+                    f,
+                    "Generator '{generator}' range {lo}..={hi} is invalid. Bounds must be within 0..=127, low first."
+                )
+            }
         }
     }
 }
 
 impl Error for SoundfontError {}
+
+/// Why a SoundFont file couldn't be read
+#[derive(Debug, Clone)]
+pub enum ParseErrorKind {
+    Io {
+        reason: String,
+    },
+    /// The file isn't a RIFF file of form type `sfbk`
+    NotSoundFont,
+    MissingChunk {
+        id: ChunkId,
+    },
+    /// A chunk header claims more bytes than its parent has left
+    ChunkTruncated {
+        len: u32,
+        available: usize,
+    },
+    ChunkLength {
+        len: usize,
+        expected: usize,
+    },
+    /// A chunk of fixed size records isn't a whole number of records long
+    ChunkSize {
+        len: usize,
+        record_size: usize,
+    },
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseErrorKind::Io { reason } => write!(f, "{reason}"),
+            ParseErrorKind::NotSoundFont => write!(f, "Not a SoundFont 2 file."),
+            ParseErrorKind::MissingChunk { id } => write!(f, "Required chunk {id} is missing."),
+            ParseErrorKind::ChunkTruncated { len, available } => write!(
+                f,
+                "Chunk is {len} bytes long, but only {available} bytes are left."
+            ),
+            ParseErrorKind::ChunkLength { len, expected } => write!(
+                f,
+                "Chunk is {len} bytes long, but must be {expected} bytes long."
+            ),
+            ParseErrorKind::ChunkSize { len, record_size } => write!(
+                f,
+                "Chunk is {len} bytes long, which isn't a multiple of the {record_size} byte record size."
+            ),
+        }
+    }
+}
+
+/// A SoundFont file read error, located in the file.
+///
+/// Errors are created relative to the bytes being parsed, and shifted by the offset of each
+/// containing chunk on their way up.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Innermost chunk the error is in
+    pub chunk: ChunkId,
+    /// Byte offset of the offending chunk or record
+    pub offset: u64,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, chunk: [u8; 4]) -> Self {
+        Self {
+            kind,
+            chunk: ChunkId { value: chunk },
+            offset: 0,
+        }
+    }
+
+    /// Shift the offset by the position of the bytes that were parsed.
+    pub fn offset_by(mut self, offset: u64) -> Self {
+        self.offset += offset;
+        self
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "In chunk {} at byte {}: {}",
+            self.chunk, self.offset, self.kind
+        )
+    }
+}
+
+impl Error for ParseError {}
@@ -5,7 +5,10 @@ mod version_tag;
 
 use riff::{ChunkContents, ChunkId};
 
-use super::{string_to_bytes, SoundfontError};
+use super::{
+    bytes_to_string, string_to_bytes, subchunks, ParseError, ParseErrorKind, RawChunk,
+    SoundfontError, Subchunk,
+};
 pub use version_tag::VersionTag;
 
 /// Represents SoundFont 2 INFO Chunk.
@@ -33,6 +36,8 @@ pub struct InfoList {
     icmt: Option<String>,
     /// Name of the software used to create the soundfont e.g. "SoundFont Compiler v0.0.0"
     isft: Option<String>,
+    /// Unknown subchunks, kept as-is
+    pub extra: Vec<RawChunk>,
 }
 
 impl Default for InfoList {
@@ -49,6 +54,7 @@ impl Default for InfoList {
             icop: None,
             icmt: None,
             isft: None,
+            extra: vec![],
         }
     }
 }
@@ -57,8 +63,8 @@ impl InfoList {
     pub fn to_riff(&self) -> ChunkContents {
         let mut contents = vec![
             ChunkContents::Data(ChunkId { value: *b"ifil" }, self.ifil.to_bytes()),
-            ChunkContents::Data(ChunkId { value: *b"isng" }, string_to_bytes(&self.inam)),
-            ChunkContents::Data(ChunkId { value: *b"INAM" }, string_to_bytes(&self.isng)),
+            ChunkContents::Data(ChunkId { value: *b"isng" }, string_to_bytes(&self.isng)),
+            ChunkContents::Data(ChunkId { value: *b"INAM" }, string_to_bytes(&self.inam)),
         ];
         if let Some(irom) = &self.irom {
             contents.push(ChunkContents::Data(
@@ -108,11 +114,65 @@ impl InfoList {
                 string_to_bytes(isft),
             ));
         }
+        contents.extend(self.extra.iter().map(RawChunk::to_riff));
 
         ChunkContents::Children(riff::LIST_ID, ChunkId { value: *b"INFO" }, contents)
     }
 }
 
+impl InfoList {
+    /// Parse the subchunks of an INFO-list. ifil and INAM are required, isng defaults to
+    /// "EMU8000" as the spec says.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ParseError> {
+        let mut info = Self::default();
+        let mut ifil = None;
+        let mut inam = None;
+        for Subchunk {
+            id,
+            offset,
+            contents,
+        } in subchunks(data)?
+        {
+            let string = || Some(bytes_to_string(contents));
+            match &id.value {
+                b"ifil" => ifil = Some(read_version(contents, id, offset)?),
+                b"isng" => info.isng = bytes_to_string(contents),
+                b"INAM" => inam = string(),
+                b"irom" => info.irom = string(),
+                b"iver" => info.iver = Some(read_version(contents, id, offset)?),
+                b"ICRD" => info.icrd = string(),
+                b"IENG" => info.ieng = string(),
+                b"IPRD" => info.iprd = string(),
+                b"ICOP" => info.icop = string(),
+                b"ICMT" => info.icmt = string(),
+                b"ISFT" => info.isft = string(),
+                _ => info.extra.push(RawChunk {
+                    id,
+                    data: contents.to_vec(),
+                }),
+            }
+        }
+
+        let missing = |id: &[u8; 4]| {
+            let id = ChunkId { value: *id };
+            ParseError::new(ParseErrorKind::MissingChunk { id }, *b"INFO")
+        };
+        info.ifil = ifil.ok_or_else(|| missing(b"ifil"))?;
+        info.inam = inam.ok_or_else(|| missing(b"INAM"))?;
+        Ok(info)
+    }
+}
+
+fn read_version(contents: &[u8], id: ChunkId, offset: usize) -> Result<VersionTag, ParseError> {
+    VersionTag::from_bytes(contents).ok_or_else(|| {
+        let kind = ParseErrorKind::ChunkLength {
+            len: contents.len(),
+            expected: 4,
+        };
+        ParseError::new(kind, id.value).offset_by(offset as u64)
+    })
+}
+
 impl InfoList {
     /// SoundFont spec version
     pub fn ifil(&self) -> &VersionTag {
@@ -175,24 +235,24 @@ impl InfoList {
         self.ifil = version
     }
 
-    /// String must be ascii and fit into 256 bytes.
+    /// String must be ascii and fit into 256 bytes with its terminator.
     pub fn set_isng(&mut self, engine: String) -> Result<(), SoundfontError> {
-        validate_string(&engine, 256)?;
+        validate_string(&engine, 255)?;
         self.isng = engine;
         Ok(())
     }
 
-    /// String must be ascii and fit into 256 bytes.
+    /// String must be ascii and fit into 256 bytes with its terminator.
     pub fn set_inam(&mut self, name: String) -> Result<(), SoundfontError> {
-        validate_string(&name, 256)?;
+        validate_string(&name, 255)?;
         self.inam = name;
         Ok(())
     }
 
-    /// String must be ascii and fit into 256 bytes.
+    /// String must be ascii and fit into 256 bytes with its terminator.
     pub fn set_irom(&mut self, rom_name: Option<String>) -> Result<(), SoundfontError> {
         if let Some(value) = &rom_name {
-            validate_string(value, 256)?;
+            validate_string(value, 255)?;
         }
         self.irom = rom_name;
         Ok(())
@@ -202,55 +262,55 @@ impl InfoList {
         self.iver = rom_version;
     }
 
-    /// String must be ascii and fit into 256 bytes.
+    /// String must be ascii and fit into 256 bytes with its terminator.
     pub fn set_icrd(&mut self, creation_date: Option<String>) -> Result<(), SoundfontError> {
         if let Some(value) = &creation_date {
-            validate_string(value, 256)?;
+            validate_string(value, 255)?;
         }
         self.icrd = creation_date;
         Ok(())
     }
 
-    /// String must be ascii and fit into 256 bytes.
+    /// String must be ascii and fit into 256 bytes with its terminator.
     pub fn set_ieng(&mut self, authors: Option<String>) -> Result<(), SoundfontError> {
         if let Some(value) = &authors {
-            validate_string(value, 256)?;
+            validate_string(value, 255)?;
         }
         self.ieng = authors;
         Ok(())
     }
 
-    /// String must be ascii and fit into 256 bytes.
+    /// String must be ascii and fit into 256 bytes with its terminator.
     pub fn set_iprd(&mut self, target_product: Option<String>) -> Result<(), SoundfontError> {
         if let Some(value) = &target_product {
-            validate_string(value, 256)?;
+            validate_string(value, 255)?;
         }
         self.iprd = target_product;
         Ok(())
     }
 
-    /// String must be ascii and fit into 256 bytes.
+    /// String must be ascii and fit into 256 bytes with its terminator.
     pub fn set_icop(&mut self, copyright: Option<String>) -> Result<(), SoundfontError> {
         if let Some(value) = &copyright {
-            validate_string(value, 256)?;
+            validate_string(value, 255)?;
         }
         self.icop = copyright;
         Ok(())
     }
 
-    /// String must be ascii and fit into 65_536 bytes.
+    /// String must be ascii and fit into 65_536 bytes with its terminator.
     pub fn set_icmt(&mut self, comments: Option<String>) -> Result<(), SoundfontError> {
         if let Some(value) = &comments {
-            validate_string(value, 65536)?;
+            validate_string(value, 65535)?;
         }
         self.icmt = comments;
         Ok(())
     }
 
-    /// String must be ascii and fit into 256 bytes.
+    /// String must be ascii and fit into 256 bytes with its terminator.
     pub fn set_isft(&mut self, software_name: Option<String>) -> Result<(), SoundfontError> {
         if let Some(value) = &software_name {
-            validate_string(value, 256)?;
+            validate_string(value, 255)?;
         }
         self.isft = software_name;
         Ok(())
@@ -262,7 +322,7 @@ pub fn validate_string(value: &str, limit: usize) -> Result<(), SoundfontError>
     if !value.is_ascii() {
         return Err(SoundfontError::StringNonAscii);
     }
-    let len = value.as_bytes().len();
+    let len = value.len();
     if len > limit {
         return Err(SoundfontError::StringLimit { limit, len });
     }
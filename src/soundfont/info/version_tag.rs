@@ -12,6 +12,15 @@ impl VersionTag {
     pub fn to_bytes(&self) -> Vec<u8> {
         [self.major.to_le_bytes(), self.minor.to_le_bytes()].concat()
     }
+
+    /// None unless exactly 4 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let [major_lo, major_hi, minor_lo, minor_hi]: [u8; 4] = bytes.try_into().ok()?;
+        Some(Self {
+            major: u16::from_le_bytes([major_lo, major_hi]),
+            minor: u16::from_le_bytes([minor_lo, minor_hi]),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -23,4 +32,11 @@ mod tests {
         let tag = VersionTag::default();
         assert_eq!(tag.to_bytes().len(), 4);
     }
+
+    #[test]
+    fn test_from_bytes() {
+        let tag = VersionTag::from_bytes(&VersionTag::new(2, 4).to_bytes()).unwrap();
+        assert_eq!((tag.major, tag.minor), (2, 4));
+        assert!(VersionTag::from_bytes(&[2, 0, 4]).is_none());
+    }
 }
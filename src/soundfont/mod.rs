@@ -1,11 +1,19 @@
+mod check;
 mod error;
 pub mod info;
 pub mod pdta;
 pub mod sdta;
 
-use riff::{ChunkContents, ChunkId};
+use riff::{Chunk, ChunkContents, ChunkId};
+use std::{
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-pub use error::SoundfontError;
+pub use check::{check, Finding, FindingKind, Severity};
+pub use error::{ParseError, ParseErrorKind, SoundfontError};
 use info::InfoList;
 use pdta::PdtaList;
 use sdta::SdtaList;
@@ -15,45 +23,478 @@ pub struct SoundFont2 {
     pub info: InfoList,
     pub sdta: SdtaList,
     pub pdta: PdtaList,
+    /// Unknown chunks after the three lists, kept as-is
+    pub extra: Vec<RawChunk>,
 }
 
 impl SoundFont2 {
     pub fn new(info: InfoList, sdta: SdtaList, pdta: PdtaList) -> Self {
-        Self { info, sdta, pdta }
+        Self {
+            info,
+            sdta,
+            pdta,
+            extra: vec![],
+        }
     }
 
-    pub fn to_riff(&self) -> ChunkContents {
-        let contents = vec![
+    /// Read a SoundFont 2 file.
+    ///
+    /// Unknown chunks are kept, so writing the font back produces the same bytes, as long as
+    /// the file is well-formed: known chunks in spec order, strings null terminated and padded
+    /// to even length, and nothing after the terminator of a name. Non-ASCII characters in
+    /// strings are replaced with '?'.
+    pub fn from_reader<R>(reader: &mut R) -> Result<Self, ParseError>
+    where
+        R: Read + Seek,
+    {
+        let io_err = |err: io::Error, chunk: ChunkId, offset: u64| {
+            ParseError::new(
+                ParseErrorKind::Io {
+                    reason: err.to_string(),
+                },
+                chunk.value,
+            )
+            .offset_by(offset)
+        };
+
+        let file_len = reader
+            .seek(SeekFrom::End(0))
+            .map_err(|err| io_err(err, riff::RIFF_ID, 0))?;
+        let riff = Chunk::read(reader, 0).map_err(|err| io_err(err, riff::RIFF_ID, 0))?;
+        let form_type = riff
+            .read_type(reader)
+            .map_err(|err| io_err(err, riff::RIFF_ID, 0))?;
+        if riff.id() != riff::RIFF_ID || form_type.value != *b"sfbk" {
+            return Err(ParseError::new(
+                ParseErrorKind::NotSoundFont,
+                riff.id().value,
+            ));
+        }
+        check_len(&riff, file_len)?;
+
+        let mut info = None;
+        let mut sdta = None;
+        let mut pdta = None;
+        let mut extra = vec![];
+        let children: Vec<Chunk> = riff
+            .iter(reader)
+            .collect::<io::Result<_>>()
+            .map_err(|err| io_err(err, riff::RIFF_ID, 0))?;
+        for chunk in children {
+            check_len(&chunk, file_len)?;
+            let offset = chunk.offset();
+            let data = chunk
+                .read_contents(reader)
+                .map_err(|err| io_err(err, chunk.id(), offset))?;
+
+            let list_type = if chunk.id() == riff::LIST_ID {
+                data.get(..4)
+            } else {
+                None
+            };
+            // List contents start after the header and the list type
+            let located = |err: ParseError| err.offset_by(offset + 12);
+            match list_type {
+                Some(b"INFO") if info.is_none() => {
+                    info = Some(InfoList::from_bytes(&data[4..]).map_err(located)?)
+                }
+                Some(b"sdta") if sdta.is_none() => {
+                    sdta = Some(SdtaList::from_bytes(&data[4..]).map_err(located)?)
+                }
+                Some(b"pdta") if pdta.is_none() => {
+                    pdta = Some(PdtaList::from_bytes(&data[4..]).map_err(located)?)
+                }
+                _ => extra.push(RawChunk {
+                    id: chunk.id(),
+                    data,
+                }),
+            }
+        }
+
+        let missing = |id: &[u8; 4]| {
+            ParseError::new(
+                ParseErrorKind::MissingChunk {
+                    id: ChunkId { value: *id },
+                },
+                *b"sfbk",
+            )
+        };
+        Ok(Self {
+            info: info.ok_or_else(|| missing(b"INFO"))?,
+            sdta: sdta.ok_or_else(|| missing(b"sdta"))?,
+            pdta: pdta.ok_or_else(|| missing(b"pdta"))?,
+            extra,
+        })
+    }
+
+    /// Read a SoundFont 2 file from memory. See [SoundFont2::from_reader].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        Self::from_reader(&mut Cursor::new(bytes))
+    }
+
+    /// Fails if a pdta list is empty, or a name doesn't fit.
+    pub fn to_riff(&self) -> Result<ChunkContents, SoundfontError> {
+        let mut contents = vec![
             self.info.to_riff(),
             self.sdta.to_riff(),
-            self.pdta.to_riff(),
+            self.pdta.to_riff()?,
         ];
+        contents.extend(self.extra.iter().map(RawChunk::to_riff));
+
+        Ok(ChunkContents::Children(
+            riff::RIFF_ID,
+            ChunkId { value: *b"sfbk" },
+            contents,
+        ))
+    }
+
+    /// Write to a file atomically. The font is written into a temporary file next to the
+    /// target, and renamed over it only once complete.
+    pub fn save<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let temp_path = temp_path(path)?;
+
+        let riff = self
+            .to_riff()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let result = File::create(&temp_path).and_then(|mut file| {
+            riff.write(&mut file)?;
+            file.sync_all()
+        });
+        match result.and_then(|_| std::fs::rename(&temp_path, path)) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let _ = std::fs::remove_file(&temp_path);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A temporary file next to `path`. The name is unique to this process and call, so that
+/// concurrent saves to the same target don't write into each other's file.
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let Some(filename) = path.file_name() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Output path has no filename.",
+        ));
+    };
+    let mut temp_name = filename.to_os_string();
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    temp_name.push(format!(".{}-{count}.tmp", std::process::id()));
+    Ok(path.with_file_name(temp_name))
+}
 
-        ChunkContents::Children(riff::RIFF_ID, ChunkId { value: *b"sfbk" }, contents)
+/// A chunk kept as-is, e.g. one this crate doesn't know about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawChunk {
+    pub id: ChunkId,
+    pub data: Vec<u8>,
+}
+
+impl RawChunk {
+    pub fn to_riff(&self) -> ChunkContents {
+        ChunkContents::Data(self.id, self.data.clone())
+    }
+}
+
+/// Fails if the chunk claims more bytes than the file has left.
+fn check_len(chunk: &Chunk, file_len: u64) -> Result<(), ParseError> {
+    let available = file_len.saturating_sub(chunk.offset() + 8);
+    if chunk.len() as u64 > available {
+        let kind = ParseErrorKind::ChunkTruncated {
+            len: chunk.len(),
+            available: available as usize,
+        };
+        return Err(ParseError::new(kind, chunk.id().value).offset_by(chunk.offset()));
+    }
+    Ok(())
+}
+
+/// A subchunk within list contents
+struct Subchunk<'a> {
+    id: ChunkId,
+    /// Offset of the subchunk header within the list contents
+    offset: usize,
+    contents: &'a [u8],
+}
+
+/// Split list contents into subchunks.
+fn subchunks(data: &[u8]) -> Result<Vec<Subchunk<'_>>, ParseError> {
+    let mut chunks = vec![];
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let id = ChunkId {
+            value: data[pos..pos + 4].try_into().unwrap(),
+        };
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap());
+        let start = pos + 8;
+        let available = data.len() - start;
+        if len as usize > available {
+            let kind = ParseErrorKind::ChunkTruncated { len, available };
+            return Err(ParseError::new(kind, id.value).offset_by(pos as u64));
+        }
+        chunks.push(Subchunk {
+            id,
+            offset: pos,
+            contents: &data[start..start + len as usize],
+        });
+        // Odd length chunks are followed by a pad byte
+        pos = start + len as usize + len as usize % 2;
     }
+    Ok(chunks)
 }
 
-/// Convert to bytes, with SF2-compliant terminators.
+/// Convert to bytes, with SF2-compliant terminators: always null terminated, and padded to an
+/// even length.
 fn string_to_bytes(value: &String) -> Vec<u8> {
     let mut bytes = value.as_bytes().to_vec();
-    if bytes.len() % 2 != 0 {
+    bytes.push(0);
+    if !bytes.len().is_multiple_of(2) {
         bytes.push(0);
     }
     bytes
 }
 
-//
-// misplaced stuff below
-//
-
-#[allow(dead_code)]
-enum SFSampleLink {
-    Mono = 1,
-    Right = 2,
-    Left = 4,
-    Linked = 8,
-    RomMono = 0x8001,
-    RomRight = 0x8002,
-    RomLeft = 0x8004,
-    RomLinked = 0x8008,
+/// Read a string up to its terminator. Non-ASCII characters are replaced with '?'.
+fn bytes_to_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|byte| **byte != 0)
+        .map(|byte| if byte.is_ascii() { *byte as char } else { '?' })
+        .collect()
+}
+
+/// A record name as its 20 bytes. Written as read from the file while the name is unchanged, so
+/// non-ASCII bytes and anything after the terminator survive a round trip.
+fn name_to_bytes(name: &str, raw: &Option<[u8; 20]>) -> Result<[u8; 20], SoundfontError> {
+    if let Some(raw) = raw.filter(|raw| bytes_to_string(raw) == name) {
+        return Ok(raw);
+    }
+    let len = name.len();
+    if len > 20 {
+        return Err(SoundfontError::StringLimit { limit: 20, len });
+    }
+    let mut bytes = [0; 20];
+    bytes[..len].copy_from_slice(name.as_bytes());
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdta::{
+        GenAmountType, Generator, InstZone, Instrument, InstrumentGen, InstrumentMod,
+        ModDestination, ModTransform, ModulatorSource, PresetGen, PresetHeader, PresetMod,
+        PresetZone, SampleHeader,
+    };
+
+    pub(super) fn to_bytes(font: &SoundFont2) -> Vec<u8> {
+        let mut cursor = Cursor::new(vec![]);
+        font.to_riff().unwrap().write(&mut cursor).unwrap();
+        cursor.into_inner()
+    }
+
+    pub(super) fn font() -> SoundFont2 {
+        let mut info = InfoList::default();
+        info.set_inam("Round Trip".into()).unwrap();
+        info.set_icmt(Some("Odd".into())).unwrap();
+        info.extra.push(RawChunk {
+            id: ChunkId { value: *b"ISBJ" },
+            data: b"Subject\0".to_vec(),
+        });
+
+        let sdta = SdtaList::new((0..110).map(|i| i * 100).collect(), Some(vec![7; 110]));
+
+        let mut pdta = PdtaList::default();
+        pdta.phdr.contents = vec![
+            PresetHeader {
+                name: "Sine".into(),
+                ..Default::default()
+            },
+            PresetHeader {
+                name: "EOP".into(),
+                pbag_idx: 1,
+                ..Default::default()
+            },
+        ];
+        pdta.pbag.contents = vec![PresetZone::new(0, 0), PresetZone::new(1, 0)];
+        pdta.pmod.contents = vec![PresetMod::terminal()];
+        pdta.pgen.contents = vec![
+            PresetGen::new(Generator::Instrument, GenAmountType::Unsigned(0)).unwrap(),
+            PresetGen::terminal(),
+        ];
+        pdta.inst.contents = vec![
+            Instrument {
+                name: "Sine".into(),
+                raw_name: None,
+                inst_bag_idx: 0,
+            },
+            Instrument {
+                name: "EOI".into(),
+                raw_name: None,
+                inst_bag_idx: 1,
+            },
+        ];
+        pdta.ibag.contents = vec![InstZone::new(0, 0), InstZone::new(2, 1)];
+        pdta.imod.contents = vec![
+            InstrumentMod {
+                sf_mod_src_oper: ModulatorSource::cc(1),
+                sf_mod_dest_oper: ModDestination::Generator(Generator::Pan),
+                mod_amount: -200,
+                sf_mod_amt_src_oper: ModulatorSource::NONE,
+                sf_mod_trans_oper: ModTransform::AbsoluteValue,
+            },
+            InstrumentMod::terminal(),
+        ];
+        pdta.igen.contents = vec![
+            InstrumentGen::new(Generator::KeyRange, GenAmountType::Range { lo: 0, hi: 60 })
+                .unwrap(),
+            InstrumentGen::new(Generator::SampleId, GenAmountType::Unsigned(0)).unwrap(),
+            InstrumentGen::terminal(),
+        ];
+        pdta.shdr.contents = vec![
+            SampleHeader {
+                name: "Sine".into(),
+                raw_name: None,
+                start: 0,
+                end: 64,
+                startloop: 8,
+                endloop: 56,
+                sample_rate: 44100,
+                original_pitch: 60,
+                pitch_correction: -3,
+                sample_link: 0,
+                sample_type: 1,
+            },
+            SampleHeader::terminal(),
+        ];
+
+        let mut font = SoundFont2::new(info, sdta, pdta);
+        font.extra.push(RawChunk {
+            id: ChunkId { value: *b"JUNK" },
+            data: vec![1, 2, 3],
+        });
+        font
+    }
+
+    #[test]
+    fn test_write_errors() {
+        let mut font = font();
+        font.pdta.inst.contents[0].name = "Twenty-one characters".into();
+        assert!(matches!(
+            font.to_riff(),
+            Err(SoundfontError::StringLimit { limit: 20, len: 21 })
+        ));
+
+        font.pdta.inst.contents.clear();
+        assert!(matches!(
+            font.to_riff(),
+            Err(SoundfontError::ChunkEmpty { id }) if id.value == *b"inst"
+        ));
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = std::env::temp_dir().join(format!("sfc-save-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let expected = to_bytes(&font());
+
+        let path = dir.join("out.sf2");
+        let saved = font().save(&path).map(|_| std::fs::read(&path).unwrap());
+
+        // A font that can't be written leaves the earlier file as it was
+        let mut broken = font();
+        broken.pdta.inst.contents.clear();
+        let broken_failed = broken.save(&path);
+        let kept_font = std::fs::read(&path).unwrap();
+
+        // Renaming over a directory fails once the temporary file is written
+        let blocked = dir.join("blocked.sf2");
+        std::fs::create_dir(&blocked).unwrap();
+        std::fs::write(blocked.join("kept.txt"), "kept").unwrap();
+        let failed = font().save(&blocked);
+        let kept = std::fs::read_to_string(blocked.join("kept.txt"));
+        let mut files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(saved.unwrap(), expected);
+        assert!(broken_failed.is_err());
+        assert_eq!(kept_font, expected);
+        assert!(failed.is_err());
+        assert_eq!(kept.unwrap(), "kept");
+        assert_eq!(files, ["blocked.sf2", "out.sf2"]);
+        assert_ne!(temp_path(&path).unwrap(), temp_path(&path).unwrap());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = to_bytes(&font());
+        let parsed = SoundFont2::from_bytes(&bytes).unwrap();
+
+        assert_eq!(parsed.info.isng(), "EMU8000");
+        assert_eq!(parsed.info.inam(), "Round Trip");
+        assert_eq!(parsed.info.icmt(), Some("Odd"));
+        assert_eq!(parsed.info.extra, font().info.extra);
+        assert_eq!(parsed.sdta.smpl, font().sdta.smpl);
+        assert_eq!(parsed.pdta.imod.contents, font().pdta.imod.contents);
+        assert_eq!(parsed.pdta.shdr.contents[0].pitch_correction, -3);
+        assert_eq!(parsed.extra, font().extra);
+
+        assert_eq!(to_bytes(&parsed), bytes);
+    }
+
+    #[test]
+    fn test_string_terminator() {
+        assert_eq!(string_to_bytes(&"Odd".into()), b"Odd\0");
+        assert_eq!(string_to_bytes(&"Even".into()), b"Even\0\0");
+        assert_eq!(bytes_to_string(b"Name\0junk"), "Name");
+    }
+
+    #[test]
+    fn test_round_trip_unknown() {
+        // An operator past the spec in the second igen record, and a sample name in Latin-1 with
+        // junk after its terminator
+        let mut bytes = to_bytes(&font());
+        let igen = bytes.windows(4).position(|id| id == b"igen").unwrap() + 8;
+        bytes[igen + 4..igen + 6].copy_from_slice(&99u16.to_le_bytes());
+        let shdr = bytes.windows(4).position(|id| id == b"shdr").unwrap() + 8;
+        bytes[shdr..shdr + 10].copy_from_slice(b"Caf\xe9\0junk\0");
+
+        let parsed = SoundFont2::from_bytes(&bytes).unwrap();
+        let gen = &parsed.pdta.igen.contents[1];
+        assert_eq!(gen.sf_gen_oper, Generator::Unknown(99));
+        assert_eq!(parsed.pdta.shdr.contents[0].name, "Caf?");
+        assert_eq!(to_bytes(&parsed), bytes);
+
+        // A new name replaces the raw bytes
+        let mut renamed = parsed;
+        renamed.pdta.shdr.contents[0].name = "Cafe".into();
+        let renamed = SoundFont2::from_bytes(&to_bytes(&renamed)).unwrap();
+        assert_eq!(
+            renamed.pdta.shdr.contents[0].raw_name.unwrap()[..6],
+            *b"Cafe\0\0"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = SoundFont2::from_bytes(b"RIFF\x04\0\0\0WAVE").unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::NotSoundFont));
+
+        let bytes = to_bytes(&font());
+        let err = SoundFont2::from_bytes(&bytes[..bytes.len() - 20]).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::ChunkTruncated { .. }));
+    }
 }
@@ -0,0 +1,495 @@
+//! SFGenerator - generator operators of SoundFont 2.04
+
+use std::fmt::Display;
+
+use super::GenAmountType;
+use crate::soundfont::SoundfontError;
+
+/// Zone types a generator may appear in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeneratorLevel {
+    /// Preset zones only
+    Preset,
+    /// Instrument zones only
+    Instrument,
+    /// Both preset and instrument zones
+    Both,
+    /// Reserved, unused, or a terminator. Not allowed anywhere.
+    None,
+}
+
+/// Which [GenAmountType] variant a generator takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenAmountKind {
+    Range,
+    Signed,
+    Unsigned,
+}
+
+/// SFGenerator 16-bit enum. Covers every operator in the spec, including reserved and unused
+/// slots, and keeps any other value as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u16)]
+pub enum Generator {
+    StartAddrsOffset = 0,
+    EndAddrsOffset = 1,
+    StartloopAddrsOffset = 2,
+    EndloopAddrsOffset = 3,
+    StartAddrsCoarseOffset = 4,
+    ModLfoToPitch = 5,
+    VibLfoToPitch = 6,
+    ModEnvToPitch = 7,
+    InitialFilterFc = 8,
+    InitialFilterQ = 9,
+    ModLfoToFilterFc = 10,
+    ModEnvToFilterFc = 11,
+    EndAddrsCoarseOffset = 12,
+    ModLfoToVolume = 13,
+    Unused1 = 14,
+    ChorusEffectsSend = 15,
+    ReverbEffectsSend = 16,
+    Pan = 17,
+    Unused2 = 18,
+    Unused3 = 19,
+    Unused4 = 20,
+    DelayModLfo = 21,
+    FreqModLfo = 22,
+    DelayVibLfo = 23,
+    FreqVibLfo = 24,
+    DelayModEnv = 25,
+    AttackModEnv = 26,
+    HoldModEnv = 27,
+    DecayModEnv = 28,
+    SustainModEnv = 29,
+    ReleaseModEnv = 30,
+    KeynumToModEnvHold = 31,
+    KeynumToModEnvDecay = 32,
+    DelayVolEnv = 33,
+    AttackVolEnv = 34,
+    HoldVolEnv = 35,
+    DecayVolEnv = 36,
+    SustainVolEnv = 37,
+    ReleaseVolEnv = 38,
+    KeynumToVolEnvHold = 39,
+    KeynumToVolEnvDecay = 40,
+    Instrument = 41,
+    Reserved1 = 42,
+    KeyRange = 43,
+    VelRange = 44,
+    StartloopAddrsCoarseOffset = 45,
+    Keynum = 46,
+    Velocity = 47,
+    InitialAttenuation = 48,
+    Reserved2 = 49,
+    EndloopAddrsCoarseOffset = 50,
+    CoarseTune = 51,
+    FineTune = 52,
+    SampleId = 53,
+    SampleModes = 54,
+    Reserved3 = 55,
+    ScaleTuning = 56,
+    ExclusiveClass = 57,
+    OverridingRootKey = 58,
+    Unused5 = 59,
+    EndOper = 60,
+    /// An operator past the end of the spec, e.g. a vendor extension. Synths ignore it.
+    Unknown(u16),
+}
+
+/// Static properties of a generator, as listed in the spec.
+struct GeneratorSpec {
+    name: &'static str,
+    level: GeneratorLevel,
+    kind: GenAmountKind,
+    default: i16,
+    /// Legal range at instrument level. None if unbounded or meaningless.
+    range: Option<(i16, i16)>,
+}
+
+const fn spec(
+    name: &'static str,
+    level: GeneratorLevel,
+    kind: GenAmountKind,
+    default: i16,
+    range: Option<(i16, i16)>,
+) -> GeneratorSpec {
+    GeneratorSpec {
+        name,
+        level,
+        kind,
+        default,
+        range,
+    }
+}
+
+impl Generator {
+    /// All operators, in numerical order.
+    pub const ALL: [Generator; 61] = [
+        Self::StartAddrsOffset,
+        Self::EndAddrsOffset,
+        Self::StartloopAddrsOffset,
+        Self::EndloopAddrsOffset,
+        Self::StartAddrsCoarseOffset,
+        Self::ModLfoToPitch,
+        Self::VibLfoToPitch,
+        Self::ModEnvToPitch,
+        Self::InitialFilterFc,
+        Self::InitialFilterQ,
+        Self::ModLfoToFilterFc,
+        Self::ModEnvToFilterFc,
+        Self::EndAddrsCoarseOffset,
+        Self::ModLfoToVolume,
+        Self::Unused1,
+        Self::ChorusEffectsSend,
+        Self::ReverbEffectsSend,
+        Self::Pan,
+        Self::Unused2,
+        Self::Unused3,
+        Self::Unused4,
+        Self::DelayModLfo,
+        Self::FreqModLfo,
+        Self::DelayVibLfo,
+        Self::FreqVibLfo,
+        Self::DelayModEnv,
+        Self::AttackModEnv,
+        Self::HoldModEnv,
+        Self::DecayModEnv,
+        Self::SustainModEnv,
+        Self::ReleaseModEnv,
+        Self::KeynumToModEnvHold,
+        Self::KeynumToModEnvDecay,
+        Self::DelayVolEnv,
+        Self::AttackVolEnv,
+        Self::HoldVolEnv,
+        Self::DecayVolEnv,
+        Self::SustainVolEnv,
+        Self::ReleaseVolEnv,
+        Self::KeynumToVolEnvHold,
+        Self::KeynumToVolEnvDecay,
+        Self::Instrument,
+        Self::Reserved1,
+        Self::KeyRange,
+        Self::VelRange,
+        Self::StartloopAddrsCoarseOffset,
+        Self::Keynum,
+        Self::Velocity,
+        Self::InitialAttenuation,
+        Self::Reserved2,
+        Self::EndloopAddrsCoarseOffset,
+        Self::CoarseTune,
+        Self::FineTune,
+        Self::SampleId,
+        Self::SampleModes,
+        Self::Reserved3,
+        Self::ScaleTuning,
+        Self::ExclusiveClass,
+        Self::OverridingRootKey,
+        Self::Unused5,
+        Self::EndOper,
+    ];
+
+    fn spec(self) -> GeneratorSpec {
+        use GenAmountKind::*;
+        use GeneratorLevel::{Both, Instrument as Inst, None as Nowhere, Preset};
+
+        const CENTS: Option<(i16, i16)> = Some((-12000, 12000));
+        const DELAY: Option<(i16, i16)> = Some((-12000, 5000));
+        const ATTACK: Option<(i16, i16)> = Some((-12000, 8000));
+        const KEYNUM_TO: Option<(i16, i16)> = Some((-1200, 1200));
+        const LFO_FREQ: Option<(i16, i16)> = Some((-16000, 4500));
+        const SEND: Option<(i16, i16)> = Some((0, 1000));
+        const MIDI: Option<(i16, i16)> = Some((0, 127));
+
+        match self {
+            Self::StartAddrsOffset => spec("start_addrs_offset", Inst, Signed, 0, None),
+            Self::EndAddrsOffset => spec("end_addrs_offset", Inst, Signed, 0, None),
+            Self::StartloopAddrsOffset => spec("startloop_addrs_offset", Inst, Signed, 0, None),
+            Self::EndloopAddrsOffset => spec("endloop_addrs_offset", Inst, Signed, 0, None),
+            Self::StartAddrsCoarseOffset => {
+                spec("start_addrs_coarse_offset", Inst, Signed, 0, None)
+            }
+            Self::ModLfoToPitch => spec("mod_lfo_to_pitch", Both, Signed, 0, CENTS),
+            Self::VibLfoToPitch => spec("vib_lfo_to_pitch", Both, Signed, 0, CENTS),
+            Self::ModEnvToPitch => spec("mod_env_to_pitch", Both, Signed, 0, CENTS),
+            Self::InitialFilterFc => spec(
+                "initial_filter_fc",
+                Both,
+                Signed,
+                13500,
+                Some((1500, 13500)),
+            ),
+            Self::InitialFilterQ => spec("initial_filter_q", Both, Signed, 0, Some((0, 960))),
+            Self::ModLfoToFilterFc => spec("mod_lfo_to_filter_fc", Both, Signed, 0, CENTS),
+            Self::ModEnvToFilterFc => spec("mod_env_to_filter_fc", Both, Signed, 0, CENTS),
+            Self::EndAddrsCoarseOffset => spec("end_addrs_coarse_offset", Inst, Signed, 0, None),
+            Self::ModLfoToVolume => spec("mod_lfo_to_volume", Both, Signed, 0, Some((-960, 960))),
+            Self::Unused1 => spec("unused1", Nowhere, Signed, 0, None),
+            Self::ChorusEffectsSend => spec("chorus_effects_send", Both, Signed, 0, SEND),
+            Self::ReverbEffectsSend => spec("reverb_effects_send", Both, Signed, 0, SEND),
+            Self::Pan => spec("pan", Both, Signed, 0, Some((-500, 500))),
+            Self::Unused2 => spec("unused2", Nowhere, Signed, 0, None),
+            Self::Unused3 => spec("unused3", Nowhere, Signed, 0, None),
+            Self::Unused4 => spec("unused4", Nowhere, Signed, 0, None),
+            Self::DelayModLfo => spec("delay_mod_lfo", Both, Signed, -12000, DELAY),
+            Self::FreqModLfo => spec("freq_mod_lfo", Both, Signed, 0, LFO_FREQ),
+            Self::DelayVibLfo => spec("delay_vib_lfo", Both, Signed, -12000, DELAY),
+            Self::FreqVibLfo => spec("freq_vib_lfo", Both, Signed, 0, LFO_FREQ),
+            Self::DelayModEnv => spec("delay_mod_env", Both, Signed, -12000, DELAY),
+            Self::AttackModEnv => spec("attack_mod_env", Both, Signed, -12000, ATTACK),
+            Self::HoldModEnv => spec("hold_mod_env", Both, Signed, -12000, DELAY),
+            Self::DecayModEnv => spec("decay_mod_env", Both, Signed, -12000, ATTACK),
+            Self::SustainModEnv => spec("sustain_mod_env", Both, Signed, 0, Some((0, 1000))),
+            Self::ReleaseModEnv => spec("release_mod_env", Both, Signed, -12000, ATTACK),
+            Self::KeynumToModEnvHold => spec("keynum_to_mod_env_hold", Both, Signed, 0, KEYNUM_TO),
+            Self::KeynumToModEnvDecay => {
+                spec("keynum_to_mod_env_decay", Both, Signed, 0, KEYNUM_TO)
+            }
+            Self::DelayVolEnv => spec("delay_vol_env", Both, Signed, -12000, DELAY),
+            Self::AttackVolEnv => spec("attack_vol_env", Both, Signed, -12000, ATTACK),
+            Self::HoldVolEnv => spec("hold_vol_env", Both, Signed, -12000, DELAY),
+            Self::DecayVolEnv => spec("decay_vol_env", Both, Signed, -12000, ATTACK),
+            Self::SustainVolEnv => spec("sustain_vol_env", Both, Signed, 0, Some((0, 1440))),
+            Self::ReleaseVolEnv => spec("release_vol_env", Both, Signed, -12000, ATTACK),
+            Self::KeynumToVolEnvHold => spec("keynum_to_vol_env_hold", Both, Signed, 0, KEYNUM_TO),
+            Self::KeynumToVolEnvDecay => {
+                spec("keynum_to_vol_env_decay", Both, Signed, 0, KEYNUM_TO)
+            }
+            Self::Instrument => spec("instrument", Preset, Unsigned, 0, None),
+            Self::Reserved1 => spec("reserved1", Nowhere, Signed, 0, None),
+            // Range defaults are 0-127, which can't be expressed as a single i16.
+            Self::KeyRange => spec("key_range", Both, Range, 0, MIDI),
+            Self::VelRange => spec("vel_range", Both, Range, 0, MIDI),
+            Self::StartloopAddrsCoarseOffset => {
+                spec("startloop_addrs_coarse_offset", Inst, Signed, 0, None)
+            }
+            Self::Keynum => spec("keynum", Inst, Signed, -1, MIDI),
+            Self::Velocity => spec("velocity", Inst, Signed, -1, MIDI),
+            Self::InitialAttenuation => {
+                spec("initial_attenuation", Both, Signed, 0, Some((0, 1440)))
+            }
+            Self::Reserved2 => spec("reserved2", Nowhere, Signed, 0, None),
+            Self::EndloopAddrsCoarseOffset => {
+                spec("endloop_addrs_coarse_offset", Inst, Signed, 0, None)
+            }
+            Self::CoarseTune => spec("coarse_tune", Both, Signed, 0, Some((-120, 120))),
+            Self::FineTune => spec("fine_tune", Both, Signed, 0, Some((-99, 99))),
+            Self::SampleId => spec("sample_id", Inst, Unsigned, 0, None),
+            Self::SampleModes => spec("sample_modes", Inst, Unsigned, 0, Some((0, 3))),
+            Self::Reserved3 => spec("reserved3", Nowhere, Signed, 0, None),
+            Self::ScaleTuning => spec("scale_tuning", Both, Signed, 100, Some((0, 1200))),
+            Self::ExclusiveClass => spec("exclusive_class", Inst, Unsigned, 0, MIDI),
+            Self::OverridingRootKey => spec("overriding_root_key", Inst, Signed, -1, MIDI),
+            Self::Unused5 => spec("unused5", Nowhere, Signed, 0, None),
+            Self::EndOper => spec("end_oper", Nowhere, Signed, 0, None),
+            Self::Unknown(_) => spec("unknown", Nowhere, Signed, 0, None),
+        }
+    }
+
+    pub fn from_u16(value: u16) -> Self {
+        Self::ALL
+            .get(value as usize)
+            .copied()
+            .unwrap_or(Self::Unknown(value))
+    }
+
+    pub fn to_u16(self) -> u16 {
+        match self {
+            Self::Unknown(value) => value,
+            _ => Self::ALL.iter().position(|gen| *gen == self).unwrap() as u16,
+        }
+    }
+
+    /// Snake case name, as used in project files. e.g. "initial_filter_fc"
+    pub fn name(self) -> &'static str {
+        self.spec().name
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|gen| gen.name() == name)
+    }
+
+    pub fn level(self) -> GeneratorLevel {
+        self.spec().level
+    }
+
+    pub fn allowed_in_preset(self) -> bool {
+        matches!(self.level(), GeneratorLevel::Preset | GeneratorLevel::Both)
+    }
+
+    pub fn allowed_in_instrument(self) -> bool {
+        matches!(
+            self.level(),
+            GeneratorLevel::Instrument | GeneratorLevel::Both
+        )
+    }
+
+    pub fn amount_kind(self) -> GenAmountKind {
+        self.spec().kind
+    }
+
+    /// Value a synth assumes when the generator is absent.
+    pub fn default_amount(self) -> GenAmountType {
+        match self.amount_kind() {
+            GenAmountKind::Range => GenAmountType::Range { lo: 0, hi: 127 },
+            GenAmountKind::Signed => GenAmountType::Signed(self.spec().default),
+            GenAmountKind::Unsigned => GenAmountType::Unsigned(self.spec().default as u16),
+        }
+    }
+
+    /// Legal range of the amount in an instrument zone, inclusive.
+    pub fn range(self) -> Option<(i16, i16)> {
+        self.spec().range
+    }
+
+    /// Wrap a raw 16-bit value in the amount type this generator takes.
+    pub fn amount_from_i16(self, value: i16) -> GenAmountType {
+        match self.amount_kind() {
+            GenAmountKind::Range => {
+                let [lo, hi] = value.to_le_bytes();
+                GenAmountType::Range { lo, hi }
+            }
+            GenAmountKind::Signed => GenAmountType::Signed(value),
+            GenAmountKind::Unsigned => GenAmountType::Unsigned(value as u16),
+        }
+    }
+
+    /// Check that this generator with the given amount is legal at the given level.
+    ///
+    /// Preset-level amounts are relative to the instrument, so they're allowed to span the full
+    /// width of the range in either direction.
+    pub fn validate(
+        self,
+        amount: &GenAmountType,
+        level: GeneratorLevel,
+    ) -> Result<(), SoundfontError> {
+        let allowed = match level {
+            GeneratorLevel::Preset => self.allowed_in_preset(),
+            GeneratorLevel::Instrument => self.allowed_in_instrument(),
+            GeneratorLevel::Both | GeneratorLevel::None => false,
+        };
+        if !allowed {
+            return Err(SoundfontError::GeneratorNotAllowed {
+                generator: self,
+                level,
+            });
+        }
+
+        let value = match (self.amount_kind(), amount) {
+            (GenAmountKind::Range, GenAmountType::Range { lo, hi }) => {
+                if lo > hi || *hi > 127 {
+                    return Err(SoundfontError::GeneratorInvalidRange {
+                        generator: self,
+                        lo: *lo,
+                        hi: *hi,
+                    });
+                }
+                return Ok(());
+            }
+            (GenAmountKind::Signed, GenAmountType::Signed(value)) => *value as i32,
+            (GenAmountKind::Unsigned, GenAmountType::Unsigned(value)) => *value as i32,
+            _ => return Err(SoundfontError::GeneratorAmountType { generator: self }),
+        };
+
+        let Some((min, max)) = self.range() else {
+            return Ok(());
+        };
+        let (min, max) = match level {
+            GeneratorLevel::Preset => {
+                let span = max as i32 - min as i32;
+                (-span, span)
+            }
+            _ => (min as i32, max as i32),
+        };
+        // -1 means "unset" for these.
+        let unset = matches!(
+            self,
+            Self::Keynum | Self::Velocity | Self::OverridingRootKey
+        ) && value == -1;
+        if !unset && (value < min || value > max) {
+            return Err(SoundfontError::GeneratorOutOfRange {
+                generator: self,
+                value,
+                min,
+                max,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Display for Generator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown(value) => write!(f, "unknown generator {value}"),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+impl Display for GeneratorLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeneratorLevel::Preset => write!(f, "preset"),
+            GeneratorLevel::Instrument => write!(f, "instrument"),
+            GeneratorLevel::Both => write!(f, "preset or instrument"),
+            GeneratorLevel::None => write!(f, "no"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_in_order() {
+        for (i, gen) in Generator::ALL.iter().enumerate() {
+            assert_eq!(gen.to_u16() as usize, i);
+            assert_eq!(Generator::from_u16(i as u16), *gen);
+            assert_eq!(Generator::from_name(gen.name()), Some(*gen));
+        }
+        assert_eq!(Generator::from_u16(61), Generator::Unknown(61));
+        assert_eq!(Generator::Unknown(61).to_u16(), 61);
+        assert_eq!(Generator::from_name("unknown"), None);
+    }
+
+    #[test]
+    fn test_validate_level() {
+        let amount = GenAmountType::Unsigned(0);
+        assert!(Generator::SampleId
+            .validate(&amount, GeneratorLevel::Instrument)
+            .is_ok());
+        assert!(Generator::SampleId
+            .validate(&amount, GeneratorLevel::Preset)
+            .is_err());
+        assert!(Generator::Instrument
+            .validate(&amount, GeneratorLevel::Instrument)
+            .is_err());
+        assert!(Generator::Reserved1
+            .validate(&amount, GeneratorLevel::Instrument)
+            .is_err());
+    }
+
+    #[test]
+    fn test_validate_range() {
+        let level = GeneratorLevel::Instrument;
+        assert!(Generator::Pan
+            .validate(&GenAmountType::Signed(500), level)
+            .is_ok());
+        assert!(Generator::Pan
+            .validate(&GenAmountType::Signed(501), level)
+            .is_err());
+        assert!(Generator::Pan
+            .validate(&GenAmountType::Signed(-1000), GeneratorLevel::Preset)
+            .is_ok());
+        assert!(Generator::OverridingRootKey
+            .validate(&GenAmountType::Signed(-1), level)
+            .is_ok());
+        assert!(Generator::KeyRange
+            .validate(&GenAmountType::Range { lo: 60, hi: 40 }, level)
+            .is_err());
+        assert!(Generator::Pan
+            .validate(&GenAmountType::Unsigned(0), level)
+            .is_err());
+    }
+}
@@ -1,4 +1,7 @@
-use riff::{ChunkContents, ChunkId};
+use riff::ChunkContents;
+
+use super::{list_chunk, records, u16_at};
+use crate::soundfont::{ParseError, SoundfontError};
 
 #[derive(Debug, Clone)]
 pub struct InstBag {
@@ -16,16 +19,20 @@ impl InstBag {
         Self { contents: vec![] }
     }
 
-    pub fn to_riff(&self) -> ChunkContents {
+    pub fn to_riff(&self) -> Result<ChunkContents, SoundfontError> {
         let mut contents = vec![];
         for bag in &self.contents {
             contents.append(&mut bag.to_bytes());
         }
 
-        assert_ne!(contents.len(), 0);
-        assert_eq!(contents.len() % 4, 0);
+        list_chunk(*b"ibag", contents)
+    }
 
-        ChunkContents::Data(ChunkId { value: *b"ibag" }, contents)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let contents = records(bytes, 4, *b"ibag")?
+            .map(InstZone::from_bytes)
+            .collect();
+        Ok(Self { contents })
     }
 }
 
@@ -47,6 +54,14 @@ impl InstZone {
         bytes
     }
 
+    /// `bytes` must be one 4 byte record.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            gen_idx: u16_at(bytes, 0),
+            mod_idx: u16_at(bytes, 2),
+        }
+    }
+
     /// Null valued terminal record
     pub fn terminal() -> Self {
         Self {
@@ -1,4 +1,9 @@
-use riff::{ChunkContents, ChunkId};
+use riff::ChunkContents;
+
+use super::{
+    list_chunk, records, u16_at, Generator, ModDestination, ModTransform, ModulatorSource,
+};
+use crate::soundfont::{ParseError, SoundfontError};
 
 /// Modulator list for Presets and Instruments
 #[derive(Debug, Clone)]
@@ -17,17 +22,65 @@ impl InstModList {
         Self { contents: vec![] }
     }
 
-    pub fn to_riff(&self) -> ChunkContents {
-        let contents = vec![];
-        //for gen in value.contents {
-        //    contents.append(&mut gen.as_bytes());
-        //}
+    pub fn to_riff(&self) -> Result<ChunkContents, SoundfontError> {
+        let mut contents = vec![];
+        for modulator in &self.contents {
+            contents.append(&mut modulator.to_bytes());
+        }
 
-        assert_eq!(contents.len() % 4, 0);
+        list_chunk(*b"imod", contents)
+    }
 
-        ChunkContents::Data(ChunkId { value: *b"pmod" }, contents)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let contents = records(bytes, 10, *b"imod")?
+            .map(InstrumentMod::from_bytes)
+            .collect();
+        Ok(Self { contents })
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct InstrumentMod {}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstrumentMod {
+    pub sf_mod_src_oper: ModulatorSource,
+    pub sf_mod_dest_oper: ModDestination,
+    pub mod_amount: i16,
+    /// Scales the amount. [ModulatorSource::NONE] for a fixed amount.
+    pub sf_mod_amt_src_oper: ModulatorSource,
+    pub sf_mod_trans_oper: ModTransform,
+}
+
+impl InstrumentMod {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        bytes.append(&mut self.sf_mod_src_oper.to_u16().to_le_bytes().to_vec());
+        bytes.append(&mut self.sf_mod_dest_oper.to_u16().to_le_bytes().to_vec());
+        bytes.append(&mut self.mod_amount.to_le_bytes().to_vec());
+        bytes.append(&mut self.sf_mod_amt_src_oper.to_u16().to_le_bytes().to_vec());
+        bytes.append(&mut self.sf_mod_trans_oper.to_u16().to_le_bytes().to_vec());
+
+        bytes
+    }
+
+    /// `bytes` must be one 10 byte record
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            sf_mod_src_oper: ModulatorSource::from_u16(u16_at(bytes, 0)),
+            sf_mod_dest_oper: ModDestination::from_u16(u16_at(bytes, 2)),
+            mod_amount: u16_at(bytes, 4) as i16,
+            sf_mod_amt_src_oper: ModulatorSource::from_u16(u16_at(bytes, 6)),
+            sf_mod_trans_oper: ModTransform::from_u16(u16_at(bytes, 8)),
+        }
+    }
+
+    /// Null valued terminal record
+    pub fn terminal() -> Self {
+        Self {
+            sf_mod_src_oper: ModulatorSource::NONE,
+            sf_mod_dest_oper: ModDestination::Generator(Generator::StartAddrsOffset),
+            mod_amount: 0,
+            sf_mod_amt_src_oper: ModulatorSource::NONE,
+            sf_mod_trans_oper: ModTransform::Linear,
+        }
+    }
+}
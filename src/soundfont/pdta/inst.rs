@@ -1,4 +1,7 @@
-use riff::{ChunkContents, ChunkId};
+use riff::ChunkContents;
+
+use super::{list_chunk, records, u16_at};
+use crate::soundfont::{bytes_to_string, name_to_bytes, ParseError, SoundfontError};
 
 #[derive(Debug, Clone)]
 pub struct Instruments {
@@ -16,33 +19,48 @@ impl Instruments {
         Self { contents: vec![] }
     }
 
-    pub fn to_riff(&self) -> ChunkContents {
+    pub fn to_riff(&self) -> Result<ChunkContents, SoundfontError> {
         let mut contents = vec![];
         for gen in &self.contents {
-            contents.append(&mut gen.to_bytes());
+            contents.append(&mut gen.to_bytes()?);
         }
-        ChunkContents::Data(ChunkId { value: *b"inst" }, contents)
+
+        list_chunk(*b"inst", contents)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let contents = records(bytes, 22, *b"inst")?
+            .map(Instrument::from_bytes)
+            .collect();
+        Ok(Self { contents })
     }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Instrument {
     pub name: String,
+    /// The name as read from the file, written back while `name` is unchanged
+    pub raw_name: Option<[u8; 20]>,
     pub inst_bag_idx: u16,
 }
 
 impl Instrument {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Fails if the name doesn't fit into 20 bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SoundfontError> {
         let mut bytes = vec![];
 
-        let mut name_bytes = self.name.as_bytes().to_vec();
-        assert!(name_bytes.len() <= 20);
-        name_bytes.resize(20, 0);
-
-        bytes.append(&mut name_bytes);
+        bytes.extend(name_to_bytes(&self.name, &self.raw_name)?);
         bytes.append(&mut self.inst_bag_idx.to_le_bytes().to_vec());
 
-        assert_eq!(bytes.len(), 22);
-        bytes
+        Ok(bytes)
+    }
+
+    /// `bytes` must be one 22 byte record.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            name: bytes_to_string(&bytes[..20]),
+            raw_name: Some(bytes[..20].try_into().unwrap()),
+            inst_bag_idx: u16_at(bytes, 20),
+        }
     }
 }
@@ -1,10 +1,12 @@
 //! Level 0 RIFF chunk 3/3 - sdta-list
 //! Contains presets, instruments, sample headers
 
+mod generator;
 mod ibag;
 mod igen;
 mod imod;
 mod inst;
+mod modulator;
 mod pbag;
 mod pgen;
 mod phdr;
@@ -12,21 +14,28 @@ mod pmod;
 mod shdr;
 
 use riff::{ChunkContents, ChunkId};
+use std::slice::ChunksExact;
 
+use super::{subchunks, ParseError, ParseErrorKind, RawChunk, SoundfontError, Subchunk};
+
+pub use generator::{GenAmountKind, Generator, GeneratorLevel};
 pub use ibag::{InstBag, InstZone};
 pub use igen::{InstGenList, InstrumentGen};
-pub use imod::InstModList;
+pub use imod::{InstModList, InstrumentMod};
 pub use inst::{Instrument, Instruments};
+pub use modulator::{
+    DefaultModulator, GeneralController, ModDestination, ModTransform, ModulatorSource, SourceCurve,
+};
 pub use pbag::{PresetBag, PresetZone};
 pub use pgen::{PresetGen, PresetGenList};
 pub use phdr::{PresetHeader, PresetHeaders};
-pub use pmod::PresetModList;
-pub use shdr::{SampleHeader, SampleList};
+pub use pmod::{PresetMod, PresetModList};
+pub use shdr::{SFSampleLink, SampleHeader, SampleList};
 
 /// The Hydra
 ///
 /// Rough hierarchy based on pointers:
-/// ```
+/// ```text
 /// pdta
 /// ├── phdr
 /// │   └── pbag
@@ -49,8 +58,11 @@ pub struct PdtaList {
     pub imod: InstModList,   // Instrument Modulators
     pub igen: InstGenList,   //
     pub shdr: SampleList,    // Sample
+    /// Unknown subchunks, kept as-is
+    pub extra: Vec<RawChunk>,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for PdtaList {
     fn default() -> Self {
         Self {
@@ -65,26 +77,111 @@ impl Default for PdtaList {
             igen: InstGenList::new(),
 
             shdr: SampleList::new(),
+            extra: vec![],
         }
     }
 }
 
 impl PdtaList {
-    pub fn to_riff(&self) -> ChunkContents {
-        let contents: Vec<ChunkContents> = vec![
-            self.phdr.to_riff(),
-            self.pbag.to_riff(),
-            self.pmod.to_riff(),
-            self.pgen.to_riff(),
-            self.inst.to_riff(),
-            self.ibag.to_riff(),
-            self.imod.to_riff(),
-            self.igen.to_riff(),
-            self.shdr.to_riff(),
+    /// Fails if a list is empty, or a name doesn't fit.
+    pub fn to_riff(&self) -> Result<ChunkContents, SoundfontError> {
+        let mut contents: Vec<ChunkContents> = vec![
+            self.phdr.to_riff()?,
+            self.pbag.to_riff()?,
+            self.pmod.to_riff()?,
+            self.pgen.to_riff()?,
+            self.inst.to_riff()?,
+            self.ibag.to_riff()?,
+            self.imod.to_riff()?,
+            self.igen.to_riff()?,
+            self.shdr.to_riff()?,
         ];
+        contents.extend(self.extra.iter().map(RawChunk::to_riff));
 
-        ChunkContents::Children(riff::LIST_ID, ChunkId { value: *b"pdta" }, contents)
+        Ok(ChunkContents::Children(
+            riff::LIST_ID,
+            ChunkId { value: *b"pdta" },
+            contents,
+        ))
     }
+
+    /// Parse the subchunks of a pdta-list. All nine are required, in any order.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ParseError> {
+        let mut pdta = Self::default();
+        let mut found = vec![];
+        for Subchunk {
+            id,
+            offset,
+            contents,
+        } in subchunks(data)?
+        {
+            let located = |err: ParseError| err.offset_by(offset as u64 + 8);
+            match &id.value {
+                b"phdr" => pdta.phdr = PresetHeaders::from_bytes(contents).map_err(located)?,
+                b"pbag" => pdta.pbag = PresetBag::from_bytes(contents).map_err(located)?,
+                b"pmod" => pdta.pmod = PresetModList::from_bytes(contents).map_err(located)?,
+                b"pgen" => pdta.pgen = PresetGenList::from_bytes(contents).map_err(located)?,
+                b"inst" => pdta.inst = Instruments::from_bytes(contents).map_err(located)?,
+                b"ibag" => pdta.ibag = InstBag::from_bytes(contents).map_err(located)?,
+                b"imod" => pdta.imod = InstModList::from_bytes(contents).map_err(located)?,
+                b"igen" => pdta.igen = InstGenList::from_bytes(contents).map_err(located)?,
+                b"shdr" => pdta.shdr = SampleList::from_bytes(contents).map_err(located)?,
+                _ => {
+                    pdta.extra.push(RawChunk {
+                        id,
+                        data: contents.to_vec(),
+                    });
+                    continue;
+                }
+            }
+            found.push(id.value);
+        }
+
+        let required = [
+            b"phdr", b"pbag", b"pmod", b"pgen", b"inst", b"ibag", b"imod", b"igen", b"shdr",
+        ];
+        if let Some(id) = required.into_iter().find(|id| !found.contains(*id)) {
+            let id = ChunkId { value: *id };
+            return Err(ParseError::new(
+                ParseErrorKind::MissingChunk { id },
+                *b"pdta",
+            ));
+        }
+        Ok(pdta)
+    }
+}
+
+/// Split a chunk into fixed size records. Fails if it isn't a whole number of records long.
+fn records(
+    bytes: &[u8],
+    record_size: usize,
+    chunk: [u8; 4],
+) -> Result<ChunksExact<'_, u8>, ParseError> {
+    if !bytes.len().is_multiple_of(record_size) {
+        let kind = ParseErrorKind::ChunkSize {
+            len: bytes.len(),
+            record_size,
+        };
+        return Err(ParseError::new(kind, chunk));
+    }
+    Ok(bytes.chunks_exact(record_size))
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// A list chunk of records. Every list ends in a terminal record, so it can't be empty.
+fn list_chunk(id: [u8; 4], records: Vec<u8>) -> Result<ChunkContents, SoundfontError> {
+    let id = ChunkId { value: id };
+    if records.is_empty() {
+        return Err(SoundfontError::ChunkEmpty { id });
+    }
+    Ok(ChunkContents::Data(id, records))
 }
 
 /*
@@ -101,7 +198,7 @@ impl RangesType {
 }*/
 
 /// 16-bit types for generator values
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GenAmountType {
     Range { lo: u8, hi: u8 },
     Signed(i16),
@@ -109,15 +206,17 @@ pub enum GenAmountType {
 }
 
 impl GenAmountType {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let bytes = match self {
-            GenAmountType::Range { lo, hi } => vec![*lo, *hi],
-            GenAmountType::Signed(value) => value.to_le_bytes().to_vec(),
-            GenAmountType::Unsigned(value) => value.to_le_bytes().to_vec(),
-        };
-
-        assert_eq!(bytes.len(), 2);
+    pub fn to_bytes(&self) -> [u8; 2] {
+        match self {
+            GenAmountType::Range { lo, hi } => [*lo, *hi],
+            GenAmountType::Signed(value) => value.to_le_bytes(),
+            GenAmountType::Unsigned(value) => value.to_le_bytes(),
+        }
+    }
 
-        bytes
+    /// The raw 16-bit value, as it's stored
+    pub fn to_i16(&self) -> i16 {
+        let bytes = self.to_bytes();
+        i16::from_le_bytes([bytes[0], bytes[1]])
     }
 }
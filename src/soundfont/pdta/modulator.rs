@@ -0,0 +1,381 @@
+//! SFModulator, SFTransform and the building blocks of modulator records
+
+use std::fmt::Display;
+
+use super::{Generator, InstrumentMod};
+
+/// General controller palette. Used as the modulator source index when the CC flag is clear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum GeneralController {
+    NoController = 0,
+    NoteOnVelocity = 2,
+    NoteOnKeyNumber = 3,
+    PolyPressure = 10,
+    ChannelPressure = 13,
+    PitchWheel = 14,
+    PitchWheelSensitivity = 16,
+    Link = 127,
+}
+
+impl GeneralController {
+    pub const ALL: [GeneralController; 8] = [
+        Self::NoController,
+        Self::NoteOnVelocity,
+        Self::NoteOnKeyNumber,
+        Self::PolyPressure,
+        Self::ChannelPressure,
+        Self::PitchWheel,
+        Self::PitchWheelSensitivity,
+        Self::Link,
+    ];
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|ctrl| *ctrl as u8 == index)
+    }
+
+    /// Snake case name, as used in project files. e.g. "channel_pressure"
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NoController => "none",
+            Self::NoteOnVelocity => "velocity",
+            Self::NoteOnKeyNumber => "key",
+            Self::PolyPressure => "poly_pressure",
+            Self::ChannelPressure => "channel_pressure",
+            Self::PitchWheel => "pitch_wheel",
+            Self::PitchWheelSensitivity => "pitch_wheel_sensitivity",
+            Self::Link => "link",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|ctrl| ctrl.name() == name)
+    }
+}
+
+/// Source type, i.e. the shape of the mapping from controller value to 0..1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum SourceCurve {
+    #[default]
+    Linear = 0,
+    Concave = 1,
+    Convex = 2,
+    Switch = 3,
+    /// A type past the end of the spec. Synths ignore the modulator.
+    Unknown(u8),
+}
+
+impl SourceCurve {
+    pub const ALL: [SourceCurve; 4] = [Self::Linear, Self::Concave, Self::Convex, Self::Switch];
+
+    pub fn from_u8(value: u8) -> Self {
+        Self::ALL
+            .get(value as usize)
+            .copied()
+            .unwrap_or(Self::Unknown(value))
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Self::Unknown(value) => value,
+            _ => Self::ALL.iter().position(|curve| *curve == self).unwrap() as u8,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Linear => "linear",
+            Self::Concave => "concave",
+            Self::Convex => "convex",
+            Self::Switch => "switch",
+            Self::Unknown(_) => "unknown",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|curve| curve.name() == name)
+    }
+}
+
+/// SFModulator 16-bit bitfield. Describes a modulator source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ModulatorSource {
+    /// Controller number. A MIDI CC if `midi_cc` is set, otherwise a [GeneralController].
+    pub index: u8,
+    pub midi_cc: bool,
+    /// Maps max to min instead of min to max
+    pub negative: bool,
+    /// Maps to -1..1 instead of 0..1
+    pub bipolar: bool,
+    pub curve: SourceCurve,
+}
+
+impl ModulatorSource {
+    /// "No controller". Used as the amount source when the amount is fixed.
+    pub const NONE: ModulatorSource = ModulatorSource {
+        index: 0,
+        midi_cc: false,
+        negative: false,
+        bipolar: false,
+        curve: SourceCurve::Linear,
+    };
+
+    pub fn general(controller: GeneralController) -> Self {
+        Self {
+            index: controller as u8,
+            ..Default::default()
+        }
+    }
+
+    pub fn cc(number: u8) -> Self {
+        Self {
+            index: number,
+            midi_cc: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_u16(&self) -> u16 {
+        (self.index as u16 & 0x7f)
+            | (self.midi_cc as u16) << 7
+            | (self.negative as u16) << 8
+            | (self.bipolar as u16) << 9
+            | (self.curve.to_u8() as u16) << 10
+    }
+
+    pub fn from_u16(value: u16) -> Self {
+        Self {
+            index: (value & 0x7f) as u8,
+            midi_cc: value & 0x80 != 0,
+            negative: value & 0x100 != 0,
+            bipolar: value & 0x200 != 0,
+            curve: SourceCurve::from_u8((value >> 10) as u8),
+        }
+    }
+
+    /// Whether the spec allows this controller as a source.
+    pub fn is_legal(&self) -> bool {
+        if self.midi_cc {
+            // Bank select, data entry, their LSBs, (N)RPN and channel mode messages are excluded.
+            !matches!(self.index, 0 | 6 | 32 | 38 | 98..=101 | 120..=127)
+        } else {
+            GeneralController::from_index(self.index).is_some()
+        }
+    }
+}
+
+impl Display for ModulatorSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.midi_cc, GeneralController::from_index(self.index)) {
+            (true, _) => write!(f, "cc{}", self.index),
+            (false, Some(ctrl)) => write!(f, "{}", ctrl.name()),
+            (false, None) => write!(f, "controller {}", self.index),
+        }
+    }
+}
+
+/// Modulator destination. Either a generator, or another modulator in the same zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModDestination {
+    Generator(Generator),
+    /// Index of the target modulator within the zone
+    Link(u16),
+}
+
+impl ModDestination {
+    pub fn to_u16(&self) -> u16 {
+        match self {
+            ModDestination::Generator(generator) => generator.to_u16(),
+            ModDestination::Link(index) => 0x8000 | index,
+        }
+    }
+
+    pub fn from_u16(value: u16) -> Self {
+        if value & 0x8000 != 0 {
+            return Self::Link(value & 0x7fff);
+        }
+        Self::Generator(Generator::from_u16(value))
+    }
+}
+
+/// SFTransform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u16)]
+pub enum ModTransform {
+    #[default]
+    Linear = 0,
+    AbsoluteValue = 2,
+    /// A transform the spec doesn't define. Synths ignore the modulator.
+    Unknown(u16),
+}
+
+impl ModTransform {
+    pub fn to_u16(self) -> u16 {
+        match self {
+            Self::Linear => 0,
+            Self::AbsoluteValue => 2,
+            Self::Unknown(value) => value,
+        }
+    }
+
+    pub fn from_u16(value: u16) -> Self {
+        match value {
+            0 => Self::Linear,
+            2 => Self::AbsoluteValue,
+            _ => Self::Unknown(value),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Linear => "linear",
+            Self::AbsoluteValue => "absolute",
+            Self::Unknown(_) => "unknown",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Linear, Self::AbsoluteValue]
+            .into_iter()
+            .find(|transform| transform.name() == name)
+    }
+}
+
+/// The ten modulators every synth applies to instrument zones unless overridden.
+///
+/// A zone overrides one by containing a modulator with the same sources, destination and
+/// transform. An override with amount 0 disables it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultModulator {
+    VelocityToAttenuation,
+    VelocityToFilterFc,
+    ChannelPressureToVibrato,
+    ModWheelToVibrato,
+    VolumeToAttenuation,
+    PanToPan,
+    ExpressionToAttenuation,
+    ReverbToReverbSend,
+    ChorusToChorusSend,
+    PitchWheelToPitch,
+}
+
+impl DefaultModulator {
+    pub const ALL: [DefaultModulator; 10] = [
+        Self::VelocityToAttenuation,
+        Self::VelocityToFilterFc,
+        Self::ChannelPressureToVibrato,
+        Self::ModWheelToVibrato,
+        Self::VolumeToAttenuation,
+        Self::PanToPan,
+        Self::ExpressionToAttenuation,
+        Self::ReverbToReverbSend,
+        Self::ChorusToChorusSend,
+        Self::PitchWheelToPitch,
+    ];
+
+    /// Snake case name, as used in project files. e.g. "velocity_to_filter_fc"
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::VelocityToAttenuation => "velocity_to_attenuation",
+            Self::VelocityToFilterFc => "velocity_to_filter_fc",
+            Self::ChannelPressureToVibrato => "channel_pressure_to_vibrato",
+            Self::ModWheelToVibrato => "mod_wheel_to_vibrato",
+            Self::VolumeToAttenuation => "volume_to_attenuation",
+            Self::PanToPan => "pan_to_pan",
+            Self::ExpressionToAttenuation => "expression_to_attenuation",
+            Self::ReverbToReverbSend => "reverb_to_reverb_send",
+            Self::ChorusToChorusSend => "chorus_to_chorus_send",
+            Self::PitchWheelToPitch => "pitch_wheel_to_pitch",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|modulator| modulator.name() == name)
+    }
+
+    /// The modulator as defined by the spec.
+    pub fn modulator(self) -> InstrumentMod {
+        // Source, destination, amount, amount source
+        let (src, dest, amount, amt_src) = match self {
+            Self::VelocityToAttenuation => (0x0502, Generator::InitialAttenuation, 960, 0),
+            Self::VelocityToFilterFc => (0x0102, Generator::InitialFilterFc, -2400, 0),
+            Self::ChannelPressureToVibrato => (0x000d, Generator::VibLfoToPitch, 50, 0),
+            Self::ModWheelToVibrato => (0x0081, Generator::VibLfoToPitch, 50, 0),
+            Self::VolumeToAttenuation => (0x0587, Generator::InitialAttenuation, 960, 0),
+            Self::PanToPan => (0x028a, Generator::Pan, 1000, 0),
+            Self::ExpressionToAttenuation => (0x058b, Generator::InitialAttenuation, 960, 0),
+            Self::ReverbToReverbSend => (0x00db, Generator::ReverbEffectsSend, 200, 0),
+            Self::ChorusToChorusSend => (0x00dd, Generator::ChorusEffectsSend, 200, 0),
+            // The destination is "initial pitch", which occupies the last unused slot.
+            Self::PitchWheelToPitch => (0x020e, Generator::Unused5, 12700, 0x0010),
+        };
+        InstrumentMod {
+            sf_mod_src_oper: ModulatorSource::from_u16(src),
+            sf_mod_dest_oper: ModDestination::Generator(dest),
+            mod_amount: amount,
+            sf_mod_amt_src_oper: ModulatorSource::from_u16(amt_src),
+            sf_mod_trans_oper: ModTransform::Linear,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_bits() {
+        // Spec default modulators
+        let velocity_to_attenuation = ModulatorSource {
+            index: GeneralController::NoteOnVelocity as u8,
+            midi_cc: false,
+            negative: true,
+            bipolar: false,
+            curve: SourceCurve::Concave,
+        };
+        assert_eq!(velocity_to_attenuation.to_u16(), 0x0502);
+        assert_eq!(ModulatorSource::cc(1).to_u16(), 0x0081);
+        assert_eq!(ModulatorSource::from_u16(0x0502), velocity_to_attenuation);
+        let unknown = ModulatorSource::from_u16(0x1002);
+        assert_eq!(unknown.curve, SourceCurve::Unknown(4));
+        assert_eq!(unknown.to_u16(), 0x1002);
+    }
+
+    #[test]
+    fn test_legal() {
+        assert!(ModulatorSource::cc(1).is_legal());
+        assert!(!ModulatorSource::cc(6).is_legal());
+        assert!(!ModulatorSource {
+            index: 5,
+            ..Default::default()
+        }
+        .is_legal());
+    }
+
+    #[test]
+    fn test_destination() {
+        let dest = ModDestination::Generator(Generator::Pan);
+        assert_eq!(ModDestination::from_u16(dest.to_u16()), dest);
+        assert_eq!(ModDestination::Link(3).to_u16(), 0x8003);
+        assert_eq!(ModDestination::from_u16(0x8003), ModDestination::Link(3));
+        assert_eq!(
+            ModDestination::from_u16(99),
+            ModDestination::Generator(Generator::Unknown(99))
+        );
+        assert_eq!(ModTransform::from_u16(1).to_u16(), 1);
+    }
+
+    #[test]
+    fn test_default_modulators() {
+        for default in DefaultModulator::ALL {
+            assert_eq!(DefaultModulator::from_name(default.name()), Some(default));
+        }
+        let pitch_wheel = DefaultModulator::PitchWheelToPitch.modulator();
+        assert_eq!(pitch_wheel.sf_mod_src_oper.to_u16(), 0x020e);
+        assert_eq!(pitch_wheel.sf_mod_dest_oper.to_u16(), 59);
+        assert_eq!(pitch_wheel.sf_mod_amt_src_oper.to_u16(), 0x0010);
+    }
+}
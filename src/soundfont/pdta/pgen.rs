@@ -1,6 +1,7 @@
-use riff::{ChunkContents, ChunkId};
+use riff::ChunkContents;
 
-use super::GenAmountType;
+use super::{list_chunk, records, u16_at, GenAmountType, Generator, GeneratorLevel};
+use crate::soundfont::{ParseError, SoundfontError};
 
 #[derive(Debug, Clone)]
 pub struct PresetGenList {
@@ -18,40 +19,60 @@ impl PresetGenList {
         Self { contents: vec![] }
     }
 
-    pub fn to_riff(&self) -> ChunkContents {
+    pub fn to_riff(&self) -> Result<ChunkContents, SoundfontError> {
         let mut contents = vec![];
         for gen in &self.contents {
             contents.append(&mut gen.to_bytes());
         }
 
-        assert_ne!(contents.len(), 0);
-        assert_eq!(contents.len() % 4, 0);
+        list_chunk(*b"pgen", contents)
+    }
 
-        ChunkContents::Data(ChunkId { value: *b"pgen" }, contents)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let contents = records(bytes, 4, *b"pgen")?
+            .map(PresetGen::from_bytes)
+            .collect();
+        Ok(Self { contents })
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct PresetGen {
-    pub sf_gen_oper: u16, // SFGenerator 16-bit enum value
+    pub sf_gen_oper: Generator,
     pub gen_amount: GenAmountType,
 }
 
 impl PresetGen {
+    /// Fails if the generator isn't allowed in presets, or the amount is of wrong type or out of range.
+    pub fn new(sf_gen_oper: Generator, gen_amount: GenAmountType) -> Result<Self, SoundfontError> {
+        sf_gen_oper.validate(&gen_amount, GeneratorLevel::Preset)?;
+        Ok(Self {
+            sf_gen_oper,
+            gen_amount,
+        })
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = vec![];
 
-        bytes.append(&mut self.sf_gen_oper.to_le_bytes().to_vec());
-        bytes.append(&mut self.gen_amount.to_bytes());
-
-        assert_eq!(bytes.len(), 4);
+        bytes.append(&mut self.sf_gen_oper.to_u16().to_le_bytes().to_vec());
+        bytes.extend(self.gen_amount.to_bytes());
 
         bytes
     }
 
+    /// `bytes` must be one 4 byte record. The amount isn't validated.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let sf_gen_oper = Generator::from_u16(u16_at(bytes, 0));
+        Self {
+            sf_gen_oper,
+            gen_amount: sf_gen_oper.amount_from_i16(u16_at(bytes, 2) as i16),
+        }
+    }
+
     pub fn terminal() -> Self {
         Self {
-            sf_gen_oper: 0,
+            sf_gen_oper: Generator::StartAddrsOffset,
             gen_amount: GenAmountType::Signed(0),
         }
     }
@@ -1,4 +1,7 @@
-use riff::{ChunkContents, ChunkId};
+use riff::ChunkContents;
+
+use super::{list_chunk, records, u16_at, u32_at};
+use crate::soundfont::{bytes_to_string, name_to_bytes, ParseError, SoundfontError};
 
 #[derive(Debug, Clone)]
 pub struct PresetHeaders {
@@ -16,16 +19,20 @@ impl PresetHeaders {
         Self { contents: vec![] }
     }
 
-    pub fn to_riff(&self) -> ChunkContents {
+    pub fn to_riff(&self) -> Result<ChunkContents, SoundfontError> {
         let mut contents = vec![];
         for header in &self.contents {
-            contents.append(&mut header.to_bytes());
+            contents.append(&mut header.to_bytes()?);
         }
 
-        assert_ne!(contents.len(), 0);
-        assert_eq!(contents.len() % 38, 0);
+        list_chunk(*b"phdr", contents)
+    }
 
-        ChunkContents::Data(ChunkId { value: *b"phdr" }, contents)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let contents = records(bytes, 38, *b"phdr")?
+            .map(PresetHeader::from_bytes)
+            .collect();
+        Ok(Self { contents })
     }
 }
 
@@ -33,6 +40,8 @@ impl PresetHeaders {
 pub struct PresetHeader {
     /// Must be unique, ASCII, and at most 256B long.
     pub name: String,
+    /// The name as read from the file, written back while `name` is unchanged
+    pub raw_name: Option<[u8; 20]>,
     /// MIDI Preset number. Bank/Preset combination must be unique.
     pub preset: u16,
     /// MIDI Bank number. Bank/Preset combination must be unique.
@@ -48,14 +57,11 @@ pub struct PresetHeader {
 }
 
 impl PresetHeader {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Fails if the name doesn't fit into 20 bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SoundfontError> {
         let mut bytes = vec![];
 
-        let mut name_bytes = self.name.as_bytes().to_vec();
-        assert!(name_bytes.len() <= 20);
-        name_bytes.resize(20, 0);
-
-        bytes.append(&mut name_bytes);
+        bytes.extend(name_to_bytes(&self.name, &self.raw_name)?);
         bytes.append(&mut self.preset.to_le_bytes().to_vec());
         bytes.append(&mut self.bank.to_le_bytes().to_vec());
         bytes.append(&mut self.pbag_idx.to_le_bytes().to_vec());
@@ -63,7 +69,20 @@ impl PresetHeader {
         bytes.append(&mut self.genre.to_le_bytes().to_vec());
         bytes.append(&mut self.morphology.to_le_bytes().to_vec());
 
-        assert_eq!(bytes.len(), 38);
-        bytes
+        Ok(bytes)
+    }
+
+    /// `bytes` must be one 38 byte record.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            name: bytes_to_string(&bytes[..20]),
+            raw_name: Some(bytes[..20].try_into().unwrap()),
+            preset: u16_at(bytes, 20),
+            bank: u16_at(bytes, 22),
+            pbag_idx: u16_at(bytes, 24),
+            library: u32_at(bytes, 26),
+            genre: u32_at(bytes, 30),
+            morphology: u32_at(bytes, 34),
+        }
     }
 }
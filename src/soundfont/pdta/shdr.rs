@@ -1,6 +1,7 @@
-use riff::{ChunkContents, ChunkId};
+use riff::ChunkContents;
 
-use crate::soundfont::SoundfontError;
+use super::{list_chunk, records, u16_at, u32_at};
+use crate::soundfont::{bytes_to_string, name_to_bytes, ParseError, SoundfontError};
 
 #[derive(Debug, Clone)]
 pub struct SampleList {
@@ -18,23 +19,42 @@ impl SampleList {
         Self { contents: vec![] }
     }
 
-    pub fn to_riff(&self) -> ChunkContents {
+    pub fn to_riff(&self) -> Result<ChunkContents, SoundfontError> {
         let mut contents = vec![];
         for header in &self.contents {
-            let _ = header.validate();
-            contents.append(&mut header.to_bytes());
+            contents.append(&mut header.to_bytes()?);
         }
 
-        assert_ne!(contents.len(), 0);
-        assert_eq!(contents.len() % 46, 0);
+        list_chunk(*b"shdr", contents)
+    }
 
-        ChunkContents::Data(ChunkId { value: *b"shdr" }, contents)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let contents = records(bytes, 46, *b"shdr")?
+            .map(SampleHeader::from_bytes)
+            .collect();
+        Ok(Self { contents })
     }
 }
 
+/// Sample type, and how it links to another sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum SFSampleLink {
+    Mono = 1,
+    Right = 2,
+    Left = 4,
+    Linked = 8,
+    RomMono = 0x8001,
+    RomRight = 0x8002,
+    RomLeft = 0x8004,
+    RomLinked = 0x8008,
+}
+
 #[derive(Debug, Clone)]
 pub struct SampleHeader {
     pub name: String,
+    /// The name as read from the file, written back while `name` is unchanged
+    pub raw_name: Option<[u8; 20]>,
     pub start: u32,
     pub end: u32,
     pub startloop: u32,
@@ -42,19 +62,17 @@ pub struct SampleHeader {
     pub sample_rate: u32,
     pub original_pitch: u8,
     pub pitch_correction: i8,
+    /// Index of the other sample of a stereo pair
     pub sample_link: u16,
     pub sample_type: u16, //SFSampleLink enum
 }
 
 impl SampleHeader {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Fails if the name doesn't fit into 20 bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SoundfontError> {
         let mut bytes = vec![];
 
-        let mut name_bytes = self.name.as_bytes().to_vec();
-        assert!(name_bytes.len() <= 20);
-        name_bytes.resize(20, 0);
-
-        bytes.append(&mut name_bytes);
+        bytes.extend(name_to_bytes(&self.name, &self.raw_name)?);
         bytes.append(&mut self.start.to_le_bytes().to_vec());
         bytes.append(&mut self.end.to_le_bytes().to_vec());
         bytes.append(&mut self.startloop.to_le_bytes().to_vec());
@@ -65,9 +83,24 @@ impl SampleHeader {
         bytes.append(&mut self.sample_link.to_le_bytes().to_vec());
         bytes.append(&mut self.sample_type.to_le_bytes().to_vec());
 
-        assert_eq!(bytes.len(), 46);
+        Ok(bytes)
+    }
 
-        bytes
+    /// `bytes` must be one 46 byte record. The sample isn't validated.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            name: bytes_to_string(&bytes[..20]),
+            raw_name: Some(bytes[..20].try_into().unwrap()),
+            start: u32_at(bytes, 20),
+            end: u32_at(bytes, 24),
+            startloop: u32_at(bytes, 28),
+            endloop: u32_at(bytes, 32),
+            sample_rate: u32_at(bytes, 36),
+            original_pitch: bytes[40],
+            pitch_correction: bytes[41] as i8,
+            sample_link: u16_at(bytes, 42),
+            sample_type: u16_at(bytes, 44),
+        }
     }
 
     pub fn validate(&self) -> Result<(), SoundfontError> {
@@ -89,22 +122,22 @@ impl SampleHeader {
         }
 
         // Sample must be at least 48 data points long.
-        if self.end - self.start < 48 {
+        if self.end.saturating_sub(self.start) < 48 {
             return Err(SoundfontError::SampleTooShort);
         }
 
         // The loop must be at least 32 data points long.
-        if self.endloop - self.startloop < 32 {
+        if self.endloop.saturating_sub(self.startloop) < 32 {
             return Err(SoundfontError::SampleLoopTooShort);
         }
 
         // There must be at least 8 data points before startloop
-        if self.startloop - self.start < 8 {
+        if self.startloop.saturating_sub(self.start) < 8 {
             return Err(SoundfontError::SampleLoopNotEnoughLead);
         }
 
         // There must be at least 8 data points after endloop
-        if self.end - self.endloop < 8 {
+        if self.end.saturating_sub(self.endloop) < 8 {
             return Err(SoundfontError::SampleLoopNotEnoughTail);
         }
 
@@ -114,6 +147,7 @@ impl SampleHeader {
     pub fn terminal() -> Self {
         Self {
             name: "EOS".into(),
+            raw_name: None,
             start: 0,
             end: 0,
             startloop: 0,
@@ -3,12 +3,16 @@
 
 use riff::{ChunkContents, ChunkId};
 
+use super::{subchunks, ParseError, ParseErrorKind, RawChunk, Subchunk};
+
 #[derive(Debug, Clone, Default)]
 pub struct SdtaList {
     /// 16-bit
     pub smpl: Vec<i16>,
     /// 24-bit extension
     pub sm24: Option<Vec<u8>>,
+    /// Unknown subchunks, kept as-is
+    pub extra: Vec<RawChunk>,
 }
 
 impl SdtaList {
@@ -24,13 +28,51 @@ impl SdtaList {
                 sm24.clone(),
             ));
         }
+        contents.extend(self.extra.iter().map(RawChunk::to_riff));
 
         ChunkContents::Children(riff::LIST_ID, ChunkId { value: *b"sdta" }, contents)
     }
+
+    /// Parse the subchunks of an sdta-list. A missing smpl chunk means there are no samples.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ParseError> {
+        let mut sdta = Self::default();
+        for Subchunk {
+            id,
+            offset,
+            contents,
+        } in subchunks(data)?
+        {
+            match &id.value {
+                b"smpl" => {
+                    if !contents.len().is_multiple_of(2) {
+                        let kind = ParseErrorKind::ChunkSize {
+                            len: contents.len(),
+                            record_size: 2,
+                        };
+                        return Err(ParseError::new(kind, id.value).offset_by(offset as u64));
+                    }
+                    sdta.smpl = contents
+                        .chunks_exact(2)
+                        .map(|point| i16::from_le_bytes([point[0], point[1]]))
+                        .collect();
+                }
+                b"sm24" => sdta.sm24 = Some(contents.to_vec()),
+                _ => sdta.extra.push(RawChunk {
+                    id,
+                    data: contents.to_vec(),
+                }),
+            }
+        }
+        Ok(sdta)
+    }
 }
 
 impl SdtaList {
     pub fn new(smpl: Vec<i16>, sm24: Option<Vec<u8>>) -> Self {
-        Self { smpl, sm24 }
+        Self {
+            smpl,
+            sm24,
+            extra: vec![],
+        }
     }
 }